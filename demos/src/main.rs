@@ -15,5 +15,5 @@ mod wasm {
 fn run() {
     let params = platform::get_state_params();
     let state = app::State::new(params);
-    platform::run(state);
+    platform::run(state, env!("CARGO_PKG_NAME"));
 }
\ No newline at end of file
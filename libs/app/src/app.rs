@@ -37,7 +37,7 @@ impl State {
 }
 
 impl platform_types::State for State {
-    fn frame(&mut self) -> (&[platform_types::Command], &[SFX]) {
+    fn frame(&mut self) -> (platform_types::Graphics<'_>, &[SFX]) {
         self.commands.clear();
         self.speaker.clear();
         update_and_render(
@@ -49,7 +49,13 @@ impl platform_types::State for State {
 
         self.input.previous_gamepad = self.input.gamepad;
 
-        (self.commands.slice(), self.speaker.slice())
+        (self.commands.graphics(), self.speaker.slice())
+    }
+
+    fn atlas_sources(&self) -> &[platform_types::atlas::Source] {
+        // Every demo's atlases are loaded at startup, so this would need to
+        // offset the ids if another demo added some.
+        splat::ATLAS_SOURCES
     }
 
     fn press(&mut self, button: Button) {
@@ -0,0 +1,83 @@
+//! Compares ways of drawing the fireflies scene, from the particles' positions
+//! to pixels in a frame buffer.
+//!
+//! Run with `cargo bench -p fireflies`.
+use fireflies::{particle_positions, State};
+use gfx::Commands;
+use platform_types::{colours, command, Input, Speaker};
+use render::FrameBuffer;
+
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+/// Enough for the particles to go from scattered to settled, so each frame
+/// changes, and the dirty cells do not let us skip any work.
+const FRAME_COUNT: usize = 300;
+
+type Draw = fn(&mut Commands, &State);
+
+fn draw_point_per_particle(commands: &mut Commands, state: &State) {
+    for at in particle_positions(state) {
+        commands.draw_point(at, colours::RED);
+    }
+}
+
+fn draw_texture(commands: &mut Commands, state: &State) {
+    let texture = commands.texture_mut();
+    texture.fill(0);
+    for at in particle_positions(state) {
+        texture.set(at.x.get().into(), at.y.get().into(), colours::RED);
+    }
+    commands.draw_texture(<_>::default());
+}
+
+fn draw_points(commands: &mut Commands, state: &State) {
+    commands.draw_points(
+        particle_positions(state).map(|at| (at.into(), colours::RED))
+    );
+}
+
+/// Returns the total time spent building and rendering the commands, not
+/// counting updating the particles.
+fn time(draw: Draw) -> Duration {
+    let mut state = State::new([0xF1; 16]);
+    let mut commands = Commands::default();
+    let mut speaker = Speaker::default();
+    let mut frame_buffer = FrameBuffer::from_size((command::WIDTH, command::HEIGHT));
+
+    let mut total = Duration::ZERO;
+    for _ in 0..FRAME_COUNT {
+        // This draws the scene the usual way too, but we only time the drawing
+        // below.
+        fireflies::update_and_render(&mut commands, &mut state, Input::default(), &mut speaker);
+        commands.clear();
+
+        let start = Instant::now();
+        draw(&mut commands, &state);
+        black_box(render::render_graphics(&mut frame_buffer, commands.graphics()));
+        total += start.elapsed();
+    }
+
+    total
+}
+
+fn main() {
+    let cases: [(&str, Draw); 3] = [
+        ("draw_point per particle", draw_point_per_particle),
+        ("draw_texture", draw_texture),
+        ("draw_points", draw_points),
+    ];
+
+    println!("{FRAME_COUNT} frames of fireflies at {}x{}:", command::WIDTH, command::HEIGHT);
+    for (name, draw) in cases {
+        // Warm up caches, and the `LazyLock` in `fireflies`.
+        time(draw);
+
+        let total = time(draw);
+
+        println!(
+            "{name:>24}: {:>8.1?} per frame",
+            total / FRAME_COUNT as u32,
+        );
+    }
+}
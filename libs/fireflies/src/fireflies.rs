@@ -133,23 +133,27 @@ fn ease_in_out_back(frac: f32) -> f32 {
     }
 }
 
-#[inline]
-fn render(commands: &mut Commands, state: &State) {
-    for &Particle { start, frac, target } in &state.particles {
+/// Returns where each particle currently is.
+pub fn particle_positions(state: &State) -> impl Iterator<Item = unscaled::XY> + '_ {
+    state.particles.iter().map(|&Particle { start, frac, target }| {
         let t = ease_in_out_back(frac);
 
-        let at = unscaled::XY {
+        unscaled::XY {
             x: <_>::try_from(
                 lerp(start.x.into(), t, target.x.into())
             ).unwrap_or(target.x),
             y: <_>::try_from(
                 lerp(start.y.into(), t, target.y.into())
             ).unwrap_or(target.y),
-        };
+        }
+    })
+}
 
-        //commands.draw_point(target, colours::BLUE);
-        commands.draw_point(at, colours::RED);
-    }
+#[inline]
+fn render(commands: &mut Commands, state: &State) {
+    commands.draw_points(
+        particle_positions(state).map(|at| (at.into(), colours::RED))
+    );
 }
 
 #[inline]
@@ -161,4 +165,48 @@ pub fn update_and_render(
 ) {
     update(state, input, speaker);
     render(commands, state);
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod renders_the_same_as_the_golden_image {
+    use super::*;
+
+    const SEED: Seed = [0xF1; 16];
+
+    fn assert_frame_matches(frame_count: usize, name: &str) {
+        let mut state = State::new(SEED);
+        let mut commands = Commands::default();
+        let mut speaker = Speaker::default();
+
+        for _ in 0..frame_count {
+            commands.clear();
+            update_and_render(&mut commands, &mut state, Input::default(), &mut speaker);
+        }
+
+        let frame_buffer = render::render_graphics_headless(
+            commands.graphics(),
+            (command::WIDTH, command::HEIGHT),
+        );
+
+        snapshot::assert_matches_golden(
+            std::path::Path::new(env!("CARGO_MANIFEST_DIR"))
+                .join("golden")
+                .join(name),
+            &frame_buffer,
+        );
+    }
+
+    #[test]
+    fn on_the_first_frame() {
+        assert_frame_matches(1, "first_frame.png");
+    }
+
+    #[test]
+    fn partway_through_the_animation() {
+        assert_frame_matches(128, "partway.png");
+    }
+
+    #[test]
+    fn after_the_particles_have_settled() {
+        assert_frame_matches(300, "settled.png");
+    }
+}
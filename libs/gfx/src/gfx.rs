@@ -1,19 +1,378 @@
 use models::{Card, Rank, Suit, get_rank, get_suit, suits};
 
-use platform_types::{ARGB, Command, PALETTE, sprite, unscaled, command::{self, Rect}, PaletteIndex, FONT_BASE_Y, FONT_WIDTH};
+use platform_types::{ARGB, atlas, BlendMode, Command, Modulation, Orientation, PALETTE, sprite, unscaled, command::{self, Rect}, PaletteIndex, Point, Texture, Graphics, world, FONT_BASE_Y, FONT_WIDTH};
+
+/// Commands on higher layers are drawn on top of commands on lower layers.
+/// Within a layer, commands are drawn in the order they were added.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Layer(pub i8);
+
+impl Layer {
+    pub const BACKGROUND: Self = Self(-64);
+    pub const ACTORS: Self = Self(0);
+    pub const UI: Self = Self(64);
+}
+
+/// The colours that the shape drawing methods accept: either an `ARGB` colour,
+/// or an index into `PALETTE`.
+pub trait Colour: Copy {
+    fn to_argb(self) -> ARGB;
+}
+
+impl Colour for ARGB {
+    fn to_argb(self) -> ARGB {
+        self
+    }
+}
+
+impl Colour for PaletteIndex {
+    fn to_argb(self) -> ARGB {
+        PALETTE[self as usize]
+    }
+}
 
 #[derive(Default)]
 pub struct Commands {
     commands: Vec<Command>,
+    // The layer of each command in `commands`.
+    layers: Vec<Layer>,
+    // Whether a command has been added on a lower layer than the one before
+    // it, since `commands` was last sorted by layer.
+    needs_sort: bool,
+    layer: Layer,
+    // `None` means nothing is visible.
+    clip_rects: Vec<Option<command::Rect>>,
+    // Each element already has the previous ones added to it.
+    cameras: Vec<world::XY>,
+    blend_mode: BlendMode,
+    modulation: Modulation,
+    atlas: atlas::Id,
+    texture: Texture,
+    // Bumped each time `texture_mut` is called.
+    texture_version: u32,
+    points: Vec<Point>,
+    // The fonts added with `add_font`, after the built-in one.
+    fonts: Vec<text::Font>,
+    font: text::FontId,
 }
 
 impl Commands {
-    pub fn slice(&self) -> &[Command] {
+    /// Returns the commands, sorted by layer.
+    pub fn slice(&mut self) -> &[Command] {
+        self.sort_by_layer();
+
         &self.commands
     }
 
+    /// Returns the commands, sorted by layer, along with the points and
+    /// texture they draw from.
+    pub fn graphics(&mut self) -> Graphics<'_> {
+        self.sort_by_layer();
+
+        Graphics {
+            commands: &self.commands,
+            points: &self.points,
+            texture: &self.texture,
+            texture_version: self.texture_version,
+        }
+    }
+
+    /// Removes all the commands, empties the clip rect and camera stacks, and
+    /// resets the layer, blend mode, tint, alpha, atlas and font to the
+    /// defaults. The texture and the added fonts are left as they are.
     pub fn clear(&mut self) {
         self.commands.clear();
+        self.points.clear();
+        self.layers.clear();
+        self.needs_sort = false;
+        self.layer = <_>::default();
+        self.clip_rects.clear();
+        self.cameras.clear();
+        self.blend_mode = <_>::default();
+        self.modulation = <_>::default();
+        self.atlas = <_>::default();
+        self.font = <_>::default();
+    }
+
+    /// Sets the layer used by the commands added after this call, and returns
+    /// the previous one, so it can be restored afterwards.
+    pub fn set_layer(&mut self, layer: Layer) -> Layer {
+        core::mem::replace(&mut self.layer, layer)
+    }
+
+    /// Restricts the commands added after this call to the intersection of the
+    /// given rect and the current clip rect, if any, until the matching call
+    /// to `pop_clip_rect`. Clip rects are in screen coordinates, so they are
+    /// not affected by the camera.
+    pub fn push_clip_rect(&mut self, rect: unscaled::Rect) {
+        let on_screen = if rect.w.get() == 0
+        || rect.h.get() == 0
+        || rect.x.get() >= command::WIDTH
+        || rect.y.get() >= command::HEIGHT {
+            None
+        } else {
+            Some(Rect::from_unscaled(rect))
+        };
+
+        let clip_rect = match self.clip_rects.last() {
+            None => on_screen,
+            Some(&current) => current.zip(on_screen)
+                .and_then(|(a, b)| intersect(a, b)),
+        };
+
+        self.clip_rects.push(clip_rect);
+    }
+
+    /// Undoes the most recent `push_clip_rect` call. Does nothing if the stack
+    /// is empty.
+    pub fn pop_clip_rect(&mut self) {
+        self.clip_rects.pop();
+    }
+
+    /// Moves the camera by the given offset, relative to where it currently is,
+    /// until the matching call to `pop_camera`. Everything drawn after this
+    /// call is drawn at its position minus the camera's position. The camera's
+    /// position saturates at the ends of the range of world coordinates.
+    pub fn push_camera(&mut self, offset: world::XY) {
+        let camera = self.camera();
+        self.cameras.push(world::XY {
+            x: world::X(camera.x.0.saturating_add(offset.x.0)),
+            y: world::Y(camera.y.0.saturating_add(offset.y.0)),
+        });
+    }
+
+    /// Undoes the most recent `push_camera` call. Does nothing if the stack is
+    /// empty.
+    pub fn pop_camera(&mut self) {
+        self.cameras.pop();
+    }
+
+    fn camera(&self) -> world::XY {
+        self.cameras.last().copied().unwrap_or_default()
+    }
+
+    /// Moves the rect by the camera, then trims it to the screen and the clip
+    /// rect, if any, adjusting `sprite_xy` to match. Then adds the command to
+    /// the current layer.
+    fn push_sprite(
+        &mut self,
+        sprite_xy: sprite::XY,
+        rect: world::Rect,
+        orientation: Orientation,
+        modulation: Modulation,
+        atlas: atlas::Id,
+    ) {
+        self.push_tiled_sprite(
+            sprite_xy,
+            rect,
+            orientation,
+            modulation,
+            atlas,
+            <_>::default(),
+        );
+    }
+
+    /// Like `push_sprite`, but if the tile is not empty, trimming the rect
+    /// adjusts the tile offsets instead of `sprite_xy`.
+    fn push_tiled_sprite(
+        &mut self,
+        mut sprite_xy: sprite::XY,
+        rect: world::Rect,
+        orientation: Orientation,
+        modulation: Modulation,
+        atlas: atlas::Id,
+        mut tile: command::Tile,
+    ) {
+        let visible = match self.clip_rects.last() {
+            None => SCREEN_RECT,
+            Some(&Some(clip_rect)) => clip_rect,
+            Some(&None) => return,
+        };
+
+        let camera = self.camera();
+
+        // Any world coordinate minus any camera position fits in an `i64`.
+        let x = i64::from(rect.x.0) - i64::from(camera.x.0);
+        let y = i64::from(rect.y.0) - i64::from(camera.y.0);
+        let x_end = x + i64::from(rect.w.get());
+        let y_end = y + i64::from(rect.h.get());
+
+        let visible_x = core::cmp::max(x, visible.x_min.get().get().into());
+        let visible_y = core::cmp::max(y, visible.y_min.get().get().into());
+        let visible_x_end = core::cmp::min(
+            x_end,
+            i64::from(visible.x_max.get().get()) + 1
+        );
+        let visible_y_end = core::cmp::min(
+            y_end,
+            i64::from(visible.y_max.get().get()) + 1
+        );
+
+        if visible_x >= visible_x_end || visible_y >= visible_y_end {
+            return
+        }
+
+        // These are all at most the rect's width or height, so they fit.
+        if !tile.is_empty() {
+            tile.x_offset = (
+                (i64::from(tile.x_offset) + visible_x - x)
+                % i64::from(tile.w)
+            ) as sprite::Inner;
+            tile.y_offset = (
+                (i64::from(tile.y_offset) + visible_y - y)
+                % i64::from(tile.h)
+            ) as sprite::Inner;
+        } else {
+            shift_for_cut(
+                &mut sprite_xy,
+                orientation,
+                (
+                    (visible_x - x) as sprite::Inner,
+                    (x_end - visible_x_end) as sprite::Inner,
+                ),
+                (
+                    (visible_y - y) as sprite::Inner,
+                    (y_end - visible_y_end) as sprite::Inner,
+                ),
+            );
+        }
+
+        let command = Command {
+            sprite_xy,
+            // These are all on screen, so they fit.
+            rect: Rect {
+                x_min: command::X::clipped_inner(visible_x as unscaled::Inner),
+                y_min: command::Y::clipped_inner(visible_y as unscaled::Inner),
+                x_max: command::X::clipped_inner((visible_x_end - 1) as unscaled::Inner),
+                y_max: command::Y::clipped_inner((visible_y_end - 1) as unscaled::Inner),
+            },
+            modulation,
+            orientation,
+            blend_mode: self.blend_mode,
+            atlas,
+            points: <_>::default(),
+            tile,
+        };
+
+        self.push_command(command);
+    }
+
+    /// Adds the command to the current layer.
+    fn push_command(&mut self, command: Command) {
+        let layer = self.layer;
+
+        // Adding commands in layer order is expected to be the common case,
+        // so we only sort if that did not happen.
+        if self.layers.last().is_some_and(|&last| last > layer) {
+            self.needs_sort = true;
+        }
+
+        self.commands.push(command);
+        self.layers.push(layer);
+    }
+
+    fn sort_by_layer(&mut self) {
+        if !self.needs_sort {
+            return
+        }
+        self.needs_sort = false;
+
+        let mut pairs: Vec<(Layer, Command)> = self.layers.drain(..)
+            .zip(self.commands.drain(..))
+            .collect();
+        // This is a stable sort, so commands on the same layer stay in the
+        // order they were added.
+        pairs.sort_by_key(|&(layer, _)| layer);
+
+        (self.layers, self.commands) = pairs.into_iter().unzip();
+    }
+
+    /// Sets the blend mode used by the commands added after this call, and
+    /// returns the previous one, so it can be restored afterwards.
+    pub fn set_blend_mode(&mut self, blend_mode: BlendMode) -> BlendMode {
+        core::mem::replace(&mut self.blend_mode, blend_mode)
+    }
+
+    /// Sets the tint used by the commands added after this call, and returns
+    /// the previous one. See `Modulation::tint`.
+    pub fn set_tint(&mut self, tint: ARGB) -> ARGB {
+        core::mem::replace(&mut self.modulation.tint, tint)
+    }
+
+    /// Sets the alpha used by the commands added after this call, and returns
+    /// the previous one. See `Modulation::alpha`.
+    pub fn set_alpha(&mut self, alpha: u8) -> u8 {
+        core::mem::replace(&mut self.modulation.alpha, alpha)
+    }
+
+    /// Sets the atlas that `sspr` and friends draw from after this call, and
+    /// returns the previous one. Text, cards and points always come from the
+    /// built-in atlas.
+    pub fn set_atlas(&mut self, atlas: atlas::Id) -> atlas::Id {
+        core::mem::replace(&mut self.atlas, atlas)
+    }
+
+    /// Makes the font available to `set_font`, and returns its id. Returns
+    /// `None` if the maximum number of fonts is already added.
+    pub fn add_font(&mut self, font: text::Font) -> Option<text::FontId> {
+        let id = text::FontId(u8::try_from(self.fonts.len() + 1).ok()?);
+
+        self.fonts.push(font);
+
+        Some(id)
+    }
+
+    /// Returns the font with the given id, or the built-in font if there is
+    /// no such font.
+    pub fn font(&self, id: text::FontId) -> &text::Font {
+        id.0.checked_sub(1)
+            .and_then(|index| self.fonts.get(usize::from(index)))
+            .unwrap_or(&text::BUILT_IN_FONT)
+    }
+
+    /// Sets the font that `print_str` and friends use after this call, and
+    /// returns the previous one. `print_char` and friends always use the
+    /// built-in font.
+    pub fn set_font(&mut self, font: text::FontId) -> text::FontId {
+        core::mem::replace(&mut self.font, font)
+    }
+
+    /// Returns the size that `print_str_laid_out` would take up, with the
+    /// current font.
+    pub fn measure_str(&self, text: &str, layout: text::Layout) -> unscaled::WH {
+        text::measure(text, layout, self.font(self.font))
+    }
+
+    /// The pixels that `draw_texture` draws, as of the end of the frame.
+    pub fn texture(&self) -> &Texture {
+        &self.texture
+    }
+
+    /// The renderer only compares and copies the texture's pixels on frames
+    /// where this was called, so only call it when writing to the texture.
+    pub fn texture_mut(&mut self) -> &mut Texture {
+        self.texture_version = self.texture_version.wrapping_add(1);
+
+        &mut self.texture
+    }
+
+    /// Draws the whole texture, with its top left corner at the given point.
+    /// Use `set_atlas` with `atlas::Id::TEXTURE` to draw parts of it instead.
+    pub fn draw_texture(&mut self, xy: world::XY) {
+        let to_inner = |n: usize| n.try_into().unwrap_or(unscaled::Inner::MAX);
+
+        self.push_sprite(
+            <_>::default(),
+            world::Rect {
+                x: xy.x,
+                y: xy.y,
+                w: unscaled::W(to_inner(self.texture.width())),
+                h: unscaled::H(to_inner(self.texture.height())),
+            },
+            <_>::default(),
+            self.modulation,
+            atlas::Id::TEXTURE,
+        );
     }
 
     pub fn sspr(
@@ -21,13 +380,247 @@ impl Commands {
         sprite_xy: sprite::XY,
         rect: command::Rect,
     ) {
-        self.commands.push(
-            Command {
-                sprite_xy,
-                rect,
-                colour_override: 0,
+        self.push_sprite(
+            sprite_xy,
+            rect.unscaled().into(),
+            <_>::default(),
+            self.modulation,
+            self.atlas,
+        );
+    }
+
+    /// Like `sspr`, but with the sprite mirrored and/or rotated. Note that the
+    /// rect is the size of the sprite *after* being rotated. This takes an
+    /// unclipped rect, so that we can still pick the right part of the sprite
+    /// when it is flipped and partially off screen.
+    pub fn sspr_oriented(
+        &mut self,
+        sprite_xy: sprite::XY,
+        rect: unscaled::Rect,
+        orientation: Orientation,
+    ) {
+        self.sspr_world(sprite_xy, rect.into(), orientation);
+    }
+
+    /// Like `sspr_oriented`, but the rect can be partly or entirely off any
+    /// edge of the screen.
+    pub fn sspr_world(
+        &mut self,
+        sprite_xy: sprite::XY,
+        rect: world::Rect,
+        orientation: Orientation,
+    ) {
+        self.push_sprite(sprite_xy, rect, orientation, self.modulation, self.atlas);
+    }
+
+    /// Draws the tiles of the tilemap that are visible, from the current atlas,
+    /// with the map's top left corner at the world origin, moved by the
+    /// scroll offset. `tick` picks the frame of any animated tiles.
+    pub fn draw_tilemap(&mut self, tilemap: &tilemap::Tilemap, tick: u32) {
+        let visible = match self.clip_rects.last() {
+            None => SCREEN_RECT,
+            Some(&Some(clip_rect)) => clip_rect,
+            Some(&None) => return,
+        };
+
+        let tile_w = i64::from(tilemap.tile_size.w.get());
+        let tile_h = i64::from(tilemap.tile_size.h.get());
+        if tile_w == 0 || tile_h == 0 {
+            return
+        }
+
+        let camera = self.camera();
+        let scroll_x = i64::from(tilemap.scroll.x.0);
+        let scroll_y = i64::from(tilemap.scroll.y.0);
+        // The visible part of the screen, relative to the map's top left corner.
+        let x_min = i64::from(visible.x_min.get().get()) + i64::from(camera.x.0) + scroll_x;
+        let y_min = i64::from(visible.y_min.get().get()) + i64::from(camera.y.0) + scroll_y;
+        let x_max = i64::from(visible.x_max.get().get()) + i64::from(camera.x.0) + scroll_x;
+        let y_max = i64::from(visible.y_max.get().get()) + i64::from(camera.y.0) + scroll_y;
+
+        let to_index = |i: i64, len: usize| usize::try_from(i.max(0)).unwrap_or(len).min(len);
+        let columns = to_index(x_min.div_euclid(tile_w), tilemap.columns())
+            ..to_index(x_max.div_euclid(tile_w) + 1, tilemap.columns());
+        let rows = to_index(y_min.div_euclid(tile_h), tilemap.rows())
+            ..to_index(y_max.div_euclid(tile_h) + 1, tilemap.rows());
+
+        for row in rows {
+            for column in columns.clone() {
+                let Some(tile) = tilemap.get(column, row) else { continue };
+
+                // Visible tiles can still be past the ends of the range of
+                // world coordinates, if the camera is near them. We skip those.
+                let (Ok(x), Ok(y)) = (
+                    world::Inner::try_from(column as i64 * tile_w - scroll_x),
+                    world::Inner::try_from(row as i64 * tile_h - scroll_y),
+                ) else { continue };
+
+                self.push_sprite(
+                    tilemap.sprite_xy_at(tile, tick),
+                    world::Rect {
+                        x: world::X(x),
+                        y: world::Y(y),
+                        w: tilemap.tile_size.w,
+                        h: tilemap.tile_size.h,
+                    },
+                    <_>::default(),
+                    self.modulation,
+                    self.atlas,
+                );
             }
+        }
+    }
+
+    /// Draws the player's current frame with its top left corner at `xy`, from
+    /// the current atlas. Draws nothing if the animation has no frames.
+    pub fn draw_animation(
+        &mut self,
+        player: &animation::Player,
+        xy: world::XY,
+        orientation: Orientation,
+    ) {
+        let Some(sprite_xy) = player.sprite_xy() else { return };
+        let size = player.animation.size;
+
+        self.sspr_world(
+            sprite_xy,
+            world::Rect {
+                x: xy.x,
+                y: xy.y,
+                w: size.w,
+                h: size.h,
+            },
+            orientation,
+        );
+    }
+
+    /// Like `sspr_world`, but repeats the `tile` sized region at `sprite_xy`
+    /// across the rect, instead of drawing the texels past it. Like the rect,
+    /// `tile` is the size as drawn, after the orientation is applied. The
+    /// pattern starts at the top left corner of the rect. A zero sized tile
+    /// draws nothing.
+    pub fn sspr_tiled(
+        &mut self,
+        sprite_xy: sprite::XY,
+        tile: unscaled::WH,
+        rect: world::Rect,
+        orientation: Orientation,
+    ) {
+        if tile.w.get() == 0 || tile.h.get() == 0 {
+            return
+        }
+
+        self.push_tiled_sprite(
+            sprite_xy,
+            rect,
+            orientation,
+            self.modulation,
+            self.atlas,
+            command::Tile {
+                w: tile.w.get(),
+                h: tile.h.get(),
+                x_offset: 0,
+                y_offset: 0,
+            },
+        );
+    }
+
+    /// Draws the nine slice sprite so that it covers `rect`, from the current
+    /// atlas. The corners are drawn as they are, and the edges and centre are
+    /// tiled or stretched to fill the rest. If `rect` is too small for the
+    /// corners, they are cut down in proportion to their insets, keeping their
+    /// outer parts.
+    pub fn draw_nine_slice(
+        &mut self,
+        nine_slice: &nine_slice::NineSlice,
+        rect: unscaled::Rect,
+    ) {
+        use nine_slice::{parts, segments};
+
+        let insets = nine_slice.insets;
+        let x_parts = parts(
+            nine_slice.size.w.get(),
+            (insets.left.get(), insets.right.get()),
+            rect.w.get(),
+        );
+        let y_parts = parts(
+            nine_slice.size.h.get(),
+            (insets.top.get(), insets.bottom.get()),
+            rect.h.get(),
         );
+
+        for (row, y_part) in y_parts.into_iter().enumerate() {
+            for (column, x_part) in x_parts.into_iter().enumerate() {
+                let fill = if row == 1 && column == 1 {
+                    nine_slice.centre
+                } else {
+                    nine_slice.edges
+                };
+
+                if fill == nine_slice::Fill::Tile {
+                    if x_part.src_len == 0 || y_part.src_len == 0 {
+                        continue
+                    }
+
+                    self.push_tiled_sprite(
+                        sprite::XY {
+                            x: nine_slice.xy.x + unscaled::W(x_part.src),
+                            y: nine_slice.xy.y + unscaled::H(y_part.src),
+                        },
+                        unscaled::Rect {
+                            x: rect.x + unscaled::W(x_part.dst),
+                            y: rect.y + unscaled::H(y_part.dst),
+                            w: unscaled::W(x_part.dst_len),
+                            h: unscaled::H(y_part.dst_len),
+                        }.into(),
+                        <_>::default(),
+                        self.modulation,
+                        self.atlas,
+                        command::Tile {
+                            w: x_part.src_len,
+                            h: y_part.src_len,
+                            x_offset: 0,
+                            y_offset: 0,
+                        },
+                    );
+                    continue
+                }
+
+                for y_segment in segments(y_part) {
+                    for x_segment in segments(x_part) {
+                        // Segments that repeat a texel are tiled, which covers
+                        // the rest, as those tiles are as big as the segments.
+                        let tile = if x_segment.is_repeated() || y_segment.is_repeated() {
+                            command::Tile {
+                                w: x_segment.src_len,
+                                h: y_segment.src_len,
+                                x_offset: 0,
+                                y_offset: 0,
+                            }
+                        } else {
+                            <_>::default()
+                        };
+
+                        self.push_tiled_sprite(
+                            sprite::XY {
+                                x: nine_slice.xy.x + unscaled::W(x_segment.src),
+                                y: nine_slice.xy.y + unscaled::H(y_segment.src),
+                            },
+                            unscaled::Rect {
+                                x: rect.x + unscaled::W(x_segment.dst),
+                                y: rect.y + unscaled::H(y_segment.dst),
+                                w: unscaled::W(x_segment.len),
+                                h: unscaled::H(y_segment.len),
+                            }.into(),
+                            <_>::default(),
+                            self.modulation,
+                            self.atlas,
+                            tile,
+                        );
+                    }
+                }
+            }
+        }
     }
 
     pub fn print_char(
@@ -37,36 +630,82 @@ impl Commands {
         y: unscaled::Y,
         colour: PaletteIndex
     ) {
-        fn get_char_xy(sprite_number: u8) -> sprite::XY {
-            type Inner = sprite::Inner;
-            let sprite_number = Inner::from(sprite_number);
-            const CH_SIZE: Inner = CHAR_SIZE as Inner;
-            const SPRITES_PER_ROW: Inner = FONT_WIDTH as Inner / CH_SIZE;
-        
-            sprite::XY {
-                x: sprite::X(
-                    (sprite_number % SPRITES_PER_ROW) * CH_SIZE
-                ),
-                y: sprite::Y(
-                    FONT_BASE_Y as Inner + 
-                    (sprite_number / SPRITES_PER_ROW) * CH_SIZE
-                ),
+        self.print_char_oriented(character, x, y, colour, <_>::default());
+    }
+
+    pub fn print_char_oriented(
+        &mut self,
+        character: u8, 
+        x: unscaled::X,
+        y: unscaled::Y,
+        colour: PaletteIndex,
+        orientation: Orientation,
+    ) {
+        self.push_sprite(
+            get_char_xy(character),
+            unscaled::Rect {
+                x,
+                y,
+                w: CHAR_W,
+                h: CHAR_H,
+            }.into(),
+            orientation,
+            Modulation {
+                replace: Some(PALETTE[colour as usize]),
+                ..self.modulation
+            },
+            atlas::Id::BUILT_IN,
+        );
+    }
+
+    /// Prints the text with its top left corner at `xy`, left aligned and
+    /// without wrapping. See `print_str_laid_out` for the details.
+    pub fn print_str(&mut self, text: &str, xy: unscaled::XY, colour: PaletteIndex) {
+        self.print_str_laid_out(text, xy, colour, <_>::default());
+    }
+
+    /// Prints the text in the current font with its top left corner at `xy`,
+    /// breaking lines at newlines, and wrapping and aligning as the layout
+    /// says. The text starts out in the given colour, and
+    /// `text::COLOUR_ESCAPE` followed by a digit switches to that palette
+    /// colour. See `text::lay_out`.
+    pub fn print_str_laid_out(
+        &mut self,
+        text: &str,
+        xy: unscaled::XY,
+        colour: PaletteIndex,
+        layout: text::Layout,
+    ) {
+        let font = self.font(self.font);
+        let (atlas, cell_size) = (font.atlas, font.cell_size);
+        let sprites: Vec<_> = text::lay_out(text, colour, layout, font).glyphs
+            .into_iter()
+            .map(|glyph| (font.cell_xy(glyph.cell), glyph))
+            .collect();
+
+        for (sprite_xy, glyph) in sprites {
+            let x = xy.x.saturating_add(glyph.offset.w);
+            let y = xy.y.saturating_add(glyph.offset.h);
+            if x.get() >= command::WIDTH || y.get() >= command::HEIGHT {
+                continue
             }
-        }
 
-        let sprite_xy = get_char_xy(character);
-        self.commands.push(
-            Command {
+            self.push_sprite(
                 sprite_xy,
-                rect: Rect::from_unscaled(unscaled::Rect {
+                unscaled::Rect {
                     x,
                     y,
-                    w: CHAR_W,
-                    h: CHAR_H,
-                }),
-                colour_override: PALETTE[colour as usize],
-            }
-        );
+                    w: cell_size.w,
+                    h: cell_size.h,
+                }.into(),
+                <_>::default(),
+                Modulation {
+                    replace: Some(PALETTE[glyph.colour as usize]),
+                    ..self.modulation
+                },
+                atlas,
+            );
+        }
     }
 
     pub fn draw_card(
@@ -75,17 +714,20 @@ impl Commands {
         x: unscaled::X,
         y: unscaled::Y
     ) {
-        self.sspr(
+        self.push_sprite(
             sprite::XY {
                 x: sprite::X(card::FRONT_SPRITE_X as _),
                 y: sprite::Y(card::FRONT_SPRITE_Y as _),
             },
-            Rect::from_unscaled(unscaled::Rect {
+            unscaled::Rect {
                 x,
                 y,
                 w: card::WIDTH,
                 h: card::HEIGHT,
-            })
+            }.into(),
+            <_>::default(),
+            self.modulation,
+            atlas::Id::BUILT_IN,
         );
 
         let (colour, suit_char) = get_suit_colour_and_char(get_suit(card));
@@ -105,100 +747,571 @@ impl Commands {
             colour,
         );
 
-        self.print_char(
-            rank_char | FONT_FLIP,
+        self.print_char_oriented(
+            rank_char,
             x + card::RIGHT_RANK_EDGE_W,
             y + card::RIGHT_RANK_EDGE_H,
             colour,
+            Orientation::ROTATE_180,
         );
-        self.print_char(
-            suit_char | FONT_FLIP,
+        self.print_char_oriented(
+            suit_char,
             x + card::RIGHT_SUIT_EDGE_W,
             y + card::RIGHT_SUIT_EDGE_H,
             colour,
+            Orientation::ROTATE_180,
         );
     }
 
+    /// Draws each of the given pixels, with a single command. This is much
+    /// faster than calling `draw_point` for each one, when there are many.
+    /// The colours are treated like texels, so the tint, alpha and blend mode
+    /// apply to them.
+    pub fn draw_points(&mut self, points: impl IntoIterator<Item = (world::XY, ARGB)>) {
+        let visible = match self.clip_rects.last() {
+            None => SCREEN_RECT,
+            Some(&Some(clip_rect)) => clip_rect,
+            Some(&None) => return,
+        };
+        let x_range = i64::from(visible.x_min.get().get())
+            ..=i64::from(visible.x_max.get().get());
+        let y_range = i64::from(visible.y_min.get().get())
+            ..=i64::from(visible.y_max.get().get());
+
+        let camera = self.camera();
+
+        let start = self.points.len();
+        // The bounding box of the visible points, as min and max corners.
+        let mut bounds: Option<((i64, i64), (i64, i64))> = None;
+
+        for (xy, colour) in points {
+            let x = i64::from(xy.x.0) - i64::from(camera.x.0);
+            let y = i64::from(xy.y.0) - i64::from(camera.y.0);
+            if !x_range.contains(&x) || !y_range.contains(&y) {
+                continue
+            }
+
+            bounds = Some(match bounds {
+                None => ((x, y), (x, y)),
+                Some(((x_min, y_min), (x_max, y_max))) => (
+                    (x_min.min(x), y_min.min(y)),
+                    (x_max.max(x), y_max.max(y)),
+                ),
+            });
+
+            // These are on screen, so they fit.
+            self.points.push(Point {
+                x: command::X::clipped_inner(x as unscaled::Inner),
+                y: command::Y::clipped_inner(y as unscaled::Inner),
+                colour,
+            });
+        }
+
+        let Some(((x_min, y_min), (x_max, y_max))) = bounds else { return };
+
+        // These are on screen, so they fit.
+        let rect = Rect {
+            x_min: command::X::clipped_inner(x_min as unscaled::Inner),
+            y_min: command::Y::clipped_inner(y_min as unscaled::Inner),
+            x_max: command::X::clipped_inner(x_max as unscaled::Inner),
+            y_max: command::Y::clipped_inner(y_max as unscaled::Inner),
+        };
+
+        // We don't expect anywhere near `u32::MAX` points in one frame.
+        self.push_command(Command {
+            rect,
+            modulation: self.modulation,
+            blend_mode: self.blend_mode,
+            points: command::Points {
+                start: start as u32,
+                end: self.points.len() as u32,
+            },
+            ..<_>::default()
+        });
+    }
+
     pub fn draw_point(
         &mut self,
         xy: unscaled::XY,
         colour: ARGB
     ) {
-        self.commands.push(
-            Command {
-                sprite_xy: <_>::default(),
-                rect: Rect::from_unscaled(unscaled::Rect {
-                    x: xy.x,
-                    y: xy.y,
-                    w: platform_types::unscaled::W(1),
-                    h: platform_types::unscaled::H(1),
-                }),
-                colour_override: colour,
-            }
+        self.push_solid(
+            unscaled::Rect {
+                x: xy.x,
+                y: xy.y,
+                w: platform_types::unscaled::W(1),
+                h: platform_types::unscaled::H(1),
+            }.into(),
+            colour,
         );
     }
-}
 
-pub mod card {
-    use super::*;
+    /// Fills the rect with the colour, with a single command.
+    pub fn fill_rect(&mut self, rect: world::Rect, colour: impl Colour) {
+        self.push_solid(rect, colour.to_argb());
+    }
 
-    use unscaled::{W, H, w_const_add, w_const_sub, h_const_add, h_const_sub};
+    /// Fills the rect by repeating the one texel of the solid atlas across it,
+    /// replaced with the colour.
+    fn push_solid(&mut self, rect: world::Rect, colour: ARGB) {
+        self.push_tiled_sprite(
+            <_>::default(),
+            rect,
+            <_>::default(),
+            Modulation {
+                replace: Some(colour),
+                ..self.modulation
+            },
+            atlas::Id::SOLID,
+            command::Tile { w: 1, h: 1, ..<_>::default() },
+        );
+    }
 
-    pub const WIDTH: W = W(20);
-    pub const HEIGHT: H = H(30);
+    /// Draws the one pixel wide outline of the rect, just inside it.
+    pub fn draw_rect(&mut self, rect: world::Rect, colour: impl Colour) {
+        let world::Rect { x, y, w, h } = rect;
+        let (w_inner, h_inner) = (w.get(), h.get());
+        if w_inner == 0 || h_inner == 0 {
+            return
+        }
 
-    pub const FRONT_SPRITE_X: u8 = 2;
-    pub const FRONT_SPRITE_Y: u8 = 1;
+        // Sides that start past the end of the range of world coordinates
+        // cannot be drawn, so we skip them.
+        let row = |y| world::Rect { x, y, w, h: unscaled::H(1) };
+        let column = |x| y.0.checked_add(1).map(|column_y| world::Rect {
+            x,
+            y: world::Y(column_y),
+            w: unscaled::W(1),
+            h: unscaled::H(h_inner.saturating_sub(2)),
+        });
+        let far_x = x.0.checked_add(world::Inner::from(w_inner - 1)).map(world::X);
+        let far_y = y.0.checked_add(world::Inner::from(h_inner - 1)).map(world::Y);
 
-    pub const LEFT_RANK_EDGE_W: W = W(3);
-    pub const LEFT_RANK_EDGE_H: H = H(3);
+        self.fill_rect(row(y), colour);
+        if let (true, Some(far_y)) = (h_inner > 1, far_y) {
+            self.fill_rect(row(far_y), colour);
+        }
+        if h_inner > 2 {
+            if let Some(near_column) = column(x) {
+                self.fill_rect(near_column, colour);
+            }
+            if let (true, Some(far_column)) = (w_inner > 1, far_x.and_then(column)) {
+                self.fill_rect(far_column, colour);
+            }
+        }
+    }
 
-    pub const LEFT_SUIT_EDGE_W: W = W(1);
-    pub const LEFT_SUIT_EDGE_H: H = H(10);
+    /// The part of the world that is currently visible, as ranges of x and y.
+    /// Returns `None` if nothing is visible. The ranges stop at the ends of
+    /// the range of world coordinates, so everything in them fits in a
+    /// `world::Inner`.
+    fn visible_world_ranges(&self) -> Option<(core::ops::RangeInclusive<i64>, core::ops::RangeInclusive<i64>)> {
+        let visible = match self.clip_rects.last() {
+            None => SCREEN_RECT,
+            Some(&Some(clip_rect)) => clip_rect,
+            Some(&None) => return None,
+        };
 
-    pub const RIGHT_RANK_EDGE_W: W = w_const_sub(
-        WIDTH, 
-        w_const_add(LEFT_RANK_EDGE_W, CHAR_W)
-    );
-    pub const RIGHT_RANK_EDGE_H: H = h_const_sub(
-        HEIGHT, 
-        h_const_add(LEFT_RANK_EDGE_H, CHAR_H)
-    );
+        let camera = self.camera();
+        let x = i64::from(camera.x.0);
+        let y = i64::from(camera.y.0);
 
-    pub const RIGHT_SUIT_EDGE_W: W = w_const_sub(
-        WIDTH, 
-        w_const_add(LEFT_SUIT_EDGE_W, CHAR_W)
-    );
-    pub const RIGHT_SUIT_EDGE_H: H = h_const_sub(
-        HEIGHT, 
-        h_const_add(LEFT_SUIT_EDGE_H, CHAR_H)
-    );
-}
+        let in_world = |start: i64, end: i64| {
+            let range = start.max(world::Inner::MIN.into())..=end.min(world::Inner::MAX.into());
 
-pub const TEN_CHAR: u8 = 27;
+            (!range.is_empty()).then_some(range)
+        };
 
-pub const CLUB_CHAR: u8 = 31;
-pub const DIAMOND_CHAR: u8 = 29;
-pub const HEART_CHAR: u8 = 30;
-pub const SPADE_CHAR: u8 = 28;
+        Some((
+            in_world(x + i64::from(visible.x_min.get().get()), x + i64::from(visible.x_max.get().get()))?,
+            in_world(y + i64::from(visible.y_min.get().get()), y + i64::from(visible.y_max.get().get()))?,
+        ))
+    }
 
-pub fn get_suit_colour_and_char(suit: Suit) -> (u8, u8) {
-    const RED_INDEX: u8 = 2;
-    const PURPLE_INDEX: u8 = 4;
-    const BLACK_INDEX: u8 = 7;
+    /// Draws a one pixel wide line, including both ends, with one pixel for
+    /// each step along the longer axis. Only the visible part of the line is
+    /// worked out, so the ends can be as far away as `world::XY` allows.
+    pub fn draw_line(&mut self, from: world::XY, to: world::XY, colour: impl Colour) {
+        let colour = colour.to_argb();
 
-    match suit {
-        suits::CLUBS => (BLACK_INDEX, CLUB_CHAR),
-        suits::DIAMONDS => (RED_INDEX, DIAMOND_CHAR),
-        suits::HEARTS => (RED_INDEX, HEART_CHAR),
-        suits::SPADES => (BLACK_INDEX, SPADE_CHAR),
-        _ => (PURPLE_INDEX, 33), //purple "!"
+        let Some((x_range, y_range)) = self.visible_world_ranges() else { return };
+
+        let (x0, y0) = (i64::from(from.x.0), i64::from(from.y.0));
+        let (x1, y1) = (i64::from(to.x.0), i64::from(to.y.0));
+
+        // We step along the major axis, one pixel at a time, and work out
+        // where along the minor axis each step is.
+        let is_x_major = (x1 - x0).abs() >= (y1 - y0).abs();
+        let (major, minor, major_range, minor_range) = if is_x_major {
+            ((x0, x1), (y0, y1), x_range, y_range)
+        } else {
+            ((y0, y1), (x0, x1), y_range, x_range)
+        };
+
+        let line = line::Line::new(major, minor);
+        let Some(steps) = line.visible_steps(major_range, minor_range) else { return };
+
+        self.draw_points(steps.map(|step| {
+            let (major, minor) = line.at(step);
+            let (x, y) = if is_x_major { (major, minor) } else { (minor, major) };
+
+            // These are visible, so they fit.
+            (
+                world::XY { x: world::X(x as world::Inner), y: world::Y(y as world::Inner) },
+                colour,
+            )
+        }));
     }
-}
 
-pub fn get_rank_char(card: Card) -> u8 {
-    get_rank_char_from_rank(get_rank(card))
-}
+    /// Fills the ellipse with the given center and radii, with one command per
+    /// visible row. The ellipse is `2 * radius + 1` pixels across in each
+    /// direction. Only the visible rows are worked out, so the radii can be as
+    /// large as `unscaled::Inner` allows.
+    pub fn fill_ellipse(
+        &mut self,
+        center: world::XY,
+        (x_radius, y_radius): (unscaled::W, unscaled::H),
+        colour: impl Colour,
+    ) {
+        let Some((x_range, y_range)) = self.visible_world_ranges() else { return };
+
+        let (x_radius, y_radius) = (x_radius.get(), y_radius.get());
+        let (center_x, center_y) = (i64::from(center.x.0), i64::from(center.y.0));
+
+        for dy in visible_offsets(center_y, y_radius, &y_range) {
+            let half_width = ellipse_half_width(x_radius, y_radius, dy);
+
+            // Trimming the row to the visible columns also keeps its width
+            // small enough to fit.
+            let x_start = core::cmp::max(center_x - half_width, *x_range.start());
+            let x_end = core::cmp::min(center_x + half_width, *x_range.end());
+            if x_start > x_end {
+                continue
+            }
+
+            // These are visible, so they fit.
+            self.fill_rect(
+                world::Rect {
+                    x: world::X(x_start as world::Inner),
+                    y: world::Y((center_y + dy) as world::Inner),
+                    w: unscaled::W((x_end - x_start + 1) as unscaled::Inner),
+                    h: unscaled::H(1),
+                },
+                colour,
+            );
+        }
+    }
+
+    /// Draws the one pixel wide outline of the ellipse that `fill_ellipse`
+    /// would fill, with a single command.
+    pub fn draw_ellipse(
+        &mut self,
+        center: world::XY,
+        (x_radius, y_radius): (unscaled::W, unscaled::H),
+        colour: impl Colour,
+    ) {
+        let colour = colour.to_argb();
+        let Some((x_range, y_range)) = self.visible_world_ranges() else { return };
+
+        let (x_radius, y_radius) = (x_radius.get(), y_radius.get());
+        let (center_x, center_y) = (i64::from(center.x.0), i64::from(center.y.0));
+
+        // Rows outside the ellipse are treated as being -1 wide.
+        let half_width = |dy: i64| if dy.abs() <= i64::from(y_radius) {
+            ellipse_half_width(x_radius, y_radius, dy)
+        } else {
+            -1
+        };
+
+        // The outline is the filled pixels that are next to an unfilled one.
+        // On each row, those are the ones past the end of the shorter of the
+        // rows above and below, or just the ends, if that is further out.
+        let mut points = Vec::new();
+        for dy in visible_offsets(center_y, y_radius, &y_range) {
+            let half_width_here = half_width(dy);
+            let narrower = core::cmp::min(half_width(dy - 1), half_width(dy + 1));
+            let start = core::cmp::min(narrower + 1, half_width_here);
+
+            // Only the visible parts of each side are added. The right side
+            // leaves out the center column, so it is not added twice.
+            let left = core::cmp::max(center_x - half_width_here, *x_range.start())
+                ..=core::cmp::min(center_x - start, *x_range.end());
+            let right = core::cmp::max(center_x + core::cmp::max(start, 1), *x_range.start())
+                ..=core::cmp::min(center_x + half_width_here, *x_range.end());
+
+            // These are visible, so they fit.
+            let y = world::Y((center_y + dy) as world::Inner);
+            for x in left.chain(right) {
+                points.push((world::XY { x: world::X(x as world::Inner), y }, colour));
+            }
+        }
+
+        self.draw_points(points);
+    }
+
+    /// Like `fill_ellipse` with both radii the same.
+    pub fn fill_circle(&mut self, center: world::XY, radius: unscaled::Inner, colour: impl Colour) {
+        self.fill_ellipse(center, (unscaled::W(radius), unscaled::H(radius)), colour);
+    }
+
+    /// Like `draw_ellipse` with both radii the same.
+    pub fn draw_circle(&mut self, center: world::XY, radius: unscaled::Inner, colour: impl Colour) {
+        self.draw_ellipse(center, (unscaled::W(radius), unscaled::H(radius)), colour);
+    }
+}
+
+/// Returns the offsets from `center` within `radius` of it that are in the
+/// visible range.
+fn visible_offsets(
+    center: i64,
+    radius: unscaled::Inner,
+    visible: &core::ops::RangeInclusive<i64>,
+) -> core::ops::RangeInclusive<i64> {
+    let radius = i64::from(radius);
+
+    core::cmp::max(-radius, visible.start() - center)
+        ..=core::cmp::min(radius, visible.end() - center)
+}
+
+/// Returns how far the filled pixels extend either side of the center column,
+/// on the row `dy` rows below the center of the ellipse with the given radii.
+/// `dy` must be within the y radius. A pixel is filled if its center is
+/// inside the ellipse with radii half a pixel larger than the given ones,
+/// which gives rounder small circles.
+fn ellipse_half_width(x_radius: unscaled::Inner, y_radius: unscaled::Inner, dy: i64) -> i64 {
+    // Doubling everything keeps the half pixels as integers. Then a pixel at
+    // offset `(dx, dy)` is filled if
+    // `(2dx / x_diameter)^2 + (2dy / y_diameter)^2 <= 1`.
+    let x_diameter = 2 * i128::from(x_radius) + 1;
+    let y_diameter = 2 * i128::from(y_radius) + 1;
+    let limit = x_diameter * x_diameter * y_diameter * y_diameter;
+    let dy = i128::from(dy);
+    let is_inside = |dx: i128| {
+        4 * dx * dx * y_diameter * y_diameter + 4 * dy * dy * x_diameter * x_diameter <= limit
+    };
+
+    // The center column is always inside, and once a column is outside, so
+    // are all the ones further out, so we can search for the last one inside.
+    let (mut inside, mut outside) = (0, i128::from(x_radius) + 1);
+    while outside - inside > 1 {
+        let middle = (inside + outside) / 2;
+        if is_inside(middle) {
+            inside = middle;
+        } else {
+            outside = middle;
+        }
+    }
+
+    // This is at most the x radius, so it fits.
+    inside as i64
+}
+
+const SCREEN_RECT: command::Rect = Rect {
+    x_min: command::X::clipped_inner(0),
+    y_min: command::Y::clipped_inner(0),
+    x_max: command::X::MAX,
+    y_max: command::Y::MAX,
+};
+
+fn intersect(a: command::Rect, b: command::Rect) -> Option<command::Rect> {
+    let x_min = core::cmp::max(a.x_min.get().get(), b.x_min.get().get());
+    let y_min = core::cmp::max(a.y_min.get().get(), b.y_min.get().get());
+    let x_max = core::cmp::min(a.x_max.get().get(), b.x_max.get().get());
+    let y_max = core::cmp::min(a.y_max.get().get(), b.y_max.get().get());
+
+    if x_min > x_max || y_min > y_max {
+        return None
+    }
+
+    Some(Rect {
+        x_min: command::X::clipped_inner(x_min),
+        y_min: command::Y::clipped_inner(y_min),
+        x_max: command::X::clipped_inner(x_max),
+        y_max: command::Y::clipped_inner(y_max),
+    })
+}
+
+/// Moves `sprite_xy` to account for the given number of pixels being cut off
+/// each edge of a command's rect, so that the rest of the sprite stays where
+/// it was on screen.
+fn shift_for_cut(
+    sprite_xy: &mut sprite::XY,
+    orientation: Orientation,
+    (left, right): (sprite::Inner, sprite::Inner),
+    (top, bottom): (sprite::Inner, sprite::Inner),
+) {
+    // The flips happen before the transpose, so they pick which edge matters.
+    let along_x = if orientation.contains(Orientation::FLIP_X) { right } else { left };
+    let along_y = if orientation.contains(Orientation::FLIP_Y) { bottom } else { top };
+
+    if orientation.contains(Orientation::TRANSPOSE) {
+        sprite_xy.x.0 = sprite_xy.x.0.saturating_add(along_y);
+        sprite_xy.y.0 = sprite_xy.y.0.saturating_add(along_x);
+    } else {
+        sprite_xy.x.0 = sprite_xy.x.0.saturating_add(along_x);
+        sprite_xy.y.0 = sprite_xy.y.0.saturating_add(along_y);
+    }
+}
+
+/// The maths behind `Commands::draw_line`, in terms of a major axis, that the
+/// line moves one pixel along each step, and a minor axis.
+mod line {
+    use core::cmp::{max, min};
+    use core::ops::RangeInclusive;
+
+    pub struct Line {
+        /// The first pixel, as major then minor.
+        start: (i64, i64),
+        /// How many steps there are after the first pixel.
+        steps: i64,
+        /// How far the line goes along the minor axis, in total.
+        rise: i64,
+        major_sign: i64,
+        minor_sign: i64,
+    }
+
+    fn sign(from: i64, to: i64) -> i64 {
+        if to < from { -1 } else { 1 }
+    }
+
+    impl Line {
+        /// The line from `(major.0, minor.0)` to `(major.1, minor.1)`. The
+        /// line should be at least as long along the major axis as along the
+        /// minor one.
+        pub fn new(major: (i64, i64), minor: (i64, i64)) -> Self {
+            Self {
+                start: (major.0, minor.0),
+                steps: (major.1 - major.0).abs(),
+                rise: (minor.1 - minor.0).abs(),
+                major_sign: sign(major.0, major.1),
+                minor_sign: sign(minor.0, minor.1),
+            }
+        }
+
+        /// How far along the minor axis the pixel at the given step is. That
+        /// is, how far the exact line has gone by then, rounded to the nearest
+        /// pixel, with halves rounded up.
+        fn offset(&self, step: i64) -> i64 {
+            if self.steps == 0 {
+                return 0
+            }
+
+            let (steps, rise) = (i128::from(self.steps), i128::from(self.rise));
+
+            ((2 * i128::from(step) * rise + steps) / (2 * steps)) as i64
+        }
+
+        /// The pixel at the given step, as major then minor.
+        pub fn at(&self, step: i64) -> (i64, i64) {
+            (
+                self.start.0 + self.major_sign * step,
+                self.start.1 + self.minor_sign * self.offset(step),
+            )
+        }
+
+        /// The steps whose pixels are inside both ranges, if there are any.
+        pub fn visible_steps(
+            &self,
+            major_range: RangeInclusive<i64>,
+            minor_range: RangeInclusive<i64>,
+        ) -> Option<RangeInclusive<i64>> {
+            let (min_step, max_step) = distances(self.start.0, self.major_sign, major_range);
+            let (min_offset, max_offset) = distances(self.start.1, self.minor_sign, minor_range);
+
+            let mut first = i128::from(max(0, min_step));
+            let mut last = i128::from(min(self.steps, max_step));
+
+            if self.rise == 0 {
+                if !(min_offset..=max_offset).contains(&0) {
+                    return None
+                }
+            } else {
+                // `offset` never goes down as the steps go up, so solving
+                // `offset(step) >= min_offset` and `offset(step) <= max_offset`
+                // for `step` gives the range of steps where it is visible.
+                let (steps, rise) = (i128::from(self.steps), i128::from(self.rise));
+                let (min_offset, max_offset) = (i128::from(min_offset), i128::from(max_offset));
+
+                first = max(first, -(-(2 * min_offset - 1) * steps).div_euclid(2 * rise));
+                last = min(last, ((2 * max_offset + 1) * steps - 1).div_euclid(2 * rise));
+            }
+
+            // Both are between 0 and `self.steps` here, so they fit.
+            (first <= last).then_some(first as i64..=last as i64)
+        }
+    }
+
+    /// The distances from `start`, going in the direction of `sign`, that end
+    /// up inside `range`, as min and max.
+    fn distances(start: i64, sign: i64, range: RangeInclusive<i64>) -> (i64, i64) {
+        if sign > 0 {
+            (range.start() - start, range.end() - start)
+        } else {
+            (start - range.end(), start - range.start())
+        }
+    }
+}
+
+pub mod card {
+    use super::*;
+
+    use unscaled::{W, H, w_const_add, w_const_sub, h_const_add, h_const_sub};
+
+    pub const WIDTH: W = W(20);
+    pub const HEIGHT: H = H(30);
+
+    pub const FRONT_SPRITE_X: u8 = 2;
+    pub const FRONT_SPRITE_Y: u8 = 1;
+
+    pub const LEFT_RANK_EDGE_W: W = W(3);
+    pub const LEFT_RANK_EDGE_H: H = H(3);
+
+    pub const LEFT_SUIT_EDGE_W: W = W(1);
+    pub const LEFT_SUIT_EDGE_H: H = H(10);
+
+    pub const RIGHT_RANK_EDGE_W: W = w_const_sub(
+        WIDTH, 
+        w_const_add(LEFT_RANK_EDGE_W, CHAR_W)
+    );
+    pub const RIGHT_RANK_EDGE_H: H = h_const_sub(
+        HEIGHT, 
+        h_const_add(LEFT_RANK_EDGE_H, CHAR_H)
+    );
+
+    pub const RIGHT_SUIT_EDGE_W: W = w_const_sub(
+        WIDTH, 
+        w_const_add(LEFT_SUIT_EDGE_W, CHAR_W)
+    );
+    pub const RIGHT_SUIT_EDGE_H: H = h_const_sub(
+        HEIGHT, 
+        h_const_add(LEFT_SUIT_EDGE_H, CHAR_H)
+    );
+}
+
+pub const TEN_CHAR: u8 = 27;
+
+pub const CLUB_CHAR: u8 = 31;
+pub const DIAMOND_CHAR: u8 = 29;
+pub const HEART_CHAR: u8 = 30;
+pub const SPADE_CHAR: u8 = 28;
+
+pub fn get_suit_colour_and_char(suit: Suit) -> (u8, u8) {
+    const RED_INDEX: u8 = 2;
+    const PURPLE_INDEX: u8 = 4;
+    const BLACK_INDEX: u8 = 7;
+
+    match suit {
+        suits::CLUBS => (BLACK_INDEX, CLUB_CHAR),
+        suits::DIAMONDS => (RED_INDEX, DIAMOND_CHAR),
+        suits::HEARTS => (RED_INDEX, HEART_CHAR),
+        suits::SPADES => (BLACK_INDEX, SPADE_CHAR),
+        _ => (PURPLE_INDEX, 33), //purple "!"
+    }
+}
+
+pub fn get_rank_char(card: Card) -> u8 {
+    get_rank_char_from_rank(get_rank(card))
+}
 
 pub fn get_rank_char_from_rank(rank: Rank) -> u8 {
     match rank {
@@ -219,9 +1332,2631 @@ pub fn get_rank_char_from_rank(rank: Rank) -> u8 {
     }
 }
 
-pub const CHAR_SIZE: u8 = 8;
-pub const CHAR_W: unscaled::W = unscaled::W(CHAR_SIZE as _);
-pub const CHAR_H: unscaled::H = unscaled::H(CHAR_SIZE as _);
+/// Returns where the given character is on the font sheet.
+pub fn get_char_xy(sprite_number: u8) -> sprite::XY {
+    type Inner = sprite::Inner;
+    let sprite_number = Inner::from(sprite_number);
+    const CH_SIZE: Inner = CHAR_SIZE as Inner;
+    const SPRITES_PER_ROW: Inner = FONT_WIDTH as Inner / CH_SIZE;
 
-pub const FONT_FLIP: u8 = 128;
+    sprite::XY {
+        x: sprite::X(
+            (sprite_number % SPRITES_PER_ROW) * CH_SIZE
+        ),
+        y: sprite::Y(
+            FONT_BASE_Y as Inner +
+            (sprite_number / SPRITES_PER_ROW) * CH_SIZE
+        ),
+    }
+}
+
+/// Splitting strings into positioned characters, for `Commands::print_str`
+/// and friends.
+pub mod text {
+    use super::*;
+    use std::collections::BTreeMap;
+    use std::sync::LazyLock;
+
+    /// Followed by a digit, switches to that palette colour. Followed by `r`,
+    /// switches back to the colour the text started with. Doubled, it is a
+    /// literal copy of itself. Otherwise, it is printed as is.
+    pub const COLOUR_ESCAPE: char = '^';
+
+    #[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+    pub enum Align {
+        #[default]
+        Left,
+        Centre,
+        Right,
+    }
+
+    #[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+    pub struct Layout {
+        /// If set, lines are broken between words so they are no wider than
+        /// this, or between characters, for words that are too wide alone.
+        pub wrap_width: Option<unscaled::W>,
+        /// How lines are aligned within the wrap width if there is one, or
+        /// else within the widest line.
+        pub align: Align,
+    }
+
+    /// A character of laid out text.
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    pub struct Glyph {
+        /// The cell of the font that the character is drawn with.
+        pub cell: u8,
+        /// From the top left corner of the text.
+        pub offset: unscaled::WH,
+        pub colour: PaletteIndex,
+    }
+
+    #[derive(Clone, Debug, PartialEq, Eq)]
+    pub struct LaidOut {
+        pub glyphs: Vec<Glyph>,
+        pub size: unscaled::WH,
+    }
+
+    /// Which font sheet cell each character is drawn with.
+    #[derive(Clone, Debug, PartialEq, Eq)]
+    pub struct GlyphMap {
+        cells: BTreeMap<char, u8>,
+        /// The cell for characters that are not in the map.
+        pub fallback: u8,
+    }
+
+    impl GlyphMap {
+        /// Returns a map that draws every character with the fallback cell.
+        pub fn new(fallback: u8) -> Self {
+            Self {
+                cells: BTreeMap::new(),
+                fallback,
+            }
+        }
+
+        /// Maps the character to the cell, and returns the cell it was mapped
+        /// to before, if any.
+        pub fn insert(&mut self, c: char, cell: u8) -> Option<u8> {
+            self.cells.insert(c, cell)
+        }
+
+        /// Returns the cell that `c` is drawn with.
+        pub fn get(&self, c: char) -> u8 {
+            self.cells.get(&c).copied().unwrap_or(self.fallback)
+        }
+    }
+
+    /// The map for the built-in font: printable ASCII, the suits, in both
+    /// filled and outlined forms, and `⑩` for the one cell ten.
+    impl Default for GlyphMap {
+        fn default() -> Self {
+            let mut output = Self::new(b'?');
+
+            for c in ' '..='~' {
+                output.insert(c, c as u8);
+            }
+
+            for (chars, cell) in [
+                (['♣', '♧'], CLUB_CHAR),
+                (['♦', '♢'], DIAMOND_CHAR),
+                (['♥', '♡'], HEART_CHAR),
+                (['♠', '♤'], SPADE_CHAR),
+            ] {
+                for c in chars {
+                    output.insert(c, cell);
+                }
+            }
+
+            output.insert('⑩', TEN_CHAR);
+
+            output
+        }
+    }
+
+    /// Identifies a font added with `Commands::add_font`.
+    #[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+    pub struct FontId(pub u8);
+
+    impl FontId {
+        /// The 8 by 8 font on the built-in atlas.
+        pub const BUILT_IN: Self = Self(0);
+    }
+
+    /// Where a font's glyphs are, and how to space them. The cells are laid
+    /// out in rows, left to right, then top to bottom, starting at `origin`.
+    #[derive(Clone, Debug, PartialEq, Eq)]
+    pub struct Font {
+        pub atlas: atlas::Id,
+        /// The top left corner of cell 0 in the atlas.
+        pub origin: sprite::XY,
+        pub cells_per_row: sprite::Inner,
+        /// The size of each cell, which is also the size each glyph is drawn at.
+        pub cell_size: unscaled::WH,
+        /// How far each cell moves the next glyph along the line, indexed by
+        /// cell. Cells past the end move it by the cell width.
+        pub advances: Vec<unscaled::Inner>,
+        /// How far apart the tops of consecutive lines are. Zero is treated as
+        /// one.
+        pub line_height: unscaled::H,
+        pub glyph_map: GlyphMap,
+    }
+
+    impl Font {
+        /// Returns a font where every cell moves the next glyph by the cell
+        /// width, and lines are the cell height apart, with the given glyph
+        /// map.
+        pub fn monospace(
+            atlas: atlas::Id,
+            origin: sprite::XY,
+            cells_per_row: sprite::Inner,
+            cell_size: unscaled::WH,
+            glyph_map: GlyphMap,
+        ) -> Self {
+            Self {
+                atlas,
+                origin,
+                cells_per_row,
+                cell_size,
+                advances: Vec::new(),
+                line_height: cell_size.h,
+                glyph_map,
+            }
+        }
+
+        /// Returns where the given cell is on the atlas.
+        pub fn cell_xy(&self, cell: u8) -> sprite::XY {
+            let cell = sprite::Inner::from(cell);
+            let cells_per_row = core::cmp::max(self.cells_per_row, 1);
+
+            sprite::XY {
+                x: self.origin.x + unscaled::W(
+                    (cell % cells_per_row) * self.cell_size.w.get()
+                ),
+                y: self.origin.y + unscaled::H(
+                    (cell / cells_per_row) * self.cell_size.h.get()
+                ),
+            }
+        }
+
+        /// Returns how far the given cell moves the next glyph along the line.
+        pub fn advance(&self, cell: u8) -> unscaled::Inner {
+            self.advances.get(usize::from(cell))
+                .copied()
+                .unwrap_or(self.cell_size.w.get())
+        }
+
+        fn width(&self, characters: &[Character]) -> unscaled::Inner {
+            characters.iter()
+                .fold(0, |width, character| width.saturating_add(self.advance(character.cell)))
+        }
+    }
+
+    /// The font on the built-in atlas, that `print_char` uses.
+    pub static BUILT_IN_FONT: LazyLock<Font> = LazyLock::new(|| Font::monospace(
+        atlas::Id::BUILT_IN,
+        get_char_xy(0),
+        sprite::Inner::from(FONT_WIDTH as u8 / CHAR_SIZE),
+        unscaled::WH { w: CHAR_W, h: CHAR_H },
+        <_>::default(),
+    ));
+
+    /// A character of the text, before it is laid out.
+    #[derive(Clone, Copy)]
+    struct Character {
+        c: char,
+        cell: u8,
+        colour: PaletteIndex,
+    }
+
+    /// Splits the line into characters, each with the colour it should be
+    /// drawn in, after applying any colour escapes. `colour` is updated to the
+    /// colour at the end of the line.
+    fn coloured_characters(
+        line: &str,
+        glyph_map: &GlyphMap,
+        default_colour: PaletteIndex,
+        colour: &mut PaletteIndex,
+    ) -> Vec<Character> {
+        let mut output = Vec::with_capacity(line.len());
+
+        let mut chars = line.chars().peekable();
+        while let Some(c) = chars.next() {
+            if c == COLOUR_ESCAPE {
+                match chars.peek().copied() {
+                    Some(COLOUR_ESCAPE) => {
+                        chars.next();
+                    },
+                    Some('r') => {
+                        chars.next();
+                        *colour = default_colour;
+                        continue
+                    },
+                    Some(digit) => {
+                        if let Some(index) = digit.to_digit(10)
+                            .filter(|&i| (i as usize) < PALETTE.len()) {
+                            chars.next();
+                            *colour = index as PaletteIndex;
+                            continue
+                        }
+                    },
+                    None => {},
+                }
+            }
+
+            output.push(Character {
+                c,
+                cell: glyph_map.get(c),
+                colour: *colour,
+            });
+        }
+
+        output
+    }
+
+    /// Breaks the line into lines no wider than `wrap_width`. Spaces where a
+    /// line was broken are dropped.
+    fn wrap(
+        characters: &[Character],
+        wrap_width: unscaled::Inner,
+        font: &Font,
+    ) -> Vec<Vec<Character>> {
+        let is_space = |character: &Character| character.c == ' ';
+
+        let mut lines = Vec::new();
+        let mut line = Vec::new();
+        let mut line_width: unscaled::Inner = 0;
+
+        let mut rest = characters;
+        while !rest.is_empty() {
+            let space_count = rest.iter().take_while(|c| is_space(c)).count();
+            let (spaces, after_spaces) = rest.split_at(space_count);
+            let word_length = after_spaces.iter().take_while(|c| !is_space(c)).count();
+            let (word, after_word) = after_spaces.split_at(word_length);
+            rest = after_word;
+
+            let (spaces_width, word_width) = (font.width(spaces), font.width(word));
+
+            let width_with_word = line_width
+                .saturating_add(spaces_width)
+                .saturating_add(word_width);
+            if width_with_word <= wrap_width {
+                line.extend_from_slice(spaces);
+                line.extend_from_slice(word);
+                line_width = width_with_word;
+                continue
+            }
+
+            if !line.is_empty() {
+                lines.push(core::mem::take(&mut line));
+                line_width = 0;
+            }
+
+            // The word does not fit on the end of the line, so either it
+            // starts a new one, or it is too wide alone, and gets broken up.
+            for &character in word {
+                let character_width = font.advance(character.cell);
+                if line_width.saturating_add(character_width) > wrap_width && !line.is_empty() {
+                    lines.push(core::mem::take(&mut line));
+                    line_width = 0;
+                }
+
+                line.push(character);
+                line_width = line_width.saturating_add(character_width);
+            }
+        }
+
+        lines.push(line);
+
+        lines
+    }
+
+    /// Positions each character of the text in the given font, without
+    /// drawing anything. Each line of the text takes up at least one line,
+    /// even if it is empty.
+    pub fn lay_out(
+        text: &str,
+        colour: PaletteIndex,
+        layout: Layout,
+        font: &Font,
+    ) -> LaidOut {
+        let mut current_colour = colour;
+
+        let lines: Vec<Vec<Character>> = text.split('\n')
+            .flat_map(|line| {
+                let characters = coloured_characters(
+                    line,
+                    &font.glyph_map,
+                    colour,
+                    &mut current_colour,
+                );
+
+                match layout.wrap_width {
+                    None => vec![characters],
+                    Some(wrap_width) => wrap(&characters, wrap_width.get(), font),
+                }
+            })
+            .collect();
+
+        let widest = lines.iter().map(|line| font.width(line)).max().unwrap_or(0);
+        let container_width = layout.wrap_width.map_or(widest, |w| w.get());
+        let line_height = core::cmp::max(font.line_height.get(), 1);
+
+        // Offsets past the largest one are clamped to it.
+        let mut glyphs = Vec::with_capacity(text.len());
+        let mut y: unscaled::Inner = 0;
+        for line in &lines {
+            let slack = container_width.saturating_sub(font.width(line));
+            let mut x = match layout.align {
+                Align::Left => 0,
+                Align::Centre => slack / 2,
+                Align::Right => slack,
+            };
+
+            for &Character { cell, colour, .. } in line {
+                glyphs.push(Glyph {
+                    cell,
+                    offset: unscaled::WH {
+                        w: unscaled::W(x),
+                        h: unscaled::H(y),
+                    },
+                    colour,
+                });
+                x = x.saturating_add(font.advance(cell));
+            }
+
+            y = y.saturating_add(line_height);
+        }
+
+        LaidOut {
+            glyphs,
+            size: unscaled::WH {
+                w: unscaled::W(container_width),
+                h: unscaled::H(
+                    unscaled::Inner::try_from(lines.len())
+                        .unwrap_or(unscaled::Inner::MAX)
+                        .saturating_mul(line_height)
+                ),
+            },
+        }
+    }
+
+    /// Returns the size that printing the text with the given layout and
+    /// font would take up.
+    pub fn measure(text: &str, layout: Layout, font: &Font) -> unscaled::WH {
+        lay_out(text, 0, layout, font).size
+    }
+}
+
+/// Sprites that change over time. An `Animation` describes the frames, and a
+/// `Player` keeps track of how far through them it is. Time is counted in
+/// ticks, which are usually calls to `State::frame`, so playback is the same
+/// on every run.
+pub mod animation {
+    use super::*;
+
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    pub struct Frame {
+        /// The top left corner of the frame on the atlas.
+        pub sprite_xy: sprite::XY,
+        /// How many ticks the frame is shown for. Zero is treated as one.
+        pub ticks: u32,
+    }
+
+    impl Frame {
+        fn ticks(self) -> u64 {
+            u64::from(core::cmp::max(self.ticks, 1))
+        }
+    }
+
+    /// What happens after the last frame.
+    #[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+    pub enum Mode {
+        /// Starts again from the first frame.
+        #[default]
+        Loop,
+        /// Plays the frames backwards to the first one, then forwards again,
+        /// without showing the first or last frame twice in a row.
+        PingPong,
+        /// Stays on the last frame.
+        Once,
+    }
+
+    #[derive(Clone, Debug, PartialEq, Eq)]
+    pub struct Animation {
+        pub frames: Vec<Frame>,
+        /// The size of every frame.
+        pub size: unscaled::WH,
+        pub mode: Mode,
+    }
+
+    impl Animation {
+        /// Returns how many ticks it takes to play the frames once, forwards.
+        pub fn ticks(&self) -> u64 {
+            self.frames.iter().map(|frame| frame.ticks()).sum()
+        }
+
+        /// Returns the index of the frame shown at the given tick, or `None`
+        /// if there are no frames.
+        pub fn frame_index_at(&self, tick: u32) -> Option<usize> {
+            let last = self.frames.len().checked_sub(1)?;
+            let tick = u64::from(tick);
+
+            let forwards = |mut tick: u64| {
+                for (i, frame) in self.frames.iter().enumerate() {
+                    if tick < frame.ticks() {
+                        return i
+                    }
+                    tick -= frame.ticks();
+                }
+                last
+            };
+
+            Some(match self.mode {
+                Mode::Loop => forwards(tick % self.ticks()),
+                Mode::Once => forwards(tick),
+                Mode::PingPong => {
+                    // The frames between the first and last, on the way back.
+                    let inner = self.frames.get(1..last).unwrap_or_default();
+                    let back_ticks: u64 = inner.iter().map(|frame| frame.ticks()).sum();
+
+                    let mut tick = tick % (self.ticks() + back_ticks);
+                    if tick < self.ticks() {
+                        forwards(tick)
+                    } else {
+                        tick -= self.ticks();
+
+                        let mut output = 1;
+                        for (i, frame) in inner.iter().enumerate().rev() {
+                            if tick < frame.ticks() {
+                                output = i + 1;
+                                break
+                            }
+                            tick -= frame.ticks();
+                        }
+                        output
+                    }
+                },
+            })
+        }
+    }
+
+    /// Plays an animation, one tick at a time.
+    #[derive(Clone, Debug, PartialEq, Eq)]
+    pub struct Player {
+        pub animation: Animation,
+        tick: u32,
+    }
+
+    impl Player {
+        pub fn new(animation: Animation) -> Self {
+            Self {
+                animation,
+                tick: 0,
+            }
+        }
 
+        /// How many ticks the player has advanced since it started.
+        pub fn tick(&self) -> u32 {
+            self.tick
+        }
+
+        /// Moves on by one tick. Meant to be called once per `State::frame`.
+        pub fn advance(&mut self) {
+            self.advance_by(1);
+        }
+
+        pub fn advance_by(&mut self, ticks: u32) {
+            self.tick = self.tick.saturating_add(ticks);
+        }
+
+        /// Goes back to the first frame.
+        pub fn restart(&mut self) {
+            self.tick = 0;
+        }
+
+        /// Returns the index of the frame to show now, or `None` if there are
+        /// no frames.
+        pub fn frame_index(&self) -> Option<usize> {
+            self.animation.frame_index_at(self.tick)
+        }
+
+        /// Returns where the frame to show now is on the atlas.
+        pub fn sprite_xy(&self) -> Option<sprite::XY> {
+            self.frame_index().map(|i| self.animation.frames[i].sprite_xy)
+        }
+
+        /// Returns whether a `Mode::Once` animation has shown its last frame
+        /// for as long as it should. Other modes never finish.
+        pub fn is_finished(&self) -> bool {
+            self.animation.mode == Mode::Once
+            && u64::from(self.tick) >= self.animation.ticks()
+        }
+    }
+}
+
+/// Grids of tiles from the atlas, for things like levels. See
+/// `Commands::draw_tilemap`.
+pub mod tilemap {
+    use super::*;
+    use animation::{Animation, Frame, Mode};
+    use std::collections::BTreeMap;
+
+    /// Which tile on the sheet to draw. Tiles are numbered left to right,
+    /// then top to bottom.
+    pub type Index = u16;
+
+    #[derive(Clone, Debug, PartialEq, Eq)]
+    pub struct Tilemap {
+        columns: usize,
+        rows: usize,
+        // Row major. `None` means nothing is drawn there.
+        tiles: Vec<Option<Index>>,
+        animations: BTreeMap<Index, Animation>,
+        pub tile_size: unscaled::WH,
+        /// The top left corner of tile 0 on the atlas.
+        pub sheet_origin: sprite::XY,
+        /// How many tiles each row of the sheet has.
+        pub sheet_columns: sprite::Inner,
+        /// How far the map is moved up and to the left when drawn.
+        pub scroll: world::XY,
+    }
+
+    impl Tilemap {
+        /// Returns a map of the given size with nothing in it, that draws tiles
+        /// from a sheet starting at the top left of the atlas.
+        pub fn new(
+            columns: usize,
+            rows: usize,
+            tile_size: unscaled::WH,
+            sheet_columns: sprite::Inner,
+        ) -> Self {
+            Self {
+                columns,
+                rows,
+                tiles: vec![None; columns * rows],
+                animations: BTreeMap::new(),
+                tile_size,
+                sheet_origin: <_>::default(),
+                sheet_columns,
+                scroll: <_>::default(),
+            }
+        }
+
+        pub fn columns(&self) -> usize {
+            self.columns
+        }
+
+        pub fn rows(&self) -> usize {
+            self.rows
+        }
+
+        /// Returns `None` if there is no tile there, including if the position
+        /// is outside the map.
+        pub fn get(&self, column: usize, row: usize) -> Option<Index> {
+            if column >= self.columns {
+                return None
+            }
+
+            self.tiles.get(row * self.columns + column).copied().flatten()
+        }
+
+        /// Does nothing if the position is outside the map.
+        pub fn set(&mut self, column: usize, row: usize, tile: Option<Index>) {
+            if column >= self.columns {
+                return
+            }
+
+            if let Some(slot) = self.tiles.get_mut(row * self.columns + column) {
+                *slot = tile;
+            }
+        }
+
+        /// Makes each place that has the given tile show the animation's
+        /// frames instead, and returns the tile's previous animation, if any.
+        /// The animation's `size` is not used, since every tile is drawn at
+        /// `tile_size`.
+        pub fn set_animation(&mut self, tile: Index, animation: Animation) -> Option<Animation> {
+            self.animations.insert(tile, animation)
+        }
+
+        pub fn remove_animation(&mut self, tile: Index) -> Option<Animation> {
+            self.animations.remove(&tile)
+        }
+
+        /// Returns an animation that loops through the given tiles of the
+        /// sheet, showing each for the given number of ticks.
+        pub fn animation(&self, tiles: &[Index], ticks_per_frame: u32) -> Animation {
+            Animation {
+                frames: tiles.iter()
+                    .map(|&tile| Frame {
+                        sprite_xy: self.sprite_xy(tile),
+                        ticks: ticks_per_frame,
+                    })
+                    .collect(),
+                size: self.tile_size,
+                mode: Mode::Loop,
+            }
+        }
+
+        /// Returns where on the atlas the given tile is drawn from at the given
+        /// tick, which is from its animation's frame if it has one.
+        pub fn sprite_xy_at(&self, tile: Index, tick: u32) -> sprite::XY {
+            self.animations.get(&tile)
+                .and_then(|animation| {
+                    animation.frame_index_at(tick).map(|i| animation.frames[i].sprite_xy)
+                })
+                .unwrap_or_else(|| self.sprite_xy(tile))
+        }
+
+        /// Returns where the given tile is on the atlas.
+        pub fn sprite_xy(&self, tile: Index) -> sprite::XY {
+            let sheet_columns = core::cmp::max(self.sheet_columns, 1);
+
+            sprite::XY {
+                x: self.sheet_origin.x + unscaled::W(
+                    (tile % sheet_columns) * self.tile_size.w.get()
+                ),
+                y: self.sheet_origin.y + unscaled::H(
+                    (tile / sheet_columns) * self.tile_size.h.get()
+                ),
+            }
+        }
+    }
+}
+
+/// Sprites cut into a 3 by 3 grid, so they can be drawn at any size without
+/// distorting the corners, for things like menus and dialog boxes. See
+/// `Commands::draw_nine_slice`.
+pub mod nine_slice {
+    use super::*;
+
+    /// How far in from each edge of the sprite the lines cutting it are.
+    #[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+    pub struct Insets {
+        pub left: unscaled::W,
+        pub top: unscaled::H,
+        pub right: unscaled::W,
+        pub bottom: unscaled::H,
+    }
+
+    impl Insets {
+        /// Returns insets that are the same on every side.
+        pub const fn all(inset: unscaled::Inner) -> Self {
+            Self {
+                left: unscaled::W(inset),
+                top: unscaled::H(inset),
+                right: unscaled::W(inset),
+                bottom: unscaled::H(inset),
+            }
+        }
+    }
+
+    /// How a slice covers more or less space than it takes up on the atlas.
+    #[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+    pub enum Fill {
+        /// Repeats the slice, cutting off the last copy if it does not fit.
+        /// This takes one command per slice.
+        #[default]
+        Tile,
+        /// Scales the slice, picking the nearest texel. This takes a command
+        /// for each run of texels that end up next to each other, and for each
+        /// texel that is repeated, so the number of commands depends on the
+        /// size of the slice, not the area it covers.
+        Stretch,
+    }
+
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    pub struct NineSlice {
+        /// The top left corner of the sprite on the atlas.
+        pub xy: sprite::XY,
+        pub size: unscaled::WH,
+        pub insets: Insets,
+        /// How the top and bottom edges fill the width, and the left and right
+        /// edges fill the height.
+        pub edges: Fill,
+        pub centre: Fill,
+    }
+
+    /// Where one of the three slices along an axis is, on the atlas and on
+    /// the screen, relative to the sprite and the target rect.
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    pub(crate) struct Part {
+        pub(crate) src: unscaled::Inner,
+        pub(crate) src_len: unscaled::Inner,
+        pub(crate) dst: unscaled::Inner,
+        pub(crate) dst_len: unscaled::Inner,
+    }
+
+    /// Cuts a sprite `size` long into three parts along an axis, and places
+    /// them to cover `target`.
+    pub(crate) fn parts(
+        size: unscaled::Inner,
+        (start_inset, end_inset): (unscaled::Inner, unscaled::Inner),
+        target: unscaled::Inner,
+    ) -> [Part; 3] {
+        let start_inset = core::cmp::min(start_inset, size);
+        let end_inset = core::cmp::min(end_inset, size - start_inset);
+
+        let (start, end) = if start_inset + end_inset > target {
+            let start = (
+                u32::from(target) * u32::from(start_inset)
+                / u32::from(start_inset + end_inset)
+            ) as unscaled::Inner;
+            (start, target - start)
+        } else {
+            (start_inset, end_inset)
+        };
+
+        [
+            Part {
+                src: 0,
+                src_len: start,
+                dst: 0,
+                dst_len: start,
+            },
+            Part {
+                src: start_inset,
+                src_len: size - start_inset - end_inset,
+                dst: start,
+                dst_len: target - start - end,
+            },
+            Part {
+                src: size - end,
+                src_len: end,
+                dst: target - end,
+                dst_len: end,
+            },
+        ]
+    }
+
+    /// A run of texels that are drawn next to each other, with the offsets
+    /// relative to the sprite and the target rect. Either each texel is drawn
+    /// once, and `src_len` is `len`, or one texel is repeated, and `src_len`
+    /// is 1.
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    pub(crate) struct Segment {
+        pub(crate) src: unscaled::Inner,
+        pub(crate) src_len: unscaled::Inner,
+        pub(crate) dst: unscaled::Inner,
+        pub(crate) len: unscaled::Inner,
+    }
+
+    impl Segment {
+        pub(crate) fn is_repeated(self) -> bool {
+            self.src_len < self.len
+        }
+    }
+
+    /// Splits the part into runs that can each be drawn with one command, when
+    /// it is stretched. This gives at most about two segments per texel.
+    pub(crate) fn segments(part: Part) -> Vec<Segment> {
+        let Part { src, src_len, dst, dst_len } = part;
+
+        if src_len == 0 || dst_len == 0 {
+            return Vec::new()
+        }
+
+        if src_len == dst_len {
+            return vec![Segment { src, src_len, dst, len: dst_len }]
+        }
+
+        let src_offset = |i: unscaled::Inner| (
+            u32::from(i) * u32::from(src_len) / u32::from(dst_len)
+        ) as unscaled::Inner;
+        // How far along the sprite the pixel at `i` is from the one before it.
+        let step = |i: unscaled::Inner| src_offset(i) - src_offset(i - 1);
+
+        let mut output = Vec::new();
+        let mut start = 0;
+        for i in 1..=dst_len {
+            // The second pixel decides whether the segment repeats its texel,
+            // with a step of 0, or moves along one texel each time, with a
+            // step of 1. Anything else needs a new segment.
+            let continues = i < dst_len && if i - start == 1 {
+                step(i) <= 1
+            } else {
+                step(i) == step(start + 1)
+            };
+            if continues {
+                continue
+            }
+
+            let len = i - start;
+            let is_repeated = len > 1 && step(start + 1) == 0;
+            output.push(Segment {
+                src: src + src_offset(start),
+                src_len: if is_repeated { 1 } else { len },
+                dst: dst + start,
+                len,
+            });
+            start = i;
+        }
+
+        output
+    }
+}
+
+pub const CHAR_SIZE: u8 = 8;
+pub const CHAR_W: unscaled::W = unscaled::W(CHAR_SIZE as _);
+pub const CHAR_H: unscaled::H = unscaled::H(CHAR_SIZE as _);
+
+/// The font sheet has an upside-down copy of each character at this offset.
+/// `print_char_oriented` with `Orientation::ROTATE_180` draws the same thing,
+/// and also works for any other sprite.
+pub const FONT_FLIP: u8 = 128;
+
+/// Helpers shared by the test modules below.
+#[cfg(test)]
+mod test_helpers {
+    use super::*;
+    use platform_types::colours;
+
+    /// Renders the commands, along with the points and texture they draw
+    /// from, at the size of the screen, and returns the pixels.
+    pub fn rendered(commands: &mut Commands) -> Vec<ARGB> {
+        render::render_graphics_headless(
+            commands.graphics(),
+            (command::WIDTH, command::HEIGHT),
+        ).buffer
+    }
+
+    /// Returns the top left `w` by `h` pixels of the screen sized buffer as
+    /// text, with `#` for each pixel that changed, and `.` for the rest.
+    pub fn as_text(buffer: &[ARGB], (w, h): (usize, usize)) -> String {
+        let mut output = String::new();
+        for y in 0..h {
+            for x in 0..w {
+                output.push(
+                    if buffer[y * usize::from(command::WIDTH) + x] == colours::BLACK {
+                        '.'
+                    } else {
+                        '#'
+                    }
+                );
+            }
+            output.push('\n');
+        }
+        output
+    }
+
+    pub type Piece = (sprite::Inner, sprite::Inner, u16, u16, u16, u16, sprite::Inner, sprite::Inner);
+
+    /// Returns the atlas texel, the screen rect and the tile size of each
+    /// command, as `(sprite_x, sprite_y, x, y, w, h, tile_w, tile_h)`.
+    pub fn pieces(commands: &mut Commands) -> Vec<Piece> {
+        commands.slice().iter()
+            .map(|command| {
+                let rect = command.rect;
+                let (x, y) = (rect.x_min.get().get(), rect.y_min.get().get());
+                (
+                    command.sprite_xy.x.0,
+                    command.sprite_xy.y.0,
+                    x,
+                    y,
+                    rect.x_max.get().get() - x + 1,
+                    rect.y_max.get().get() - y + 1,
+                    command.tile.w,
+                    command.tile.h,
+                )
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod print_char_oriented_with_rotate_180 {
+    use super::*;
+    use test_helpers::rendered;
+
+    fn assert_same_pixels_as_font_flip(x: unscaled::X, y: unscaled::Y) {
+        for character in 0..FONT_FLIP {
+            let mut expected = Commands::default();
+            expected.print_char(character | FONT_FLIP, x, y, 6);
+
+            let mut actual = Commands::default();
+            actual.print_char_oriented(character, x, y, 6, Orientation::ROTATE_180);
+
+            assert_eq!(
+                rendered(&mut expected),
+                rendered(&mut actual),
+                "mismatch for character {character}"
+            );
+        }
+    }
+
+    #[test]
+    fn draws_the_same_pixels_as_font_flip_for_every_character() {
+        assert_same_pixels_as_font_flip(unscaled::X(0), unscaled::Y(0));
+    }
+
+    #[test]
+    fn draws_the_same_pixels_as_font_flip_when_partially_off_screen() {
+        assert_same_pixels_as_font_flip(
+            unscaled::X(command::WIDTH - 3),
+            unscaled::Y(command::HEIGHT - 5),
+        );
+    }
+}
+
+#[cfg(test)]
+mod layers_work {
+    use super::*;
+
+    fn point_xs(commands: &mut Commands) -> Vec<unscaled::Inner> {
+        commands.slice()
+            .iter()
+            .map(|command| command.rect.unscaled().x.get())
+            .collect()
+    }
+
+    fn draw_point(commands: &mut Commands, x: unscaled::Inner) {
+        commands.draw_point(
+            unscaled::XY { x: unscaled::X(x), y: unscaled::Y(0) },
+            platform_types::colours::RED,
+        );
+    }
+
+    #[test]
+    fn on_this_out_of_order_example() {
+        let mut commands = Commands::default();
+
+        commands.set_layer(Layer::ACTORS);
+        draw_point(&mut commands, 3);
+        draw_point(&mut commands, 4);
+
+        commands.set_layer(Layer::UI);
+        draw_point(&mut commands, 6);
+
+        commands.set_layer(Layer::BACKGROUND);
+        draw_point(&mut commands, 0);
+        draw_point(&mut commands, 1);
+
+        commands.set_layer(Layer::ACTORS);
+        draw_point(&mut commands, 5);
+
+        commands.set_layer(Layer::BACKGROUND);
+        draw_point(&mut commands, 2);
+
+        assert_eq!(point_xs(&mut commands), vec![0, 1, 2, 3, 4, 5, 6]);
+    }
+
+    #[test]
+    fn on_adding_lower_layers_after_the_commands_were_sorted() {
+        let mut commands = Commands::default();
+
+        commands.set_layer(Layer::UI);
+        draw_point(&mut commands, 2);
+        commands.set_layer(Layer::ACTORS);
+        draw_point(&mut commands, 1);
+
+        assert_eq!(point_xs(&mut commands), vec![1, 2]);
+
+        commands.set_layer(Layer::BACKGROUND);
+        draw_point(&mut commands, 0);
+        commands.set_layer(Layer::UI);
+        draw_point(&mut commands, 3);
+
+        assert_eq!(point_xs(&mut commands), vec![0, 1, 2, 3]);
+    }
+
+    #[test]
+    fn clear_resets_the_layer() {
+        let mut commands = Commands::default();
+
+        commands.set_layer(Layer::UI);
+        draw_point(&mut commands, 1);
+
+        commands.clear();
+        draw_point(&mut commands, 0);
+        commands.set_layer(Layer::UI);
+        draw_point(&mut commands, 1);
+
+        assert_eq!(point_xs(&mut commands), vec![0, 1]);
+    }
+}
+
+#[cfg(test)]
+mod clip_rects_work {
+    use super::*;
+    use test_helpers::rendered;
+    use platform_types::colours;
+
+
+    fn draw_sprites(commands: &mut Commands) {
+        let orientations = [
+            Orientation::default(),
+            Orientation::FLIP_X,
+            Orientation::FLIP_Y,
+            Orientation::ROTATE_90,
+            Orientation::ROTATE_180,
+            Orientation::ROTATE_270,
+            Orientation::TRANSPOSE,
+            Orientation::TRANSPOSE | Orientation::ROTATE_180,
+        ];
+
+        for (i, &orientation) in orientations.iter().enumerate() {
+            let i = i as unscaled::Inner;
+            commands.sspr_oriented(
+                sprite::XY { x: sprite::X(8 * i), y: sprite::Y(4 * i) },
+                unscaled::Rect {
+                    x: unscaled::X(20 * i),
+                    y: unscaled::Y(10 * i),
+                    w: unscaled::W(40),
+                    h: unscaled::H(30),
+                },
+                orientation,
+            );
+        }
+        commands.draw_card(0, unscaled::X(30), unscaled::Y(30));
+    }
+
+    fn is_inside(i: usize, rect: unscaled::Rect) -> bool {
+        let x = (i % usize::from(command::WIDTH)) as unscaled::Inner;
+        let y = (i / usize::from(command::WIDTH)) as unscaled::Inner;
+
+        x >= rect.x.get() && x < rect.x.get() + rect.w.get()
+        && y >= rect.y.get() && y < rect.y.get() + rect.h.get()
+    }
+
+    #[test]
+    fn on_these_nested_rects() {
+        let outer = unscaled::Rect {
+            x: unscaled::X(13),
+            y: unscaled::Y(7),
+            w: unscaled::W(100),
+            h: unscaled::H(90),
+        };
+        let inner = unscaled::Rect {
+            x: unscaled::X(40),
+            y: unscaled::Y(21),
+            w: unscaled::W(200),
+            h: unscaled::H(40),
+        };
+        // The intersection of the two.
+        let expected_rect = unscaled::Rect {
+            x: unscaled::X(40),
+            y: unscaled::Y(21),
+            w: unscaled::W(73),
+            h: unscaled::H(40),
+        };
+
+        let mut unclipped = Commands::default();
+        draw_sprites(&mut unclipped);
+        let unclipped = rendered(&mut unclipped);
+
+        let blank = rendered(&mut Commands::default());
+
+        let mut clipped = Commands::default();
+        clipped.push_clip_rect(outer);
+        clipped.push_clip_rect(inner);
+        draw_sprites(&mut clipped);
+        let clipped = rendered(&mut clipped);
+
+        for (i, &actual) in clipped.iter().enumerate() {
+            let expected = if is_inside(i, expected_rect) {
+                unclipped[i]
+            } else {
+                blank[i]
+            };
+
+            assert_eq!(expected, actual, "mismatch at index {i}");
+        }
+    }
+
+    #[test]
+    fn on_popping_back_to_no_clip_rect() {
+        let mut expected = Commands::default();
+        draw_sprites(&mut expected);
+
+        let mut actual = Commands::default();
+        actual.push_clip_rect(unscaled::Rect {
+            x: unscaled::X(1),
+            y: unscaled::Y(2),
+            w: unscaled::W(3),
+            h: unscaled::H(4),
+        });
+        actual.pop_clip_rect();
+        draw_sprites(&mut actual);
+
+        assert_eq!(rendered(&mut expected), rendered(&mut actual));
+    }
+
+    #[test]
+    fn on_a_rect_that_is_off_screen() {
+        let mut commands = Commands::default();
+        commands.push_clip_rect(unscaled::Rect {
+            x: unscaled::X(command::WIDTH),
+            y: unscaled::Y(0),
+            w: unscaled::W(10),
+            h: unscaled::H(10),
+        });
+        commands.draw_point(
+            unscaled::XY { x: unscaled::X(command::WIDTH - 1), y: unscaled::Y(0) },
+            colours::RED,
+        );
+
+        assert!(commands.slice().is_empty());
+    }
+}
+
+#[cfg(test)]
+mod cameras_work {
+    use super::*;
+    use test_helpers::rendered;
+
+    fn draw_sprite(commands: &mut Commands, orientation: Orientation) {
+        commands.sspr_world(
+            sprite::XY { x: sprite::X(8), y: sprite::Y(4) },
+            world::Rect {
+                x: world::X(10),
+                y: world::Y(12),
+                w: unscaled::W(40),
+                h: unscaled::H(30),
+            },
+            orientation,
+        );
+    }
+
+    /// Checks that drawing with the given camera offset gives the same pixels
+    /// as drawing without one, moved by the offset. The sprite is entirely on
+    /// screen without the offset.
+    fn assert_moved_by(offsets: &[world::XY]) {
+        let blank = rendered(&mut Commands::default());
+
+        let total = offsets.iter().fold(world::XY::default(), |acc, &o| acc + o);
+
+        let orientations = [
+            Orientation::default(),
+            Orientation::FLIP_X,
+            Orientation::FLIP_Y,
+            Orientation::ROTATE_90,
+            Orientation::ROTATE_180,
+            Orientation::ROTATE_270,
+            Orientation::TRANSPOSE,
+            Orientation::TRANSPOSE | Orientation::ROTATE_180,
+        ];
+
+        for orientation in orientations {
+            let mut unmoved = Commands::default();
+            draw_sprite(&mut unmoved, orientation);
+            let unmoved = rendered(&mut unmoved);
+
+            let mut moved = Commands::default();
+            for &offset in offsets {
+                moved.push_camera(offset);
+            }
+            draw_sprite(&mut moved, orientation);
+            let moved = rendered(&mut moved);
+
+            let width = world::Inner::from(command::WIDTH);
+            let height = world::Inner::from(command::HEIGHT);
+            for (i, &actual) in moved.iter().enumerate() {
+                let x = i as world::Inner % width + total.x.0;
+                let y = i as world::Inner / width + total.y.0;
+
+                let expected = if (0..width).contains(&x) && (0..height).contains(&y) {
+                    unmoved[(y * width + x) as usize]
+                } else {
+                    blank[i]
+                };
+
+                assert_eq!(
+                    expected,
+                    actual,
+                    "mismatch at index {i} with {orientation:?}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn when_partly_off_the_top_left() {
+        assert_moved_by(&[world::XY { x: world::X(40), y: world::Y(30) }]);
+    }
+
+    #[test]
+    fn when_partly_off_the_bottom_right() {
+        assert_moved_by(&[world::XY {
+            x: world::X(-(world::Inner::from(command::WIDTH) - 30)),
+            y: world::Y(-(world::Inner::from(command::HEIGHT) - 20)),
+        }]);
+    }
+
+    #[test]
+    fn when_nested() {
+        assert_moved_by(&[
+            world::XY { x: world::X(20), y: world::Y(-300) },
+            world::XY { x: world::X(20), y: world::Y(330) },
+        ]);
+    }
+
+    #[test]
+    fn when_entirely_off_screen() {
+        let mut commands = Commands::default();
+        commands.push_camera(world::XY { x: world::X(1000), y: world::Y(0) });
+        draw_sprite(&mut commands, <_>::default());
+
+        assert!(commands.slice().is_empty());
+    }
+
+    #[test]
+    fn after_popping() {
+        let mut expected = Commands::default();
+        draw_sprite(&mut expected, <_>::default());
+
+        let mut actual = Commands::default();
+        actual.push_camera(world::XY { x: world::X(-3), y: world::Y(5) });
+        actual.pop_camera();
+        draw_sprite(&mut actual, <_>::default());
+
+        assert_eq!(rendered(&mut expected), rendered(&mut actual));
+    }
+
+    #[test]
+    fn at_the_ends_of_the_world_coordinates() {
+        let draw = |commands: &mut Commands, x: world::Inner, y: world::Inner| {
+            commands.sspr_world(
+                sprite::XY { x: sprite::X(8), y: sprite::Y(4) },
+                world::Rect {
+                    x: world::X(x - 10),
+                    y: world::Y(y + 12),
+                    w: unscaled::W(40),
+                    h: unscaled::H(30),
+                },
+                <_>::default(),
+            );
+            commands.draw_points([
+                (world::XY { x: world::X(x), y: world::Y(y) }, 0xFFFFFFFF),
+                (world::XY { x: world::X(!x), y: world::Y(!y) }, 0xFFFFFFFF),
+            ]);
+            commands.draw_tilemap(
+                &tilemap::Tilemap::new(
+                    4,
+                    4,
+                    unscaled::WH { w: unscaled::W(8), h: unscaled::H(8) },
+                    4,
+                ),
+                0,
+            );
+        };
+
+        let mut expected = Commands::default();
+        draw(&mut expected, 0, 0);
+
+        let mut actual = Commands::default();
+        let corner = world::XY { x: world::X(world::Inner::MAX), y: world::Y(world::Inner::MIN) };
+        // The second one saturates, so the camera stays at the corner.
+        actual.push_camera(corner);
+        actual.push_camera(corner);
+        draw(&mut actual, world::Inner::MAX, world::Inner::MIN);
+
+        assert_eq!(rendered(&mut expected), rendered(&mut actual));
+    }
+}
+
+#[cfg(test)]
+mod set_atlas_works {
+    use super::*;
+
+    fn sprite_rect() -> command::Rect {
+        Rect::from_unscaled(unscaled::Rect {
+            x: unscaled::X(1),
+            y: unscaled::Y(2),
+            w: unscaled::W(3),
+            h: unscaled::H(4),
+        })
+    }
+
+    #[test]
+    fn on_sprites_but_not_text() {
+        let mut commands = Commands::default();
+
+        assert_eq!(commands.set_atlas(atlas::Id(1)), atlas::Id::BUILT_IN);
+
+        commands.sspr(<_>::default(), sprite_rect());
+        commands.print_char(b'A', unscaled::X(0), unscaled::Y(0), 6);
+
+        let atlases: Vec<_> = commands.slice().iter().map(|c| c.atlas).collect();
+
+        assert_eq!(atlases, [atlas::Id(1), atlas::Id::BUILT_IN]);
+    }
+
+    #[test]
+    fn after_a_clear() {
+        let mut commands = Commands::default();
+
+        commands.set_atlas(atlas::Id(1));
+        commands.clear();
+        commands.sspr(<_>::default(), sprite_rect());
+
+        assert_eq!(commands.slice()[0].atlas, atlas::Id::BUILT_IN);
+    }
+}
+
+#[cfg(test)]
+mod draw_texture_works {
+    use super::*;
+    use platform_types::colours;
+    use test_helpers::rendered;
+
+    #[test]
+    fn on_this_texture_drawn_partly_off_screen() {
+        let mut commands = Commands::default();
+        *commands.texture_mut() = Texture::new(4, 3);
+        commands.texture_mut().set(3, 2, colours::RED);
+
+        commands.draw_texture(world::XY { x: world::X(-3), y: world::Y(-2) });
+
+        let buffer = rendered(&mut commands);
+
+        assert_eq!(buffer[0], colours::RED);
+        assert_eq!(buffer[1], colours::BLACK);
+    }
+}
+
+#[cfg(test)]
+mod draw_points_works {
+    use super::*;
+    use test_helpers::rendered;
+    use platform_types::ARGB;
+
+
+    fn points() -> Vec<(world::XY, ARGB)> {
+        let at = |x, y| world::XY { x: world::X(x), y: world::Y(y) };
+
+        vec![
+            (at(-1, 5), 0xFF0000FF),
+            (at(3, 4), 0xFF00FF00),
+            (at(3, 4), 0xFFFF0000),
+            (at(40, 30), 0xFFFFFFFF),
+            (at(479, 319), 0xFFFFFF00),
+            (at(480, 5), 0xFF00FFFF),
+            (at(100, 200), 0xFF123456),
+        ]
+    }
+
+    /// Checks that `draw_points` gives the same pixels as drawing each point
+    /// the way `draw_point` does, after `setup`. Unlike `draw_point`, this
+    /// allows points left of or above the screen.
+    fn assert_same_as_draw_point(setup: fn(&mut Commands)) {
+        let mut expected = Commands::default();
+        setup(&mut expected);
+        for (xy, colour) in points() {
+            expected.push_sprite(
+                <_>::default(),
+                world::Rect { x: xy.x, y: xy.y, w: unscaled::W(1), h: unscaled::H(1) },
+                <_>::default(),
+                Modulation::replace(colour),
+                atlas::Id::BUILT_IN,
+            );
+        }
+
+        let mut actual = Commands::default();
+        setup(&mut actual);
+        actual.draw_points(points());
+
+        assert_eq!(
+            rendered(&mut expected),
+            rendered(&mut actual),
+        );
+    }
+
+    #[test]
+    fn on_these_points() {
+        assert_same_as_draw_point(|_| {});
+    }
+
+    #[test]
+    fn with_a_camera_and_a_clip_rect() {
+        assert_same_as_draw_point(|commands| {
+            commands.push_camera(world::XY { x: world::X(-2), y: world::Y(3) });
+            commands.push_clip_rect(unscaled::Rect {
+                x: unscaled::X(2),
+                y: unscaled::Y(2),
+                w: unscaled::W(200),
+                h: unscaled::H(200),
+            });
+        });
+    }
+
+    #[test]
+    fn by_adding_nothing_when_no_points_are_visible() {
+        let mut commands = Commands::default();
+
+        commands.draw_points([(world::XY { x: world::X(-1), y: world::Y(0) }, 0xFFFFFFFF)]);
+
+        assert!(commands.slice().is_empty());
+    }
+}
+
+#[cfg(test)]
+mod shapes_work {
+    use super::*;
+    use platform_types::colours;
+    use test_helpers::{as_text, rendered};
+
+    /// Draws with `draw`, and returns the top left `w` by `h` pixels as text,
+    /// with `#` for each pixel that changed, and `.` for the rest.
+    fn drawn(draw: impl FnOnce(&mut Commands), wh: (usize, usize)) -> String {
+        let mut commands = Commands::default();
+        draw(&mut commands);
+
+        as_text(&rendered(&mut commands), wh)
+    }
+
+    fn at(x: world::Inner, y: world::Inner) -> world::XY {
+        world::XY { x: world::X(x), y: world::Y(y) }
+    }
+
+    fn rect(x: world::Inner, y: world::Inner, w: unscaled::Inner, h: unscaled::Inner) -> world::Rect {
+        world::Rect { x: world::X(x), y: world::Y(y), w: unscaled::W(w), h: unscaled::H(h) }
+    }
+
+    #[test]
+    fn on_fill_rect() {
+        assert_eq!(drawn(|c| c.fill_rect(rect(1, 1, 3, 2), colours::RED), (5, 4)), "\
+.....
+.###.
+.###.
+.....
+");
+    }
+
+    #[test]
+    fn on_fill_rect_with_a_palette_colour() {
+        let mut commands = Commands::default();
+        commands.fill_rect(rect(0, 0, 1, 1), 6 as PaletteIndex);
+
+        assert_eq!(rendered(&mut commands)[0], PALETTE[6]);
+    }
+
+    #[test]
+    fn on_fill_rect_partly_off_the_top_left() {
+        assert_eq!(drawn(|c| c.fill_rect(rect(-300, -2, 302, 4), colours::RED), (4, 3)), "\
+##..
+##..
+....
+");
+    }
+
+    #[test]
+    fn on_draw_rect() {
+        assert_eq!(drawn(|c| c.draw_rect(rect(0, 0, 5, 4), colours::RED), (6, 5)), "\
+#####.
+#...#.
+#...#.
+#####.
+......
+");
+    }
+
+    #[test]
+    fn on_draw_rect_when_it_is_thin() {
+        assert_eq!(drawn(|c| {
+            c.draw_rect(rect(0, 0, 1, 3), colours::RED);
+            c.draw_rect(rect(2, 0, 3, 2), colours::RED);
+            c.draw_rect(rect(6, 0, 0, 3), colours::RED);
+        }, (7, 4)), "\
+#.###..
+#.###..
+#......
+.......
+");
+    }
+
+    #[test]
+    fn on_draw_rect_past_the_end_of_the_world_coordinates() {
+        let max = world::Inner::MAX;
+
+        // The right side would be past the end, so it is not drawn.
+        assert_eq!(drawn(|c| {
+            c.push_camera(at(max - 2, 0));
+            c.draw_rect(rect(max - 1, 0, 5, 3), colours::RED);
+        }, (7, 3)), "\
+.#####.
+.#.....
+.#####.
+");
+    }
+
+    #[test]
+    fn on_a_shallow_line() {
+        assert_eq!(drawn(|c| c.draw_line(at(0, 0), at(5, 2), colours::RED), (7, 4)), "\
+##.....
+..##...
+....##.
+.......
+");
+    }
+
+    #[test]
+    fn on_a_steep_line_drawn_backwards() {
+        assert_eq!(drawn(|c| c.draw_line(at(2, 4), at(0, 0), colours::RED), (4, 6)), "\
+#...
+#...
+.#..
+.#..
+..#.
+....
+");
+    }
+
+    #[test]
+    fn on_lines_that_go_off_screen_at_the_same_slope() {
+        let on_screen = "\
+##.....
+..##...
+....###
+.......
+";
+
+        assert_eq!(drawn(|c| c.draw_line(at(0, 0), at(10, 4), colours::RED), (7, 4)), on_screen);
+        assert_eq!(drawn(|c| c.draw_line(at(-5, -2), at(10, 4), colours::RED), (7, 4)), on_screen);
+        assert_eq!(
+            drawn(|c| c.draw_line(at(0, 0), at(1_000_000_000, 400_000_000), colours::RED), (7, 4)),
+            on_screen
+        );
+    }
+
+    #[test]
+    fn on_a_line_between_the_furthest_points() {
+        let min = world::Inner::MIN;
+        let max = world::Inner::MAX;
+
+        assert_eq!(drawn(|c| c.draw_line(at(min, min), at(max, max), colours::RED), (3, 3)), "\
+#..
+.#.
+..#
+");
+        assert_eq!(drawn(|c| c.draw_line(at(max, min), at(min, max), colours::RED), (3, 3)), "\
+...
+...
+...
+");
+    }
+
+    #[test]
+    fn on_a_single_pixel_line() {
+        assert_eq!(drawn(|c| c.draw_line(at(1, 1), at(1, 1), colours::RED), (3, 3)), "\
+...
+.#.
+...
+");
+    }
+
+    #[test]
+    fn on_fill_circle() {
+        assert_eq!(drawn(|c| c.fill_circle(at(2, 2), 2, colours::RED), (6, 6)), "\
+.###..
+#####.
+#####.
+#####.
+.###..
+......
+");
+    }
+
+    #[test]
+    fn on_draw_circle() {
+        assert_eq!(drawn(|c| c.draw_circle(at(2, 2), 2, colours::RED), (6, 6)), "\
+.###..
+#...#.
+#...#.
+#...#.
+.###..
+......
+");
+    }
+
+    #[test]
+    fn on_a_larger_draw_circle() {
+        assert_eq!(drawn(|c| c.draw_circle(at(4, 4), 4, colours::RED), (10, 10)), "\
+..#####...
+.#.....#..
+#.......#.
+#.......#.
+#.......#.
+#.......#.
+#.......#.
+.#.....#..
+..#####...
+..........
+");
+    }
+
+    #[test]
+    fn on_fill_ellipse() {
+        let radii = (unscaled::W(3), unscaled::H(1));
+        assert_eq!(drawn(|c| c.fill_ellipse(at(3, 1), radii, colours::RED), (8, 4)), "\
+.#####..
+#######.
+.#####..
+........
+");
+    }
+
+    #[test]
+    fn on_draw_ellipse() {
+        let radii = (unscaled::W(3), unscaled::H(1));
+        assert_eq!(drawn(|c| c.draw_ellipse(at(3, 1), radii, colours::RED), (8, 4)), "\
+.#####..
+#.....#.
+.#####..
+........
+");
+    }
+
+    #[test]
+    fn on_an_ellipse_wider_than_an_unscaled_width() {
+        // The middle row is 65537 pixels wide, so it would be wrapped to one
+        // pixel if its width was not trimmed first.
+        let radii = (unscaled::W(32768), unscaled::H(1));
+        let expected = "\
+...
+###
+...
+";
+        assert_eq!(drawn(|c| c.fill_ellipse(at(32768, 1), radii, colours::RED), (3, 3)), expected);
+        assert_eq!(drawn(|c| c.draw_ellipse(at(32768, 1), radii, colours::RED), (3, 3)), expected);
+    }
+
+    #[test]
+    fn on_the_largest_circles() {
+        // Only the visible rows are worked out, so these are quick to draw.
+        let radius = unscaled::Inner::MAX;
+        let center = at(world::Inner::from(radius), 1);
+        assert_eq!(drawn(|c| c.fill_circle(center, radius, colours::RED), (3, 3)), "\
+###
+###
+###
+");
+        assert_eq!(drawn(|c| c.draw_circle(center, radius, colours::RED), (3, 3)), "\
+#..
+#..
+#..
+");
+    }
+
+    #[test]
+    fn on_a_zero_radius_circle() {
+        assert_eq!(drawn(|c| {
+            c.fill_circle(at(0, 0), 0, colours::RED);
+            c.draw_circle(at(2, 0), 0, colours::RED);
+        }, (4, 2)), "\
+#.#.
+....
+");
+    }
+}
+
+#[cfg(test)]
+mod text_layout_works {
+    use super::*;
+    use test_helpers::rendered;
+    use text::{Align, Layout};
+
+    /// Lays out the text, and returns it as a grid of characters, one per
+    /// `CHAR_W` by `CHAR_H` cell, with `.` for empty cells.
+    fn grid(text: &str, layout: Layout) -> String {
+        let laid_out = text::lay_out(text, 0, layout, &text::BUILT_IN_FONT);
+
+        let (char_w, char_h) = (CHAR_W.get(), CHAR_H.get());
+        let columns = usize::from(laid_out.size.w.get() / char_w);
+        let rows = usize::from(laid_out.size.h.get() / char_h);
+
+        let mut cells = vec![vec!['.'; columns]; rows];
+        for glyph in laid_out.glyphs {
+            assert_eq!(glyph.offset.w.get() % char_w, 0);
+            assert_eq!(glyph.offset.h.get() % char_h, 0);
+            cells[usize::from(glyph.offset.h.get() / char_h)]
+                [usize::from(glyph.offset.w.get() / char_w)] = glyph.cell as char;
+        }
+
+        cells.into_iter()
+            .map(|row| row.into_iter().collect::<String>() + "\n")
+            .collect()
+    }
+
+    fn wrapped(columns: unscaled::Inner, align: Align) -> Layout {
+        Layout {
+            wrap_width: Some(unscaled::W(columns * CHAR_W.get())),
+            align,
+        }
+    }
+
+    #[test]
+    fn on_a_single_line() {
+        assert_eq!(grid("hi there", <_>::default()), "hi there\n");
+    }
+
+    #[test]
+    fn on_newlines() {
+        assert_eq!(grid("a\nbcd\n\ne", <_>::default()), "\
+a..
+bcd
+...
+e..
+");
+    }
+
+    #[test]
+    fn on_wrapping_between_words() {
+        assert_eq!(grid("the quick brown fox", wrapped(10, Align::Left)), "\
+the quick.
+brown fox.
+");
+    }
+
+    #[test]
+    fn on_a_word_exactly_as_wide_as_the_wrap_width() {
+        assert_eq!(grid("abc de", wrapped(3, Align::Left)), "\
+abc
+de.
+");
+    }
+
+    #[test]
+    fn on_a_word_too_wide_to_fit_on_a_line() {
+        assert_eq!(grid("a bcdefgh i", wrapped(3, Align::Left)), "\
+a..
+bcd
+efg
+h i
+");
+    }
+
+    #[test]
+    fn on_a_wrap_width_narrower_than_a_character() {
+        let laid_out = text::lay_out("ab", 0, wrapped(0, Align::Left), &text::BUILT_IN_FONT);
+
+        assert_eq!(
+            laid_out.glyphs.iter().map(|glyph| glyph.offset).collect::<Vec<_>>(),
+            vec![
+                unscaled::WH { w: unscaled::W(0), h: unscaled::H(0) },
+                unscaled::WH { w: unscaled::W(0), h: CHAR_H },
+            ]
+        );
+    }
+
+    #[test]
+    fn on_leading_spaces_being_kept_at_the_start_of_a_line() {
+        assert_eq!(grid("  ab\n cd", wrapped(4, Align::Left)), "  ab\n cd.\n");
+    }
+
+    #[test]
+    fn on_centre_alignment_within_the_wrap_width() {
+        assert_eq!(grid("ab cdef g", wrapped(6, Align::Centre)), "\
+..ab..
+cdef g
+");
+        assert_eq!(grid("ab cdef", wrapped(6, Align::Centre)), "\
+..ab..
+.cdef.
+");
+    }
+
+    #[test]
+    fn on_right_alignment_within_the_widest_line() {
+        assert_eq!(grid("a\nbcd\nef", Layout { align: Align::Right, ..<_>::default() }), "\
+..a
+bcd
+.ef
+");
+    }
+
+    #[test]
+    fn on_colour_escapes() {
+        let laid_out = text::lay_out("a^3b^^c\nd^re^9f^", 1, <_>::default(), &text::BUILT_IN_FONT);
+
+        assert_eq!(
+            laid_out.glyphs.iter()
+                .map(|glyph| (glyph.cell as char, glyph.colour))
+                .collect::<Vec<_>>(),
+            vec![
+                ('a', 1), ('b', 3), ('^', 3), ('c', 3),
+                ('d', 3), ('e', 1), ('^', 1), ('9', 1), ('f', 1), ('^', 1),
+            ]
+        );
+        assert_eq!(laid_out.size.w, unscaled::W(6 * CHAR_W.get()));
+    }
+
+    #[test]
+    fn on_characters_missing_from_the_glyph_map() {
+        assert_eq!(grid("aé\t", <_>::default()), "a??\n");
+    }
+
+    #[test]
+    fn on_measure() {
+        assert_eq!(
+            text::measure("the quick brown fox", wrapped(10, Align::Right), &text::BUILT_IN_FONT),
+            unscaled::WH { w: unscaled::W(10 * CHAR_W.get()), h: unscaled::H(2 * CHAR_H.get()) }
+        );
+        assert_eq!(
+            text::measure("^1ab\n^2c", <_>::default(), &text::BUILT_IN_FONT),
+            unscaled::WH { w: unscaled::W(2 * CHAR_W.get()), h: unscaled::H(2 * CHAR_H.get()) }
+        );
+    }
+
+    #[test]
+    fn on_print_str_drawing_what_print_char_does() {
+        let mut expected = Commands::default();
+        expected.print_char(b'a', unscaled::X(10), unscaled::Y(20), 2);
+        expected.print_char(b'b', unscaled::X(10 + CHAR_W.get()), unscaled::Y(20), 4);
+        expected.print_char(b'c', unscaled::X(10), unscaled::Y(20 + CHAR_H.get()), 4);
+
+        let mut actual = Commands::default();
+        actual.print_str(
+            "a^4b\nc",
+            unscaled::XY { x: unscaled::X(10), y: unscaled::Y(20) },
+            2,
+        );
+
+
+        assert_eq!(actual.commands.len(), 3);
+        assert!(rendered(&mut actual) == rendered(&mut expected));
+    }
+}
+
+#[cfg(test)]
+mod glyph_map_works {
+    use super::*;
+    use test_helpers::rendered;
+    use text::GlyphMap;
+
+    fn cells(glyph_map: &GlyphMap, text: &str) -> Vec<u8> {
+        text.chars().map(|c| glyph_map.get(c)).collect()
+    }
+
+    #[test]
+    fn on_the_built_in_font() {
+        assert_eq!(
+            cells(&<_>::default(), "A ~♣♦♥♠♧♢♡♤⑩é"),
+            vec![
+                b'A', b' ', b'~',
+                CLUB_CHAR, DIAMOND_CHAR, HEART_CHAR, SPADE_CHAR,
+                CLUB_CHAR, DIAMOND_CHAR, HEART_CHAR, SPADE_CHAR,
+                TEN_CHAR, b'?',
+            ]
+        );
+    }
+
+    #[test]
+    fn on_a_custom_map() {
+        let mut glyph_map = GlyphMap::new(0);
+        assert_eq!(glyph_map.insert('x', 5), None);
+        assert_eq!(glyph_map.insert('x', 6), Some(5));
+
+        assert_eq!(cells(&glyph_map, "xa"), vec![6, 0]);
+    }
+
+    #[test]
+    fn on_print_str_using_the_font_glyph_map() {
+        let xy = unscaled::XY { x: unscaled::X(3), y: unscaled::Y(4) };
+
+        let mut expected = Commands::default();
+        expected.print_char(HEART_CHAR, xy.x, xy.y, 2);
+
+        let mut actual = Commands::default();
+        let mut glyph_map = GlyphMap::new(b'?');
+        glyph_map.insert('h', HEART_CHAR);
+        let font = actual.add_font(text::Font {
+            glyph_map,
+            ..text::BUILT_IN_FONT.clone()
+        }).unwrap();
+        actual.set_font(font);
+        actual.print_str("h", xy, 2);
+
+        assert!(rendered(&mut actual) == rendered(&mut expected));
+
+        // The glyph map is part of the font, so it is kept by `clear`.
+        actual.clear();
+        actual.set_font(font);
+        actual.print_str("h", xy, 2);
+
+        assert!(rendered(&mut actual) == rendered(&mut expected));
+    }
+}
+
+#[cfg(test)]
+mod fonts_work {
+    use super::*;
+    use test_helpers::as_text;
+    use text::{Font, FontId, GlyphMap};
+
+    /// A font drawn from the white atlas `drawn` loads, so each glyph is a
+    /// filled `w` by `h` rect, with `a` to `z` as cells 0 to 25, and lines
+    /// `h + 1` apart.
+    fn solid_font(w: unscaled::Inner, h: unscaled::Inner, advances: Vec<unscaled::Inner>) -> Font {
+        let mut glyph_map = GlyphMap::new(0);
+        for (cell, c) in ('a'..='z').enumerate() {
+            glyph_map.insert(c, cell as u8);
+        }
+
+        Font {
+            advances,
+            line_height: unscaled::H(h + 1),
+            ..Font::monospace(
+                atlas::Id::from_source_index(0).unwrap(),
+                <_>::default(),
+                26,
+                unscaled::WH { w: unscaled::W(w), h: unscaled::H(h) },
+                glyph_map,
+            )
+        }
+    }
+
+    /// Returns the top left `w` by `h` pixels as text, with `#` for each pixel
+    /// that changed, and `.` for the rest. A screen sized white atlas is
+    /// loaded as the first atlas after the built-in one, for `solid_font`.
+    fn drawn(commands: &mut Commands, wh: (usize, usize)) -> String {
+        let mut frame_buffer = render::FrameBuffer::from_size((command::WIDTH, command::HEIGHT));
+        frame_buffer.load_atlas(
+            render::atlas::Atlas::from_pixels(
+                command::WIDTH.into(),
+                command::HEIGHT.into(),
+                vec![0xFFFF_FFFF; command::LENGTH],
+            ).unwrap()
+        ).unwrap();
+        let _ = render::render_graphics(&mut frame_buffer, commands.graphics());
+
+        as_text(&frame_buffer.buffer, wh)
+    }
+
+    #[test]
+    fn on_the_built_in_font_matching_get_char_xy() {
+        for cell in 0..=u8::MAX {
+            assert_eq!(text::BUILT_IN_FONT.cell_xy(cell), get_char_xy(cell), "{cell}");
+        }
+    }
+
+    #[test]
+    fn on_cell_xy_with_an_origin() {
+        let font = Font::monospace(
+            atlas::Id(1),
+            sprite::XY { x: sprite::X(5), y: sprite::Y(7) },
+            3,
+            unscaled::WH { w: unscaled::W(4), h: unscaled::H(6) },
+            <_>::default(),
+        );
+
+        assert_eq!(font.cell_xy(0), sprite::XY { x: sprite::X(5), y: sprite::Y(7) });
+        assert_eq!(font.cell_xy(2), sprite::XY { x: sprite::X(13), y: sprite::Y(7) });
+        assert_eq!(font.cell_xy(4), sprite::XY { x: sprite::X(9), y: sprite::Y(13) });
+    }
+
+    #[test]
+    fn on_variable_advances() {
+        let font = solid_font(2, 2, vec![1, 3, 2]);
+
+        let laid_out = text::lay_out("abc\nd", 0, <_>::default(), &font);
+
+        assert_eq!(
+            laid_out.glyphs.iter()
+                .map(|glyph| (glyph.cell, glyph.offset.w.get(), glyph.offset.h.get()))
+                .collect::<Vec<_>>(),
+            vec![(0, 0, 0), (1, 1, 0), (2, 4, 0), (3, 0, 3)]
+        );
+        assert_eq!(
+            laid_out.size,
+            unscaled::WH { w: unscaled::W(6), h: unscaled::H(6) }
+        );
+    }
+
+    #[test]
+    fn on_wrapping_with_variable_advances() {
+        let font = solid_font(1, 1, vec![1, 3]);
+        let layout = text::Layout {
+            wrap_width: Some(unscaled::W(6)),
+            ..<_>::default()
+        };
+
+        // "aa bb" would be 9 wide, and "bb a" would be 8 wide.
+        assert_eq!(
+            text::lay_out("aa bb a", 0, layout, &font).glyphs.iter()
+                .map(|glyph| (glyph.offset.w.get(), glyph.offset.h.get()))
+                .collect::<Vec<_>>(),
+            vec![(0, 0), (1, 0), (0, 2), (3, 2), (0, 4)]
+        );
+    }
+
+    #[test]
+    fn on_print_str_with_an_added_font() {
+        let mut commands = Commands::default();
+        let font = commands.add_font(solid_font(2, 1, vec![3])).unwrap();
+        assert_eq!(font, FontId(1));
+
+        assert_eq!(commands.set_font(font), FontId::BUILT_IN);
+        commands.print_str("ab\nb", <_>::default(), 6);
+
+        assert_eq!(drawn(&mut commands, (6, 4)), "\
+##.##.
+......
+##....
+......
+");
+        assert_eq!(
+            commands.measure_str("ab\nb", <_>::default()),
+            unscaled::WH { w: unscaled::W(5), h: unscaled::H(4) }
+        );
+    }
+
+    #[test]
+    fn on_a_line_height_of_zero_being_treated_as_one() {
+        let mut font = solid_font(1, 1, vec![]);
+        font.line_height = unscaled::H(0);
+
+        let laid_out = text::lay_out("a\nb", 0, <_>::default(), &font);
+
+        assert_eq!(
+            laid_out.glyphs.iter().map(|glyph| glyph.offset.h.get()).collect::<Vec<_>>(),
+            vec![0, 1]
+        );
+        assert_eq!(laid_out.size, unscaled::WH { w: unscaled::W(1), h: unscaled::H(2) });
+    }
+
+    #[test]
+    fn on_text_laid_out_past_the_largest_offset() {
+        let mut font = solid_font(1, 1, vec![40_000]);
+        font.line_height = unscaled::H(40_000);
+
+        let laid_out = text::lay_out("aaa\na\na", 0, <_>::default(), &font);
+
+        assert_eq!(
+            laid_out.glyphs.iter()
+                .map(|glyph| (glyph.offset.w.get(), glyph.offset.h.get()))
+                .collect::<Vec<_>>(),
+            vec![(0, 0), (40_000, 0), (u16::MAX, 0), (0, 40_000), (0, u16::MAX)]
+        );
+        assert_eq!(laid_out.size, unscaled::WH { w: unscaled::W(u16::MAX), h: unscaled::H(u16::MAX) });
+    }
+
+    #[test]
+    fn on_printing_text_that_goes_past_the_largest_offset() {
+        let mut commands = Commands::default();
+        let font = commands.add_font(solid_font(1, 1, vec![40_000])).unwrap();
+        commands.set_font(font);
+
+        commands.print_str(
+            "aaa",
+            unscaled::XY { x: unscaled::X(u16::MAX - 1), y: unscaled::Y(0) },
+            6,
+        );
+        commands.print_str("aaa", <_>::default(), 6);
+
+        // Only the first glyph of the second string starts on screen.
+        assert_eq!(commands.slice().len(), 1);
+    }
+
+    #[test]
+    fn on_clear_resetting_to_the_built_in_font() {
+        let mut commands = Commands::default();
+        let font = commands.add_font(solid_font(2, 1, vec![])).unwrap();
+        commands.set_font(font);
+
+        commands.clear();
+
+        assert_eq!(commands.set_font(font), FontId::BUILT_IN);
+        assert_eq!(commands.font(font), &solid_font(2, 1, vec![]));
+    }
+
+    #[test]
+    fn on_an_unknown_font_falling_back_to_the_built_in_one() {
+        let commands = Commands::default();
+
+        assert_eq!(commands.font(FontId(3)), &*text::BUILT_IN_FONT);
+    }
+}
+
+#[cfg(test)]
+mod nine_slice_works {
+    use super::*;
+    use nine_slice::{Fill, Insets, NineSlice, Part, Segment, parts, segments};
+    use test_helpers::pieces;
+
+
+    fn part(src: unscaled::Inner, src_len: unscaled::Inner, dst: unscaled::Inner, dst_len: unscaled::Inner) -> Part {
+        Part { src, src_len, dst, dst_len }
+    }
+
+    fn segment(
+        src: unscaled::Inner,
+        src_len: unscaled::Inner,
+        dst: unscaled::Inner,
+        len: unscaled::Inner,
+    ) -> Segment {
+        Segment { src, src_len, dst, len }
+    }
+
+    #[test]
+    fn on_stretching_a_segment() {
+        assert_eq!(
+            segments(part(2, 2, 1, 5)),
+            vec![segment(2, 1, 1, 3), segment(3, 1, 4, 2)]
+        );
+    }
+
+    #[test]
+    fn on_stretching_a_segment_a_little() {
+        assert_eq!(
+            segments(part(0, 3, 0, 4)),
+            vec![segment(0, 1, 0, 2), segment(1, 2, 2, 2)]
+        );
+    }
+
+    #[test]
+    fn on_shrinking_a_segment() {
+        assert_eq!(
+            segments(part(0, 4, 0, 2)),
+            vec![segment(0, 1, 0, 1), segment(2, 1, 1, 1)]
+        );
+    }
+
+    #[test]
+    fn on_empty_segments() {
+        assert_eq!(segments(part(0, 0, 0, 5)), vec![]);
+        assert_eq!(segments(part(0, 3, 0, 0)), vec![]);
+    }
+
+    #[test]
+    fn on_parts_with_room_for_the_corners() {
+        assert_eq!(
+            parts(5, (1, 2), 9),
+            [part(0, 1, 0, 1), part(1, 2, 1, 6), part(3, 2, 7, 2)]
+        );
+    }
+
+    #[test]
+    fn on_parts_without_room_for_the_corners() {
+        assert_eq!(
+            parts(8, (2, 4), 3),
+            [part(0, 1, 0, 1), part(2, 2, 1, 0), part(6, 2, 1, 2)]
+        );
+    }
+
+    #[test]
+    fn on_drawing_a_tiled_panel() {
+        let mut commands = Commands::default();
+        commands.draw_nine_slice(
+            &NineSlice {
+                xy: sprite::XY { x: sprite::X(10), y: sprite::Y(20) },
+                size: unscaled::WH { w: unscaled::W(3), h: unscaled::H(3) },
+                insets: Insets::all(1),
+                edges: Fill::Tile,
+                centre: Fill::Tile,
+            },
+            unscaled::Rect {
+                x: unscaled::X(100),
+                y: unscaled::Y(50),
+                w: unscaled::W(4),
+                h: unscaled::H(3),
+            },
+        );
+
+        assert_eq!(
+            pieces(&mut commands),
+            vec![
+                (10, 20, 100, 50, 1, 1, 1, 1),
+                (11, 20, 101, 50, 2, 1, 1, 1),
+                (12, 20, 103, 50, 1, 1, 1, 1),
+                (10, 21, 100, 51, 1, 1, 1, 1),
+                (11, 21, 101, 51, 2, 1, 1, 1),
+                (12, 21, 103, 51, 1, 1, 1, 1),
+                (10, 22, 100, 52, 1, 1, 1, 1),
+                (11, 22, 101, 52, 2, 1, 1, 1),
+                (12, 22, 103, 52, 1, 1, 1, 1),
+            ]
+        );
+    }
+
+    #[test]
+    fn on_stretching_the_centre_and_tiling_the_edges() {
+        let mut commands = Commands::default();
+        commands.draw_nine_slice(
+            &NineSlice {
+                xy: <_>::default(),
+                size: unscaled::WH { w: unscaled::W(4), h: unscaled::H(4) },
+                insets: Insets::all(1),
+                edges: Fill::Tile,
+                centre: Fill::Stretch,
+            },
+            unscaled::Rect {
+                x: unscaled::X(0),
+                y: unscaled::Y(0),
+                w: unscaled::W(6),
+                h: unscaled::H(4),
+            },
+        );
+
+        let pieces = pieces(&mut commands);
+
+        // The top edge is the 2 texel slice, tiled twice.
+        assert_eq!(pieces[1], (1, 0, 1, 0, 4, 1, 2, 1));
+        // The centre is stretched to twice its width, but not its height, so
+        // each of its columns is a one texel wide tile, repeated twice.
+        assert_eq!(
+            &pieces[4..6],
+            &[(1, 1, 1, 1, 2, 2, 1, 2), (2, 1, 3, 1, 2, 2, 1, 2)]
+        );
+        assert_eq!(pieces.len(), 3 + 4 + 3);
+    }
+
+    #[test]
+    fn on_stretching_over_a_large_rect_with_one_command_per_slice() {
+        let mut commands = Commands::default();
+        commands.draw_nine_slice(
+            &NineSlice {
+                xy: <_>::default(),
+                size: unscaled::WH { w: unscaled::W(3), h: unscaled::H(3) },
+                insets: Insets::all(1),
+                edges: Fill::Stretch,
+                centre: Fill::Stretch,
+            },
+            unscaled::Rect {
+                x: unscaled::X(0),
+                y: unscaled::Y(0),
+                w: unscaled::W(200),
+                h: unscaled::H(150),
+            },
+        );
+
+        assert_eq!(commands.slice().len(), 9);
+    }
+
+    #[test]
+    fn on_stretching_to_the_same_pixels_as_picking_the_nearest_texel() {
+        // A checkered part of the atlas, so any misplaced texel shows.
+        let xy = sprite::XY { x: sprite::X(28), y: sprite::Y(3) };
+        let (src_w, src_h) = (5, 4);
+        let (dst_w, dst_h) = (13, 7);
+
+        let mut expected = Commands::default();
+        for y in 0..dst_h {
+            for x in 0..dst_w {
+                expected.sspr(
+                    sprite::XY {
+                        x: xy.x + unscaled::W(x * src_w / dst_w),
+                        y: xy.y + unscaled::H(y * src_h / dst_h),
+                    },
+                    Rect::from_unscaled(unscaled::Rect {
+                        x: unscaled::X(x),
+                        y: unscaled::Y(y),
+                        w: unscaled::W(1),
+                        h: unscaled::H(1),
+                    }),
+                );
+            }
+        }
+
+        let mut actual = Commands::default();
+        actual.draw_nine_slice(
+            &NineSlice {
+                xy,
+                size: unscaled::WH { w: unscaled::W(src_w), h: unscaled::H(src_h) },
+                insets: Insets::all(0),
+                edges: Fill::Stretch,
+                centre: Fill::Stretch,
+            },
+            unscaled::Rect {
+                x: unscaled::X(0),
+                y: unscaled::Y(0),
+                w: unscaled::W(dst_w),
+                h: unscaled::H(dst_h),
+            },
+        );
+
+        assert_eq!(
+            test_helpers::rendered(&mut expected),
+            test_helpers::rendered(&mut actual),
+        );
+    }
+}
+
+#[cfg(test)]
+mod sspr_tiled_works {
+    use super::*;
+
+    fn tiled_at(x: world::Inner, y: world::Inner) -> Commands {
+        let mut commands = Commands::default();
+        commands.sspr_tiled(
+            sprite::XY { x: sprite::X(8), y: sprite::Y(16) },
+            unscaled::WH { w: unscaled::W(2), h: unscaled::H(3) },
+            world::Rect { x: world::X(x), y: world::Y(y), w: unscaled::W(10), h: unscaled::H(10) },
+            <_>::default(),
+        );
+        commands
+    }
+
+    #[test]
+    fn on_a_rect_on_screen() {
+        let mut commands = tiled_at(4, 5);
+
+        let command = commands.slice()[0];
+        assert_eq!(command.sprite_xy, sprite::XY { x: sprite::X(8), y: sprite::Y(16) });
+        assert_eq!(command.tile, command::Tile { w: 2, h: 3, x_offset: 0, y_offset: 0 });
+    }
+
+    #[test]
+    fn on_a_rect_partly_off_the_top_left() {
+        let mut commands = tiled_at(-3, -7);
+
+        // The pattern stays put, rather than `sprite_xy` moving.
+        let command = commands.slice()[0];
+        assert_eq!(command.sprite_xy, sprite::XY { x: sprite::X(8), y: sprite::Y(16) });
+        assert_eq!(command.tile, command::Tile { w: 2, h: 3, x_offset: 1, y_offset: 1 });
+    }
+
+    #[test]
+    fn on_a_zero_sized_tile() {
+        let mut commands = Commands::default();
+        commands.sspr_tiled(
+            <_>::default(),
+            unscaled::WH { w: unscaled::W(0), h: unscaled::H(3) },
+            world::Rect { x: world::X(0), y: world::Y(0), w: unscaled::W(10), h: unscaled::H(10) },
+            <_>::default(),
+        );
+
+        assert!(commands.slice().is_empty());
+    }
+}
+
+#[cfg(test)]
+mod tilemap_works {
+    use super::*;
+    use animation::Mode;
+    use tilemap::Tilemap;
+
+    const TILE_SIZE: unscaled::WH = unscaled::WH { w: unscaled::W(8), h: unscaled::H(8) };
+
+    /// Returns the atlas texel and the screen position of each command, as
+    /// `(sprite_x, sprite_y, x, y)`. Every tile is the same size.
+    fn positions(commands: &mut Commands) -> Vec<(sprite::Inner, sprite::Inner, u16, u16)> {
+        test_helpers::pieces(commands).into_iter()
+            .map(|(sprite_x, sprite_y, x, y, ..)| (sprite_x, sprite_y, x, y))
+            .collect()
+    }
+
+    fn full(columns: usize, rows: usize) -> Tilemap {
+        let mut tilemap = Tilemap::new(columns, rows, TILE_SIZE, 4);
+        for row in 0..rows {
+            for column in 0..columns {
+                tilemap.set(column, row, Some(0));
+            }
+        }
+        tilemap
+    }
+
+    #[test]
+    fn on_this_small_map() {
+        let mut tilemap = Tilemap::new(3, 2, TILE_SIZE, 4);
+        tilemap.sheet_origin = sprite::XY { x: sprite::X(1), y: sprite::Y(2) };
+        tilemap.set(0, 0, Some(0));
+        tilemap.set(2, 0, Some(5));
+        tilemap.set(1, 1, Some(2));
+
+        let mut commands = Commands::default();
+        commands.draw_tilemap(&tilemap, 0);
+
+        assert_eq!(
+            positions(&mut commands),
+            vec![(1, 2, 0, 0), (9, 10, 16, 0), (17, 2, 8, 8)]
+        );
+    }
+
+    #[test]
+    fn on_get_and_set_outside_the_map() {
+        let mut tilemap = full(3, 2);
+        tilemap.set(3, 0, Some(1));
+        tilemap.set(0, 2, Some(1));
+
+        assert_eq!(tilemap.get(0, 1), Some(0));
+        assert_eq!(tilemap.get(3, 0), None);
+        assert_eq!(tilemap.get(0, 2), None);
+        assert_eq!(tilemap.get(2, 1), Some(0));
+    }
+
+    #[test]
+    fn by_only_drawing_the_visible_tiles_of_a_large_map() {
+        let mut tilemap = full(1000, 1000);
+        tilemap.scroll = world::XY { x: world::X(4), y: world::Y(-3) };
+
+        let mut commands = Commands::default();
+        commands.draw_tilemap(&tilemap, 0);
+
+        // The visible part of the map is x 4 to `WIDTH + 3` and y -3 to
+        // `HEIGHT - 4`, so at 480 by 320, columns 0 to 60, and rows 0 to 39.
+        let columns = usize::from((command::WIDTH + 3) / 8 + 1);
+        let rows = usize::from((command::HEIGHT - 4) / 8 + 1);
+        assert_eq!(commands.slice().len(), columns * rows);
+        // The first column is cut off on the left, and the map is moved down.
+        assert_eq!(&positions(&mut commands)[..2], &[(4, 0, 0, 3), (0, 0, 4, 3)]);
+    }
+
+    #[test]
+    fn by_only_drawing_the_tiles_in_the_clip_rect_with_the_camera() {
+        let tilemap = full(1000, 1000);
+
+        let mut commands = Commands::default();
+        commands.push_camera(world::XY { x: world::X(16), y: world::Y(0) });
+        commands.push_clip_rect(unscaled::Rect {
+            x: unscaled::X(10),
+            y: unscaled::Y(10),
+            w: unscaled::W(8),
+            h: unscaled::H(8),
+        });
+        commands.draw_tilemap(&tilemap, 0);
+
+        // x 26 to 33 and y 10 to 17 of the map are visible.
+        assert_eq!(
+            positions(&mut commands),
+            vec![(2, 2, 10, 10), (0, 2, 16, 10), (2, 0, 10, 16), (0, 0, 16, 16)]
+        );
+    }
+
+    #[test]
+    fn on_animated_tiles() {
+        let mut tilemap = Tilemap::new(2, 1, TILE_SIZE, 4);
+        tilemap.set(0, 0, Some(1));
+        tilemap.set(1, 0, Some(3));
+        tilemap.set_animation(1, tilemap.animation(&[4, 5, 6], 10));
+
+        assert_eq!(tilemap.sprite_xy_at(1, 0), tilemap.sprite_xy(4));
+        assert_eq!(tilemap.sprite_xy_at(1, 25), tilemap.sprite_xy(6));
+        assert_eq!(tilemap.sprite_xy_at(1, 31), tilemap.sprite_xy(4));
+        assert_eq!(tilemap.sprite_xy_at(3, 25), tilemap.sprite_xy(3));
+
+        let mut commands = Commands::default();
+        commands.draw_tilemap(&tilemap, 15);
+
+        assert_eq!(positions(&mut commands), vec![(8, 8, 0, 0), (24, 0, 8, 0)]);
+    }
+
+    #[test]
+    fn on_an_animation_without_frames() {
+        let mut tilemap = Tilemap::new(1, 1, TILE_SIZE, 4);
+        tilemap.set_animation(2, tilemap.animation(&[], 1));
+
+        assert_eq!(tilemap.sprite_xy_at(2, 7), tilemap.sprite_xy(2));
+    }
+
+    #[test]
+    fn on_an_animated_tile_that_ping_pongs() {
+        let mut tilemap = Tilemap::new(1, 1, TILE_SIZE, 4);
+        tilemap.set_animation(0, animation::Animation {
+            mode: Mode::PingPong,
+            ..tilemap.animation(&[4, 5, 6], 1)
+        });
+
+        let tiles: Vec<sprite::XY> = (0..5).map(|tick| tilemap.sprite_xy_at(0, tick)).collect();
+
+        assert_eq!(tiles, [4, 5, 6, 5, 4].map(|tile| tilemap.sprite_xy(tile)));
+    }
+}
+
+#[cfg(test)]
+mod animation_works {
+    use super::*;
+    use animation::{Animation, Frame, Mode, Player};
+
+    /// An animation with a frame for each duration, with frame `i` at
+    /// `(i * 10, 0)` on the atlas.
+    fn animation(mode: Mode, durations: &[u32]) -> Animation {
+        Animation {
+            frames: durations.iter()
+                .enumerate()
+                .map(|(i, &ticks)| Frame {
+                    sprite_xy: sprite::XY { x: sprite::X(i as sprite::Inner * 10), y: sprite::Y(0) },
+                    ticks,
+                })
+                .collect(),
+            size: unscaled::WH { w: unscaled::W(10), h: unscaled::H(12) },
+            mode,
+        }
+    }
+
+    /// Returns the frame index at each of the first `count` ticks.
+    fn indexes(animation: &Animation, count: u32) -> Vec<Option<usize>> {
+        (0..count).map(|tick| animation.frame_index_at(tick)).collect()
+    }
+
+    #[test]
+    fn on_loop() {
+        assert_eq!(
+            indexes(&animation(Mode::Loop, &[2, 1, 3]), 8),
+            [0, 0, 1, 2, 2, 2, 0, 0].map(Some)
+        );
+    }
+
+    #[test]
+    fn on_ping_pong() {
+        assert_eq!(
+            indexes(&animation(Mode::PingPong, &[1, 2, 1, 1]), 11),
+            [0, 1, 1, 2, 3, 2, 1, 1, 0, 1, 1].map(Some)
+        );
+    }
+
+    #[test]
+    fn on_ping_pong_with_two_frames() {
+        assert_eq!(
+            indexes(&animation(Mode::PingPong, &[1, 1]), 4),
+            [0, 1, 0, 1].map(Some)
+        );
+    }
+
+    #[test]
+    fn on_once() {
+        assert_eq!(
+            indexes(&animation(Mode::Once, &[1, 2]), 5),
+            [0, 1, 1, 1, 1].map(Some)
+        );
+    }
+
+    #[test]
+    fn on_zero_tick_frames_and_no_frames() {
+        assert_eq!(
+            indexes(&animation(Mode::Loop, &[0, 0]), 3),
+            [0, 1, 0].map(Some)
+        );
+        assert_eq!(indexes(&animation(Mode::Loop, &[]), 2), [None, None]);
+        assert_eq!(indexes(&animation(Mode::PingPong, &[]), 1), [None]);
+    }
+
+    #[test]
+    fn on_a_player_advancing_until_it_is_finished() {
+        let mut player = Player::new(animation(Mode::Once, &[1, 2]));
+
+        let mut seen = Vec::new();
+        while !player.is_finished() {
+            seen.push(player.sprite_xy().unwrap().x.0);
+            player.advance();
+        }
+
+        assert_eq!(seen, [0, 10, 10]);
+        assert_eq!(player.tick(), 3);
+
+        player.restart();
+        assert_eq!(player.frame_index(), Some(0));
+        assert!(!player.is_finished());
+    }
+
+    #[test]
+    fn on_a_looping_player_never_finishing() {
+        let mut player = Player::new(animation(Mode::Loop, &[1]));
+        player.advance_by(u32::MAX);
+        player.advance();
+
+        assert_eq!(player.tick(), u32::MAX);
+        assert!(!player.is_finished());
+    }
+
+    #[test]
+    fn on_draw_animation_drawing_the_current_frame() {
+        let mut player = Player::new(animation(Mode::Loop, &[1, 1, 1]));
+        player.advance_by(2);
+        let xy = world::XY { x: world::X(30), y: world::Y(40) };
+
+        let mut expected = Commands::default();
+        expected.sspr_world(
+            sprite::XY { x: sprite::X(20), y: sprite::Y(0) },
+            world::Rect { x: xy.x, y: xy.y, w: unscaled::W(10), h: unscaled::H(12) },
+            Orientation::FLIP_X,
+        );
+
+        let mut actual = Commands::default();
+        actual.draw_animation(&player, xy, Orientation::FLIP_X);
+
+        assert_eq!(actual.slice().len(), 1);
+        let (actual, expected) = (actual.slice()[0], expected.slice()[0]);
+        assert_eq!(actual.sprite_xy, expected.sprite_xy);
+        assert_eq!(actual.rect, expected.rect);
+        assert_eq!(actual.orientation, expected.orientation);
+    }
+
+    #[test]
+    fn on_draw_animation_with_no_frames() {
+        let player = Player::new(animation(Mode::Loop, &[]));
+
+        let mut commands = Commands::default();
+        commands.draw_animation(&player, <_>::default(), <_>::default());
+
+        assert!(commands.slice().is_empty());
+    }
+}
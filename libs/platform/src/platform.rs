@@ -6,18 +6,21 @@ use platform_types::{
 use softbuffer::GraphicsContext;
 
 use winit::{
-    event::{Event, WindowEvent},
+    event::{Event, VirtualKeyCode, WindowEvent},
     event_loop::{EventLoop, ControlFlow},
-    window::WindowBuilder,
+    window::{Window, WindowBuilder},
 };
 
-use render::{clip, FrameBuffer, NeedsRedraw};
+use render::{clip, post, FrameBuffer, NeedsRedraw, ScaleMode};
 
-pub fn run<S: State + 'static>(mut state: S) {
+/// Runs the state until the window is closed. `app_name` is used as the
+/// window title, and to keep this app's settings apart from other apps'.
+/// Passing `env!("CARGO_PKG_NAME")` is usually what you want.
+pub fn run<S: State + 'static>(mut state: S, app_name: &'static str) {
     let event_loop = EventLoop::new();
 
     let builder = WindowBuilder::new()
-        .with_title("demos");
+        .with_title(app_name);
 
     #[cfg(target_arch = "wasm32")]
     let builder = wasm::set_canvas(builder);
@@ -36,6 +39,21 @@ pub fn run<S: State + 'static>(mut state: S) {
         FrameBuffer::from_size((size.width as clip::W, size.height as clip::H))
     };
 
+    load_atlases(&state, &mut output_frame_buffer);
+
+    #[cfg(not(target_arch = "wasm32"))]
+    if let Ok(threads) = std::thread::available_parallelism() {
+        output_frame_buffer.upscaler = render::Upscaler::Threaded(threads);
+    }
+
+    if let Some(scale_mode) = settings::load(app_name, settings::SCALE_MODE)
+        .as_deref()
+        .and_then(ScaleMode::from_name) {
+        output_frame_buffer.set_scale_mode(scale_mode);
+    }
+
+    let filter_keys = add_filters(&mut output_frame_buffer);
+
     let mut graphics_context = unsafe { GraphicsContext::new(window) }.unwrap();
 
     let mut sound_handler = init_sound_handler();
@@ -45,6 +63,8 @@ pub fn run<S: State + 'static>(mut state: S) {
             .build_with_target_rate(60.0);
 
     let mut just_gained_focus = true;
+    // The size the buffer was last presented whole at.
+    let mut presented_size = (0, 0);
 
     event_loop.run(move |event, _, control_flow| {
         let window = graphics_context.window();
@@ -68,6 +88,21 @@ pub fn run<S: State + 'static>(mut state: S) {
                 use winit::event::{ElementState, VirtualKeyCode as VK};
                 use platform_types::Button;
 
+                if keycode == VK::F2 {
+                    if element_state == ElementState::Pressed {
+                        cycle_scale_mode(&mut output_frame_buffer, app_name);
+                    }
+                    return
+                }
+
+                if let Some(&(_, id)) = filter_keys.iter().find(|&&(key, _)| key == keycode) {
+                    if element_state == ElementState::Pressed {
+                        let enabled = !output_frame_buffer.filter_enabled(id);
+                        output_frame_buffer.set_filter_enabled(id, enabled);
+                    }
+                    return
+                }
+
                 let button = match keycode {
                     VK::Return => Button::START,
                     VK::RShift => Button::SELECT,
@@ -98,7 +133,7 @@ pub fn run<S: State + 'static>(mut state: S) {
                 just_gained_focus = true;
             }
             Event::MainEventsCleared => {
-                let (commands, sounds) = state.frame();
+                let (graphics, sounds) = state.frame();
 
                 handle_sounds(&mut sound_handler, sounds);
 
@@ -108,18 +143,24 @@ pub fn run<S: State + 'static>(mut state: S) {
                     output_frame_buffer.height = size.height as u16;
                 }
 
-                let needs_redraw = render::render(
+                let needs_redraw = render::render_graphics(
                     &mut output_frame_buffer,
-                    commands,
+                    graphics,
                 );
 
-                if NeedsRedraw::Yes == needs_redraw
-                || just_gained_focus {
+                // Presenting the whole buffer also resizes what it is presented
+                // onto, so only the changed parts can be presented if the size
+                // is the same.
+                let size = (output_frame_buffer.width, output_frame_buffer.height);
+                if just_gained_focus || size != presented_size {
                     graphics_context.set_buffer(
                         &output_frame_buffer.buffer,
                         output_frame_buffer.width,
                         output_frame_buffer.height,
                     );
+                    presented_size = size;
+                } else if let NeedsRedraw::Yes(rects) = needs_redraw {
+                    present_rects(&mut graphics_context, &output_frame_buffer, &rects);
                 }
 
                 just_gained_focus = false;
@@ -135,15 +176,211 @@ pub fn run<S: State + 'static>(mut state: S) {
     });
 }
 
+/// Presents the given parts of the buffer, which must be the same size as the
+/// last time it was presented whole.
+#[cfg(not(target_arch = "wasm32"))]
+fn present_rects(
+    graphics_context: &mut GraphicsContext<Window>,
+    frame_buffer: &FrameBuffer,
+    _: &[clip::Rect],
+) {
+    // As of this writing, `softbuffer` has no way to present only part of the
+    // buffer on desktop, so we present the whole thing.
+    graphics_context.set_buffer(
+        &frame_buffer.buffer,
+        frame_buffer.width,
+        frame_buffer.height,
+    );
+}
+
+/// Presents the given parts of the buffer, which must be the same size as the
+/// last time it was presented whole.
+#[cfg(target_arch = "wasm32")]
+fn present_rects(
+    graphics_context: &mut GraphicsContext<Window>,
+    frame_buffer: &FrameBuffer,
+    rects: &[clip::Rect],
+) {
+    if let Err(e) = wasm::put_rects(frame_buffer, rects) {
+        error_logger(&format!("Could not present part of the buffer: {e:?}"));
+
+        graphics_context.set_buffer(
+            &frame_buffer.buffer,
+            frame_buffer.width,
+            frame_buffer.height,
+        );
+    }
+}
+
+/// Switches to the next scale mode, and saves it for next time.
+fn cycle_scale_mode(frame_buffer: &mut FrameBuffer, app_name: &str) {
+    let scale_mode = frame_buffer.scale_mode().next();
+
+    frame_buffer.set_scale_mode(scale_mode);
+
+    if let Err(e) = settings::save(app_name, settings::SCALE_MODE, scale_mode.name()) {
+        error_logger(&format!("Could not save the scale mode: {e}"));
+    }
+}
+
+/// Adds the built in filters to the frame buffer, turned off, and returns the
+/// keys that turn each of them on and off.
+fn add_filters(frame_buffer: &mut FrameBuffer) -> [(VirtualKeyCode, post::Id); 5] {
+    [
+        (VirtualKeyCode::F3, Box::new(post::Scanlines::default()) as Box<dyn post::Filter>),
+        (VirtualKeyCode::F4, Box::new(post::ShadowMask::default())),
+        (VirtualKeyCode::F5, Box::new(post::Curvature::default())),
+        (VirtualKeyCode::F6, Box::new(post::Vignette::default())),
+        (VirtualKeyCode::F7, Box::new(post::Quantise)),
+    ].map(|(key, filter)| (key, frame_buffer.add_filter(filter, false)))
+}
+
+/// Loads the state's atlas sources into the frame buffer, in order. Sources
+/// that fail to load are reported, and replaced with an empty atlas, so that
+/// the ids of the later ones still line up.
+fn load_atlases<S: State>(state: &S, frame_buffer: &mut FrameBuffer) {
+    use render::atlas::{Atlas, Source};
+
+    for (i, &source) in state.atlas_sources().iter().enumerate() {
+        let loaded = match source {
+            Source::Png(bytes) => Atlas::from_png(bytes).map_err(|e| e.to_string()),
+            Source::PngFile(path) => read_png_file(path),
+        };
+
+        let atlas = loaded.unwrap_or_else(|e| {
+            error_logger(&format!("Could not load atlas source {i} ({source:?}): {e}"));
+            Atlas::default()
+        });
+
+        if frame_buffer.load_atlas(atlas).is_none() {
+            error_logger(&format!("Too many atlas sources. Skipping source {i} onwards."));
+            break
+        }
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn read_png_file(path: &str) -> Result<render::atlas::Atlas, String> {
+    let bytes = std::fs::read(path).map_err(|e| e.to_string())?;
+
+    render::atlas::Atlas::from_png(&bytes).map_err(|e| e.to_string())
+}
+
+#[cfg(target_arch = "wasm32")]
+fn read_png_file(_: &str) -> Result<render::atlas::Atlas, String> {
+    Err("Files cannot be read on the web. Use `Source::Png` instead.".to_string())
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn error_logger(s: &str) {
+    eprintln!("{}", s);
+}
+
+#[cfg(target_arch = "wasm32")]
+fn error_logger(s: &str) {
+    web_sys::console::error_1(&s.into());
+}
+
+/// Values that are kept between runs, as strings under string keys. Each app
+/// gets its own set, by the app name passed to `run`.
+mod settings {
+    pub const SCALE_MODE: &str = "scale_mode";
+
+    #[cfg(not(target_arch = "wasm32"))]
+    pub use self::file::{load, save};
+
+    #[cfg(target_arch = "wasm32")]
+    pub use self::local_storage::{load, save};
+
+    /// Settings are kept in a file with one `key=value` pair per line, in the
+    /// usual config directory for the OS, under a folder named after the app.
+    #[cfg(not(target_arch = "wasm32"))]
+    mod file {
+        use std::path::PathBuf;
+
+        fn path(app_name: &str) -> Option<PathBuf> {
+            let var = |name| std::env::var_os(name).filter(|s| !s.is_empty());
+
+            let config_dir = if cfg!(windows) {
+                var("APPDATA").map(PathBuf::from)
+            } else if cfg!(target_os = "macos") {
+                var("HOME").map(|home| PathBuf::from(home).join("Library/Application Support"))
+            } else {
+                var("XDG_CONFIG_HOME").map(PathBuf::from)
+                    .or_else(|| var("HOME").map(|home| PathBuf::from(home).join(".config")))
+            };
+
+            config_dir.map(|dir| dir.join(app_name).join("settings.txt"))
+        }
+
+        fn read(app_name: &str) -> String {
+            path(app_name)
+                .and_then(|path| std::fs::read_to_string(path).ok())
+                .unwrap_or_default()
+        }
+
+        pub fn load(app_name: &str, key: &str) -> Option<String> {
+            read(app_name).lines()
+                .filter_map(|line| line.split_once('='))
+                .find(|&(k, _)| k == key)
+                .map(|(_, value)| value.to_string())
+        }
+
+        pub fn save(app_name: &str, key: &str, value: &str) -> Result<(), String> {
+            let path = path(app_name).ok_or("Could not find a config directory")?;
+
+            let mut output: String = read(app_name).lines()
+                .filter(|line| line.split_once('=').map(|(k, _)| k) != Some(key))
+                .flat_map(|line| [line, "\n"])
+                .collect();
+            output.push_str(key);
+            output.push('=');
+            output.push_str(value);
+            output.push('\n');
+
+            if let Some(dir) = path.parent() {
+                std::fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+            }
+
+            std::fs::write(path, output).map_err(|e| e.to_string())
+        }
+    }
+
+    /// Other apps on the same origin share local storage, so keys are
+    /// prefixed with the app name.
+    #[cfg(target_arch = "wasm32")]
+    mod local_storage {
+        fn storage() -> Option<web_sys::Storage> {
+            web_sys::window()?.local_storage().ok()?
+        }
+
+        fn storage_key(app_name: &str, key: &str) -> String {
+            format!("{app_name}.{key}")
+        }
+
+        pub fn load(app_name: &str, key: &str) -> Option<String> {
+            storage()?.get_item(&storage_key(app_name, key)).ok()?
+        }
+
+        pub fn save(app_name: &str, key: &str, value: &str) -> Result<(), String> {
+            storage()
+                .ok_or("Local storage is not available")?
+                .set_item(&storage_key(app_name, key), value)
+                .map_err(|e| format!("{e:?}"))
+        }
+    }
+}
+
 #[cfg(target_arch = "wasm32")]
 mod wasm {
     use winit::{
         window::WindowBuilder,
         platform::web::WindowBuilderExtWebSys,
     };
-    use wasm_bindgen::JsCast;
-    use web_sys::HtmlCanvasElement;
+    use wasm_bindgen::{Clamped, JsCast, JsValue};
+    use web_sys::{CanvasRenderingContext2d, HtmlCanvasElement, ImageData};
     use platform_types::{SFX, unscaled};
+    use render::{clip, FrameBuffer};
 
     pub fn set_canvas(builder: WindowBuilder) -> WindowBuilder {
         let canvas = get_canvas();
@@ -170,6 +407,39 @@ mod wasm {
         style.remove_property("height").unwrap();
     }
 
+    /// Copies the given parts of the buffer onto the canvas, leaving the rest
+    /// of it as it was. Unlike `GraphicsContext::set_buffer`, this does not
+    /// resize the canvas.
+    pub fn put_rects(frame_buffer: &FrameBuffer, rects: &[clip::Rect]) -> Result<(), JsValue> {
+        let context: CanvasRenderingContext2d = get_canvas()
+            .get_context("2d")?
+            .ok_or_else(|| JsValue::from_str("The canvas has no 2d context"))?
+            .dyn_into()?;
+
+        let width = usize::from(frame_buffer.width);
+        let mut bitmap = Vec::new();
+        for rect in rects {
+            bitmap.clear();
+            for y in rect.y.clone() {
+                let row_start = usize::from(y) * width;
+                for &pixel in &frame_buffer.buffer[
+                    row_start + usize::from(rect.x.start)..row_start + usize::from(rect.x.end)
+                ] {
+                    bitmap.extend([(pixel >> 16) as u8, (pixel >> 8) as u8, pixel as u8, 255]);
+                }
+            }
+
+            let image_data = ImageData::new_with_u8_clamped_array(
+                Clamped(&bitmap),
+                rect.width().into(),
+            )?;
+
+            context.put_image_data(&image_data, rect.x.start.into(), rect.y.start.into())?;
+        }
+
+        Ok(())
+    }
+
     fn get_canvas() -> HtmlCanvasElement {
         let window = web_sys::window().unwrap();
         let document = window.document().unwrap();
@@ -226,10 +496,6 @@ pub fn get_state_params() -> StateParams {
         console::log_1(&s.into());
     }
 
-    fn error_logger(s: &str) {
-        console::error_1(&s.into());
-    }
-
     let time = Date::new_0().get_time();
 
     let seed = unsafe {
@@ -249,10 +515,6 @@ pub fn get_state_params() -> StateParams {
         println!("{}", s);
     }
 
-    fn error_logger(s: &str) {
-        eprintln!("{}", s);
-    }
-
     (
         new_seed(),
         Some(logger),
@@ -594,6 +594,108 @@ pub mod unscaled {
     }
 }
 
+/// Signed coordinates, so things can be partly or entirely off any edge of the
+/// screen. Sizes are still unsigned, so they are the same as the `unscaled` ones.
+/// Values are in pixels.
+pub mod world {
+    use super::unscaled;
+    pub use super::unscaled::{W, H};
+
+    pub type Inner = i32;
+
+    #[derive(Copy, Clone, Default, Debug, PartialEq, Eq, PartialOrd, Ord)]
+    pub struct X(pub Inner);
+    #[derive(Copy, Clone, Default, Debug, PartialEq, Eq, PartialOrd, Ord)]
+    pub struct Y(pub Inner);
+
+    impl From<unscaled::X> for X {
+        fn from(x: unscaled::X) -> Self {
+            X(x.0.into())
+        }
+    }
+
+    impl From<unscaled::Y> for Y {
+        fn from(y: unscaled::Y) -> Self {
+            Y(y.0.into())
+        }
+    }
+
+    #[derive(Clone, Copy, Default, Debug, PartialEq, Eq)]
+    pub struct XY {
+        pub x: X,
+        pub y: Y,
+    }
+
+    impl From<unscaled::XY> for XY {
+        fn from(xy: unscaled::XY) -> Self {
+            XY {
+                x: xy.x.into(),
+                y: xy.y.into(),
+            }
+        }
+    }
+
+    impl core::ops::AddAssign for XY {
+        fn add_assign(&mut self, other: Self) {
+            self.x.0 += other.x.0;
+            self.y.0 += other.y.0;
+        }
+    }
+
+    impl core::ops::Add for XY {
+        type Output = Self;
+
+        fn add(mut self, other: Self) -> Self::Output {
+            self += other;
+            self
+        }
+    }
+
+    impl core::ops::SubAssign for XY {
+        fn sub_assign(&mut self, other: Self) {
+            self.x.0 -= other.x.0;
+            self.y.0 -= other.y.0;
+        }
+    }
+
+    impl core::ops::Sub for XY {
+        type Output = Self;
+
+        fn sub(mut self, other: Self) -> Self::Output {
+            self -= other;
+            self
+        }
+    }
+
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    pub struct Rect {
+        pub x: X,
+        pub y: Y,
+        pub w: W,
+        pub h: H,
+    }
+
+    impl Rect {
+        pub fn xy(self) -> XY {
+            XY {
+                x: self.x,
+                y: self.y,
+            }
+        }
+    }
+
+    impl From<unscaled::Rect> for Rect {
+        fn from(rect: unscaled::Rect) -> Self {
+            Rect {
+                x: rect.x.into(),
+                y: rect.y.into(),
+                w: rect.w,
+                h: rect.h,
+            }
+        }
+    }
+}
+
 pub const GFX_WIDTH: usize = 128;
 pub const GFX_HEIGHT: usize = 256;
 pub const GFX_LENGTH: usize = GFX_WIDTH * GFX_HEIGHT;
@@ -605,6 +707,171 @@ pub const FONT_LENGTH: usize = FONT_WIDTH * FONT_HEIGHT;
 
 pub type PaletteIndex = u8;
 
+/// Sprite sheets, called atlases, that commands can draw from.
+pub mod atlas {
+    /// Refers to an atlas loaded by the platform. The built-in sheet is always
+    /// available, and the ones from `State::atlas_sources` are numbered after
+    /// it, in order.
+    #[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+    pub struct Id(pub u8);
+
+    impl Id {
+        /// The sheet compiled in from `assets`, that the font is on.
+        pub const BUILT_IN: Self = Self(0);
+
+        /// The texture the app writes to each frame. See `texture::Texture`.
+        pub const TEXTURE: Self = Self(u8::MAX);
+
+        /// An opaque white atlas, one texel in size, for drawing solid colours
+        /// with `Modulation::replace`, and a `Tile` one texel in size.
+        pub const SOLID: Self = Self(u8::MAX - 1);
+
+        /// How many of the atlases from `State::atlas_sources` can be loaded.
+        /// The ids after theirs are reserved for `SOLID` and `TEXTURE`.
+        pub const MAX_SOURCES: u8 = Self::SOLID.0 - 1;
+
+        /// Returns the id of the atlas at the given index into the slice
+        /// returned by `State::atlas_sources`, or `None` if the index is not
+        /// less than `MAX_SOURCES`.
+        pub const fn from_source_index(index: u8) -> Option<Self> {
+            if index < Self::MAX_SOURCES {
+                Some(Self(index + 1))
+            } else {
+                None
+            }
+        }
+    }
+
+    #[derive(Clone, Copy, Debug)]
+    pub enum Source {
+        /// The bytes of a PNG file, usually from `include_bytes!`. This works
+        /// on every platform.
+        Png(&'static [u8]),
+        /// The path of a PNG file, which is read at startup. This allows
+        /// changing the art without recompiling, but it is not available on
+        /// the web.
+        PngFile(&'static str),
+    }
+
+    #[cfg(test)]
+    mod from_source_index_works {
+        use super::*;
+
+        #[test]
+        fn on_the_first_and_last_allowed_indexes() {
+            assert_eq!(Id::from_source_index(0), Some(Id(1)));
+            assert_eq!(Id::from_source_index(Id::MAX_SOURCES - 1), Some(Id(Id::SOLID.0 - 1)));
+        }
+
+        #[test]
+        fn by_rejecting_indexes_that_would_reach_the_reserved_ids() {
+            assert_eq!(Id::from_source_index(Id::MAX_SOURCES), None);
+            assert_eq!(Id::from_source_index(u8::MAX - 1), None);
+            assert_eq!(Id::from_source_index(u8::MAX), None);
+        }
+    }
+}
+
+/// Pixels that the app can write to directly, for effects that would take too
+/// many commands to draw otherwise.
+pub mod texture {
+    use super::{ARGB, command};
+
+    /// A writable atlas, drawn from with `atlas::Id::TEXTURE`. The renderer only
+    /// redraws the parts of the screen showing the texture when its pixels
+    /// actually change.
+    #[derive(Clone, Debug, PartialEq, Eq)]
+    pub struct Texture {
+        width: usize,
+        height: usize,
+        pixels: Vec<ARGB>,
+    }
+
+    /// The same size as the screen.
+    impl Default for Texture {
+        fn default() -> Self {
+            Self::new(command::WIDTH.into(), command::HEIGHT.into())
+        }
+    }
+
+    impl Texture {
+        /// Returns a fully transparent texture of the given size.
+        pub fn new(width: usize, height: usize) -> Self {
+            Self {
+                width,
+                height,
+                pixels: vec![0; width * height],
+            }
+        }
+
+        pub fn width(&self) -> usize {
+            self.width
+        }
+
+        pub fn height(&self) -> usize {
+            self.height
+        }
+
+        /// The pixels, in rows from the top.
+        pub fn pixels(&self) -> &[ARGB] {
+            &self.pixels
+        }
+
+        pub fn pixels_mut(&mut self) -> &mut [ARGB] {
+            &mut self.pixels
+        }
+
+        /// Returns `None` if the coordinates are outside the texture.
+        pub fn get(&self, x: usize, y: usize) -> Option<ARGB> {
+            if x >= self.width {
+                return None
+            }
+
+            self.pixels.get(y * self.width + x).copied()
+        }
+
+        /// Does nothing if the coordinates are outside the texture.
+        pub fn set(&mut self, x: usize, y: usize, colour: ARGB) {
+            if x >= self.width {
+                return
+            }
+
+            if let Some(pixel) = self.pixels.get_mut(y * self.width + x) {
+                *pixel = colour;
+            }
+        }
+
+        pub fn fill(&mut self, colour: ARGB) {
+            self.pixels.fill(colour);
+        }
+    }
+}
+
+#[cfg(test)]
+mod texture_set_works {
+    use super::*;
+
+    #[test]
+    fn on_these_coordinates_just_outside_the_texture() {
+        let mut texture = Texture::new(3, 2);
+
+        texture.set(3, 0, colours::RED);
+        texture.set(0, 2, colours::RED);
+
+        assert_eq!(texture, Texture::new(3, 2));
+    }
+
+    #[test]
+    fn on_this_bottom_right_pixel() {
+        let mut texture = Texture::new(3, 2);
+
+        texture.set(2, 1, colours::RED);
+
+        assert_eq!(texture.pixels(), [0, 0, 0, 0, 0, colours::RED]);
+    }
+}
+pub use texture::Texture;
+
 
 pub mod sprite {
     pub use super::unscaled::{W, H};
@@ -704,14 +971,88 @@ pub mod sprite {
 
 pub mod command {
     use xs::Xs;
-    use super::{ARGB, sprite, unscaled};
+    use super::{ARGB, Orientation, atlas, sprite, unscaled};
 
     pub type Inner = unscaled::Inner;
 
-    // Small enough to fit on pretty much any reasonable device, at an aspect ratio
-    // of 3:2 (1.5), which is a compromise between 4:3 (1.33...) and 16:9 (1.788...).
-    pub const WIDTH: Inner = 480;
-    pub const HEIGHT: Inner = 320;
+    // By default, small enough to fit on pretty much any reasonable device, at
+    // an aspect ratio of 3:2 (1.5), which is a compromise between 4:3 (1.33...)
+    // and 16:9 (1.788...).
+    const DEFAULT_WIDTH: Inner = 480;
+    const DEFAULT_HEIGHT: Inner = 320;
+
+    /// The logical resolution. A project can pick its own by setting the
+    /// `LOGICAL_WIDTH` and `LOGICAL_HEIGHT` environment variables at build
+    /// time, for example in its `.cargo/config.toml`. The clipping, the
+    /// letterboxing, the hash cell grid and the web canvas follow from these.
+    /// Sprites, including the built in font, stay the same size in texels,
+    /// so very small resolutions leave little room for them.
+    pub const WIDTH: Inner = dimension(option_env!("LOGICAL_WIDTH"), DEFAULT_WIDTH);
+    pub const HEIGHT: Inner = dimension(option_env!("LOGICAL_HEIGHT"), DEFAULT_HEIGHT);
+
+    /// Parses a dimension given at build time, or returns the default if none
+    /// was given. Building fails if the value is not a whole number from 1 up
+    /// to `Inner::MAX`.
+    const fn dimension(value: Option<&str>, default: Inner) -> Inner {
+        let Some(value) = value else { return default };
+        let bytes = value.as_bytes();
+
+        assert!(!bytes.is_empty(), "LOGICAL_WIDTH and LOGICAL_HEIGHT must not be empty");
+
+        let mut output: u32 = 0;
+        let mut i = 0;
+        while i < bytes.len() {
+            assert!(
+                bytes[i].is_ascii_digit(),
+                "LOGICAL_WIDTH and LOGICAL_HEIGHT must be whole numbers"
+            );
+            output = output * 10 + (bytes[i] - b'0') as u32;
+            assert!(
+                output <= Inner::MAX as u32,
+                "LOGICAL_WIDTH and LOGICAL_HEIGHT are too large"
+            );
+            i += 1;
+        }
+
+        assert!(output > 0, "LOGICAL_WIDTH and LOGICAL_HEIGHT must not be zero");
+
+        output as Inner
+    }
+
+    #[cfg(test)]
+    mod dimension_works {
+        use super::*;
+
+        #[test]
+        fn on_no_value() {
+            assert_eq!(dimension(None, 480), 480);
+        }
+
+        #[test]
+        fn on_these_values() {
+            assert_eq!(dimension(Some("256"), 480), 256);
+            assert_eq!(dimension(Some("1"), 480), 1);
+            assert_eq!(dimension(Some("65535"), 480), 65535);
+        }
+
+        #[test]
+        #[should_panic]
+        fn on_zero() {
+            dimension(Some("0"), 480);
+        }
+
+        #[test]
+        #[should_panic]
+        fn on_a_value_that_is_too_large() {
+            dimension(Some("65536"), 480);
+        }
+
+        #[test]
+        #[should_panic]
+        fn on_a_value_that_is_not_a_number() {
+            dimension(Some("64O"), 480);
+        }
+    }
 
     pub const LENGTH: usize = WIDTH as usize * HEIGHT as usize;
 
@@ -1047,14 +1388,299 @@ pub mod command {
         assert_eq!(expected, actual);
     }
 
+    /// How a command's pixels are combined with the pixels already drawn.
+    /// All of these except `Replace` are done in a roughly linear colour space.
+    #[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+    pub enum BlendMode {
+        /// Regular alpha blending.
+        #[default]
+        Over,
+        /// Adds the colour, scaled by alpha, to what is underneath. Useful for
+        /// glows and lights.
+        Add,
+        /// Multiplies what is underneath by the colour, scaled by alpha.
+        /// Useful for shadows and lighting.
+        Multiply,
+        /// Overwrites what is underneath, including with transparent pixels.
+        /// This skips the blending math, so it is the fastest option for
+        /// fully opaque sprites.
+        Replace,
+    }
+
+    /// How the colours of a command's texels are changed before being blended.
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    pub struct Modulation {
+        /// If set, every fully opaque texel is replaced with this colour,
+        /// before the tint is applied. This can be a transparent colour.
+        pub replace: Option<ARGB>,
+        /// Each channel of each texel, including alpha, is multiplied by the
+        /// matching channel of this, with `0xFF` treated as 1.
+        pub tint: ARGB,
+        /// Multiplies the alpha of each texel, after the tint, with `0xFF`
+        /// treated as 1.
+        pub alpha: u8,
+    }
+
+    impl Modulation {
+        /// Leaves the texels as they are.
+        pub const IDENTITY: Self = Self {
+            replace: None,
+            tint: 0xFFFF_FFFF,
+            alpha: 0xFF,
+        };
+
+        pub const fn replace(colour: ARGB) -> Self {
+            Self {
+                replace: Some(colour),
+                ..Self::IDENTITY
+            }
+        }
+
+        pub const fn tint(tint: ARGB) -> Self {
+            Self {
+                tint,
+                ..Self::IDENTITY
+            }
+        }
+
+        pub const fn alpha(alpha: u8) -> Self {
+            Self {
+                alpha,
+                ..Self::IDENTITY
+            }
+        }
+
+        /// Returns whether only the replace colour, if any, changes the texels.
+        pub const fn is_replace_only(&self) -> bool {
+            self.tint == Self::IDENTITY.tint
+            && self.alpha == Self::IDENTITY.alpha
+        }
+    }
+
+    impl Default for Modulation {
+        fn default() -> Self {
+            Self::IDENTITY
+        }
+    }
+
+    /// A single on screen pixel, drawn by a points command.
+    #[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+    pub struct Point {
+        pub x: X,
+        pub y: Y,
+        /// Treated like a texel from an atlas, so the command's modulation and
+        /// blend mode apply to it.
+        pub colour: ARGB,
+    }
+
+    /// A range of indexes into the points that are passed along with the
+    /// commands.
+    #[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+    pub struct Points {
+        pub start: u32,
+        pub end: u32,
+    }
+
+    impl Points {
+        pub fn is_empty(self) -> bool {
+            self.start >= self.end
+        }
+
+        pub fn range(self) -> core::ops::Range<usize> {
+            self.start as usize..self.end as usize
+        }
+    }
+
+    /// A region at a command's `sprite_xy` that is repeated across its rect,
+    /// instead of the rect reading the texels past the region. Like the rect,
+    /// the size is as drawn, so with `Orientation::TRANSPOSE` the region is
+    /// `h` texels wide and `w` texels tall on the atlas.
+    #[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+    pub struct Tile {
+        pub w: sprite::Inner,
+        pub h: sprite::Inner,
+        /// Where in the region the top left corner of the rect is, so that
+        /// cutting off part of the rect does not move the pattern.
+        pub x_offset: sprite::Inner,
+        pub y_offset: sprite::Inner,
+    }
+
+    impl Tile {
+        /// A command with an empty tile does not repeat anything.
+        pub fn is_empty(self) -> bool {
+            self.w == 0 || self.h == 0
+        }
+
+        /// Returns the offset into the region, as drawn, that is drawn at the
+        /// offset `(dx, dy)` into the rect. The tile must not be empty.
+        pub fn offset(
+            self,
+            (dx, dy): (usize, usize),
+        ) -> (sprite::Inner, sprite::Inner) {
+            (
+                ((usize::from(self.x_offset) + dx) % usize::from(self.w)) as sprite::Inner,
+                ((usize::from(self.y_offset) + dy) % usize::from(self.h)) as sprite::Inner,
+            )
+        }
+    }
+
     #[derive(Clone, Copy, Debug, Default)]
     pub struct Command {
         pub rect: Rect,
         pub sprite_xy: sprite::XY,
-        pub colour_override: ARGB,
+        pub modulation: Modulation,
+        pub orientation: Orientation,
+        pub blend_mode: BlendMode,
+        pub atlas: atlas::Id,
+        /// If this is not empty, the command draws these points, which should
+        /// all be inside `rect`, instead of a sprite. `sprite_xy`, `orientation`
+        /// and `atlas` are then ignored.
+        pub points: Points,
+        /// If this is not empty, the command repeats this part of the sprite.
+        pub tile: Tile,
     }    
 }
-pub use command::Command;
+pub use command::{BlendMode, Command, Modulation, Point};
+
+/// Everything that is drawn in a frame.
+#[derive(Clone, Copy, Debug)]
+pub struct Graphics<'a> {
+    pub commands: &'a [Command],
+    /// The points that commands refer to with `Command::points`.
+    pub points: &'a [Point],
+    /// The pixels that commands using `atlas::Id::TEXTURE` draw from.
+    pub texture: &'a Texture,
+    /// Changes whenever `texture` may have been written to, so the renderer
+    /// only needs to look at its pixels when this changes.
+    pub texture_version: u32,
+}
+
+/// How a command's sprite is mirrored and/or rotated when drawn into its rect.
+/// Any combination of flips and quarter turns can be expressed with the three
+/// flag bits, the same way the Tiled map editor does it.
+pub mod orientation {
+    #[derive(Copy, Clone, Default, Debug, PartialEq, Eq)]
+    pub struct Orientation(u8);
+
+    impl Orientation {
+        /// Mirror the sprite left to right.
+        pub const FLIP_X   : Self = Self(1 << 0);
+        /// Mirror the sprite top to bottom.
+        pub const FLIP_Y   : Self = Self(1 << 1);
+        /// Swap the sprite's x and y axes. That is, mirror it along the
+        /// diagonal from the top left corner to the bottom right one. This is
+        /// applied after the flips.
+        pub const TRANSPOSE: Self = Self(1 << 2);
+
+        // Clockwise quarter turns.
+        pub const ROTATE_90 : Self = Self(Self::FLIP_X.0 | Self::TRANSPOSE.0);
+        pub const ROTATE_180: Self = Self(Self::FLIP_X.0 | Self::FLIP_Y.0);
+        pub const ROTATE_270: Self = Self(Self::FLIP_Y.0 | Self::TRANSPOSE.0);
+
+        pub const fn contains(&self, other: Self) -> bool {
+            self.0 & other.0 == other.0
+        }
+
+        pub fn insert(&mut self, other: Self) {
+            self.0 |= other.0;
+        }
+
+        pub fn remove(&mut self, other: Self) {
+            self.0 &= !other.0;
+        }
+
+        pub const fn bits(self) -> u8 {
+            self.0
+        }
+
+        /// Returns the offset into the sprite that should be drawn at the
+        /// offset `(dx, dy)` into a rect of size `(w, h)`. `dx` must be less
+        /// than `w`, and `dy` less than `h`.
+        ///
+        /// Note that when `TRANSPOSE` is set, the sprite is `h` pixels wide
+        /// and `w` pixels tall.
+        pub const fn source_offset(
+            self,
+            (dx, dy): (super::sprite::Inner, super::sprite::Inner),
+            (w, h): (super::sprite::Inner, super::sprite::Inner),
+        ) -> (super::sprite::Inner, super::sprite::Inner) {
+            let dx = if self.contains(Self::FLIP_X) { w - 1 - dx } else { dx };
+            let dy = if self.contains(Self::FLIP_Y) { h - 1 - dy } else { dy };
+
+            if self.contains(Self::TRANSPOSE) {
+                (dy, dx)
+            } else {
+                (dx, dy)
+            }
+        }
+    }
+
+    impl core::ops::BitOr for Orientation {
+        type Output = Self;
+
+        fn bitor(mut self, other: Self) -> Self::Output {
+            self.insert(other);
+            self
+        }
+    }
+
+    #[cfg(test)]
+    mod source_offset_works {
+        use super::*;
+
+        // A 3x2 sprite, with a distinct value at each offset.
+        //   0 1 2
+        //   3 4 5
+        const SPRITE: [[u8; 3]; 2] = [[0, 1, 2], [3, 4, 5]];
+
+        fn draw(orientation: Orientation) -> Vec<Vec<u8>> {
+            let (w, h) = if orientation.contains(Orientation::TRANSPOSE) {
+                (2, 3)
+            } else {
+                (3, 2)
+            };
+
+            (0..h).map(|dy|
+                (0..w).map(|dx| {
+                    let (u, v) = orientation.source_offset((dx, dy), (w, h));
+                    SPRITE[usize::from(v)][usize::from(u)]
+                }).collect()
+            ).collect()
+        }
+
+        #[test]
+        fn on_the_default() {
+            assert_eq!(draw(<_>::default()), vec![vec![0, 1, 2], vec![3, 4, 5]]);
+        }
+
+        #[test]
+        fn on_flip_x() {
+            assert_eq!(draw(Orientation::FLIP_X), vec![vec![2, 1, 0], vec![5, 4, 3]]);
+        }
+
+        #[test]
+        fn on_flip_y() {
+            assert_eq!(draw(Orientation::FLIP_Y), vec![vec![3, 4, 5], vec![0, 1, 2]]);
+        }
+
+        #[test]
+        fn on_the_quarter_turns() {
+            assert_eq!(
+                draw(Orientation::ROTATE_90),
+                vec![vec![3, 0], vec![4, 1], vec![5, 2]]
+            );
+            assert_eq!(
+                draw(Orientation::ROTATE_180),
+                vec![vec![5, 4, 3], vec![2, 1, 0]]
+            );
+            assert_eq!(
+                draw(Orientation::ROTATE_270),
+                vec![vec![2, 5], vec![1, 4], vec![0, 3]]
+            );
+        }
+    }
+}
+pub use orientation::Orientation;
 
 #[derive(Clone, Copy, Default, Debug)]
 pub struct Input {
@@ -1142,7 +1768,13 @@ pub type Logger = Option<fn(&str) -> ()>;
 pub type StateParams = ([u8; 16], Logger, Logger);
 
 pub trait State {
-    fn frame(&mut self) -> (&[Command], &[SFX]);
+    fn frame(&mut self) -> (Graphics<'_>, &[SFX]);
+
+    /// The atlases to load when starting up, in addition to the built-in one.
+    /// See `atlas::Id::from_source_index`.
+    fn atlas_sources(&self) -> &[atlas::Source] {
+        &[]
+    }
 
     fn press(&mut self, button: Button);
 
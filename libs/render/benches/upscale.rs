@@ -0,0 +1,109 @@
+//! Compares the ways the `Integer` scale mode can copy the unscaled buffer into
+//! the buffer, at common window sizes.
+//!
+//! Run with `cargo bench -p render`.
+use platform_types::{command::{self, Rect}, unscaled, Command};
+use render::{clip, CellGrid, FrameBuffer, HashCells, Upscaler};
+
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+const FRAME_COUNT: usize = 120;
+const WARM_UP_FRAME_COUNT: usize = 10;
+
+/// A single pixel that moves each frame. With a single hash cell, this makes
+/// each frame redraw the whole screen, while spending very little time on
+/// anything other than scaling it up.
+fn moving_pixel(frame: usize) -> [Command; 1] {
+    [Command {
+        rect: Rect::from_unscaled(unscaled::Rect {
+            x: unscaled::X((frame % usize::from(command::WIDTH)) as unscaled::Inner),
+            y: unscaled::Y(0),
+            w: unscaled::W(1),
+            h: unscaled::H(1),
+        }),
+        ..<_>::default()
+    }]
+}
+
+fn frame_buffer(upscaler: Upscaler, size: (clip::W, clip::H)) -> FrameBuffer {
+    let mut frame_buffer = FrameBuffer::from_size(size);
+    frame_buffer.upscaler = upscaler;
+    frame_buffer.cells = HashCells::with_grid(CellGrid::SINGLE);
+
+    frame_buffer
+}
+
+/// Returns the median time each upscaler took to render a frame, in the same
+/// order as `upscalers`. The upscalers take turns rendering each frame, in a
+/// different order each time, so that anything else slowing the machine down
+/// for a while affects them all about the same.
+fn median_frame_times(upscalers: &[Upscaler], size: (clip::W, clip::H)) -> Vec<Duration> {
+    let mut frame_buffers: Vec<FrameBuffer> = upscalers.iter()
+        .map(|&upscaler| frame_buffer(upscaler, size))
+        .collect();
+
+    // Warm up the caches, and allocate the buffers.
+    for frame in 0..WARM_UP_FRAME_COUNT {
+        for frame_buffer in &mut frame_buffers {
+            black_box(render::render(frame_buffer, &moving_pixel(frame)));
+        }
+    }
+
+    let mut times = vec![Vec::with_capacity(FRAME_COUNT); upscalers.len()];
+    for frame in 0..FRAME_COUNT {
+        let commands = moving_pixel(frame);
+
+        for turn in 0..upscalers.len() {
+            let i = (frame + turn) % upscalers.len();
+
+            let start = Instant::now();
+            black_box(render::render(&mut frame_buffers[i], &commands));
+            times[i].push(start.elapsed());
+        }
+    }
+
+    times.into_iter()
+        .map(|mut times| {
+            times.sort();
+            times[times.len() / 2]
+        })
+        .collect()
+}
+
+fn main() {
+    let threads = std::thread::available_parallelism()
+        .unwrap_or(core::num::NonZeroUsize::MIN);
+
+    let names = ["scalar", "wide", "threaded"];
+    let upscalers = [
+        Upscaler::Scalar,
+        Upscaler::Wide,
+        Upscaler::Threaded(threads),
+    ];
+
+    let sizes: [(&str, (clip::W, clip::H)); 5] = [
+        ("unscaled", (command::WIDTH, command::HEIGHT)),
+        ("2x", (command::WIDTH * 2, command::HEIGHT * 2)),
+        ("3x", (command::WIDTH * 3, command::HEIGHT * 3)),
+        ("1080p", (1920, 1080)),
+        ("4K", (3840, 2160)),
+    ];
+
+    println!(
+        "Median of {FRAME_COUNT} full redraws of {}x{}, with up to {threads} threads:",
+        command::WIDTH,
+        command::HEIGHT,
+    );
+    for (size_name, size) in sizes {
+        let times = median_frame_times(&upscalers, size);
+
+        for (name, time) in names.iter().zip(times) {
+            println!(
+                "{:>24}: {:>8.1?} per frame",
+                format!("{name} at {size_name}"),
+                time,
+            );
+        }
+    }
+}
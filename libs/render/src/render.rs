@@ -1,12 +1,16 @@
 #![deny(clippy::shadow_unrelated)]
 use platform_types::{
     Command,
-    GFX_WIDTH,
     ARGB,
     colours,
+    BlendMode,
+    Modulation,
+    Orientation,
+    Point,
     command::{self, Rect},
     sprite,
     unscaled,
+    Graphics,
 };
 
 use assets::GFX;
@@ -18,7 +22,7 @@ pub mod clip {
     pub type W = u16;
     pub type H = u16;
 
-    #[derive(Clone, Debug)]
+    #[derive(Clone, Debug, PartialEq, Eq)]
     pub struct Rect {
         pub x: Range<X>,
         pub y: Range<Y>,
@@ -35,6 +39,589 @@ pub mod clip {
     }
 }
 
+/// The sprite sheets that commands draw from.
+pub mod atlas {
+    use super::*;
+    use std::borrow::Cow;
+
+    pub use platform_types::atlas::{Id, Source};
+
+    #[derive(Debug)]
+    pub enum Error {
+        Decoding(png::DecodingError),
+        UnsupportedFormat(png::ColorType, png::BitDepth),
+    }
+
+    impl core::fmt::Display for Error {
+        fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+            use Error::*;
+            match self {
+                Decoding(e) => write!(f, "{e}"),
+                UnsupportedFormat(colour_type, bit_depth) => write!(
+                    f,
+                    "Cannot handle {colour_type:?} images with bit depth {bit_depth:?}"
+                ),
+            }
+        }
+    }
+
+    impl std::error::Error for Error {}
+
+    impl From<png::DecodingError> for Error {
+        fn from(e: png::DecodingError) -> Self {
+            Error::Decoding(e)
+        }
+    }
+
+    #[derive(Clone, Debug, Default, PartialEq, Eq)]
+    pub struct Atlas {
+        width: usize,
+        height: usize,
+        pixels: Cow<'static, [ARGB]>,
+    }
+
+    impl Atlas {
+        /// The sheet compiled in from `assets`.
+        pub fn built_in() -> Self {
+            Self {
+                width: platform_types::GFX_WIDTH,
+                height: platform_types::GFX_HEIGHT,
+                pixels: Cow::Borrowed(&GFX),
+            }
+        }
+
+        /// Returns `None` if `pixels` does not have exactly `width * height`
+        /// elements.
+        pub fn from_pixels(
+            width: usize,
+            height: usize,
+            pixels: Vec<ARGB>,
+        ) -> Option<Self> {
+            if width.checked_mul(height)? != pixels.len() {
+                return None
+            }
+
+            Some(Self {
+                width,
+                height,
+                pixels: Cow::Owned(pixels),
+            })
+        }
+
+        pub fn from_png(bytes: &[u8]) -> Result<Self, Error> {
+            let mut decoder = png::Decoder::new(bytes);
+            // Turn palettes, low bit depths and 16 bit depths into plain 8 bit
+            // channels.
+            decoder.set_transformations(png::Transformations::normalize_to_color8());
+
+            let mut reader = decoder.read_info()?;
+            let mut buf = vec![0; reader.output_buffer_size()];
+            let info = reader.next_frame(&mut buf)?;
+
+            if info.bit_depth != png::BitDepth::Eight {
+                return Err(Error::UnsupportedFormat(info.color_type, info.bit_depth));
+            }
+
+            use png::ColorType::*;
+            let to_argb: fn(&[u8]) -> ARGB = match info.color_type {
+                Rgba => |c| ARGB::from_be_bytes([c[3], c[0], c[1], c[2]]),
+                Rgb => |c| ARGB::from_be_bytes([0xFF, c[0], c[1], c[2]]),
+                GrayscaleAlpha => |c| ARGB::from_be_bytes([c[1], c[0], c[0], c[0]]),
+                Grayscale => |c| ARGB::from_be_bytes([0xFF, c[0], c[0], c[0]]),
+                Indexed => {
+                    return Err(Error::UnsupportedFormat(info.color_type, info.bit_depth));
+                }
+            };
+
+            let pixels = buf[..info.buffer_size()]
+                .chunks_exact(info.color_type.samples())
+                .map(to_argb)
+                .collect();
+
+            Ok(Self {
+                width: info.width as usize,
+                height: info.height as usize,
+                pixels: Cow::Owned(pixels),
+            })
+        }
+
+        pub fn width(&self) -> usize {
+            self.width
+        }
+
+        pub fn height(&self) -> usize {
+            self.height
+        }
+
+        pub fn pixels(&self) -> &[ARGB] {
+            &self.pixels
+        }
+    }
+
+    /// Holds the atlases that `Id`s refer to. Starts with just the built-in
+    /// atlas, the solid atlas, and an empty texture.
+    #[derive(Clone, Debug)]
+    pub struct Atlases {
+        atlases: Vec<Atlas>,
+        solid: Atlas,
+        texture: Atlas,
+        texture_version: u32,
+    }
+
+    impl Default for Atlases {
+        fn default() -> Self {
+            Self {
+                atlases: vec![Atlas::built_in()],
+                solid: Atlas {
+                    width: 1,
+                    height: 1,
+                    pixels: Cow::Borrowed(&[0xFFFF_FFFF]),
+                },
+                texture: <_>::default(),
+                texture_version: 0,
+            }
+        }
+    }
+
+    impl Atlases {
+        /// Returns `None` if the maximum number of atlases is already loaded.
+        pub fn push(&mut self, atlas: Atlas) -> Option<Id> {
+            let id = Id(u8::try_from(self.atlases.len()).ok()?);
+            if id == Id::SOLID || id == Id::TEXTURE {
+                return None
+            }
+
+            self.atlases.push(atlas);
+
+            Some(id)
+        }
+
+        pub fn get(&self, id: Id) -> Option<&Atlas> {
+            if id == Id::SOLID {
+                return Some(&self.solid)
+            }
+            if id == Id::TEXTURE {
+                return Some(&self.texture)
+            }
+
+            self.atlases.get(usize::from(id.0))
+        }
+
+        /// Copies the texture's pixels into the atlas for `Id::TEXTURE`. If they
+        /// differ from the ones already there, the texture version changes.
+        pub fn set_texture(&mut self, texture: &platform_types::Texture) {
+            let atlas = &mut self.texture;
+            if atlas.width == texture.width()
+            && atlas.height == texture.height()
+            && *atlas.pixels == *texture.pixels() {
+                return
+            }
+
+            atlas.width = texture.width();
+            atlas.height = texture.height();
+            let pixels = atlas.pixels.to_mut();
+            pixels.clear();
+            pixels.extend_from_slice(texture.pixels());
+
+            self.texture_version = self.texture_version.wrapping_add(1);
+        }
+
+        /// Changes whenever the texture's pixels do, so it can be hashed in
+        /// place of them.
+        pub fn texture_version(&self) -> u32 {
+            self.texture_version
+        }
+    }
+}
+
+#[cfg(test)]
+mod from_png_works {
+    use super::*;
+    use atlas::Atlas;
+
+    fn encode(
+        (width, height): (u32, u32),
+        colour_type: png::ColorType,
+        bytes: &[u8],
+    ) -> Vec<u8> {
+        let mut output = Vec::new();
+        {
+            let mut encoder = png::Encoder::new(&mut output, width, height);
+            encoder.set_color(colour_type);
+            encoder.set_depth(png::BitDepth::Eight);
+
+            let mut writer = encoder.write_header().unwrap();
+            writer.write_image_data(bytes).unwrap();
+        }
+        output
+    }
+
+    #[test]
+    fn on_this_rgba_example() {
+        let bytes = encode(
+            (3, 2),
+            png::ColorType::Rgba,
+            &[
+                0x52, 0xE1, 0x33, 0xFF,  0xB0, 0x6E, 0x30, 0xFF,  0, 0, 0, 0,
+                0xDE, 0x49, 0x49, 0x80,  0xFF, 0xB9, 0x37, 0xFF,  0x22, 0x22, 0x22, 0xFF,
+            ],
+        );
+
+        assert_eq!(
+            Atlas::from_png(&bytes).unwrap(),
+            Atlas::from_pixels(3, 2, vec![
+                0xFF52E133, 0xFFB06E30, 0x00000000,
+                0x80DE4949, 0xFFFFB937, 0xFF222222,
+            ]).unwrap(),
+        );
+    }
+
+    #[test]
+    fn on_this_grayscale_example() {
+        let bytes = encode((2, 1), png::ColorType::Grayscale, &[0x00, 0x80]);
+
+        assert_eq!(
+            Atlas::from_png(&bytes).unwrap(),
+            Atlas::from_pixels(2, 1, vec![0xFF000000, 0xFF808080]).unwrap(),
+        );
+    }
+
+    #[test]
+    fn on_these_bytes_that_are_not_a_png() {
+        assert!(Atlas::from_png(b"not a png").is_err());
+    }
+}
+
+/// Filters that change the pixels after the commands are drawn, to imitate the
+/// look of old screens and the like.
+pub mod post {
+    use super::*;
+
+    /// Which buffer a filter runs on.
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    pub enum Stage {
+        /// The unscaled buffer, before it is scaled up. Each pixel here is a
+        /// texel.
+        Unscaled,
+        /// The buffer, after the unscaled buffer is scaled up into it.
+        Scaled,
+    }
+
+    /// The pixels that a filter is run on.
+    pub struct Pixels<'buffer> {
+        /// The whole buffer, one row after another.
+        pub buffer: &'buffer mut [ARGB],
+        /// The length of each row in `buffer`.
+        pub width: clip::W,
+        /// Where the unscaled screen is in `buffer`. For the unscaled stage,
+        /// this is all of it.
+        pub screen: clip::Rect,
+        /// The part of `buffer` to change. Outside of `screen` are the bars,
+        /// which filters should leave alone, so this is always inside it.
+        pub rect: clip::Rect,
+    }
+
+    pub trait Filter {
+        fn stage(&self) -> Stage;
+
+        /// Whether the filter moves pixels to other places, rather than
+        /// changing each pixel in place based on its colour and position. If
+        /// so, the whole screen is redrawn and passed as the rect, whenever
+        /// anything changes, since a change in one place can show up anywhere.
+        fn moves_pixels(&self) -> bool {
+            false
+        }
+
+        /// Changes the pixels in `pixels.rect`. The pixels there will have
+        /// just been drawn, without this filter applied yet.
+        fn apply(&mut self, pixels: Pixels<'_>);
+    }
+
+    /// Refers to a filter added to a `FrameBuffer`.
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+    pub struct Id(pub(crate) usize);
+
+    pub(crate) struct Slot {
+        pub(crate) filter: Box<dyn Filter>,
+        pub(crate) enabled: bool,
+    }
+
+    /// Runs each enabled filter for the given stage, in the order they were
+    /// added.
+    pub(crate) fn run(
+        slots: &mut [Slot],
+        stage: Stage,
+        buffer: &mut [ARGB],
+        width: clip::W,
+        screen: &clip::Rect,
+        rect: &clip::Rect,
+    ) {
+        for slot in slots.iter_mut() {
+            if !slot.enabled || slot.filter.stage() != stage {
+                continue
+            }
+
+            slot.filter.apply(Pixels {
+                buffer: &mut *buffer,
+                width,
+                screen: screen.clone(),
+                rect: rect.clone(),
+            });
+        }
+    }
+
+    /// Calls `f` with each pixel in the rect, and its position relative to the
+    /// centre of the screen, where `-1.0` and `1.0` are the edges.
+    fn for_each_pixel(
+        Pixels { buffer, width, screen, rect }: Pixels<'_>,
+        mut f: impl FnMut(&mut ARGB, f32, f32),
+    ) {
+        let half_w = f32::from(screen.width()) / 2.;
+        let half_h = f32::from(screen.height()) / 2.;
+
+        for y in rect.y.clone() {
+            let v = (f32::from(y - screen.y.start) + 0.5) / half_h - 1.;
+            let row_start = usize::from(y) * usize::from(width);
+
+            for x in rect.x.clone() {
+                let u = (f32::from(x - screen.x.start) + 0.5) / half_w - 1.;
+
+                f(&mut buffer[row_start + usize::from(x)], u, v);
+            }
+        }
+    }
+
+    /// Multiplies the red, green and blue channels by the given amounts, out
+    /// of 256.
+    fn scale_channels(argb: ARGB, [r, g, b]: [u32; 3]) -> ARGB {
+        let channel = |shift: u32, scale: u32| {
+            ((((argb >> shift) & 0xFF) * scale) >> 8).min(0xFF) << shift
+        };
+
+        (argb & 0xFF00_0000) | channel(16, r) | channel(8, g) | channel(0, b)
+    }
+
+    /// How much of each channel to keep, out of 256, when darkening by the
+    /// given amount, from `0.0` for none, to `1.0` for all the way to black.
+    fn keep(amount: f32) -> u32 {
+        (256. * (1. - amount.clamp(0., 1.))).round() as u32
+    }
+
+    /// Replaces each texel with the nearest colour in `PALETTE`. Useful for
+    /// checking that art made with other tools sticks to the palette.
+    #[derive(Clone, Copy, Debug, Default)]
+    pub struct Quantise;
+
+    impl Quantise {
+        pub fn nearest(argb: ARGB) -> ARGB {
+            let distance = |other: ARGB| {
+                [16, 8, 0].into_iter()
+                    .map(|shift| {
+                        let d = ((argb >> shift) & 0xFF) as i32
+                            - ((other >> shift) & 0xFF) as i32;
+                        d * d
+                    })
+                    .sum::<i32>()
+            };
+
+            platform_types::PALETTE.into_iter()
+                .min_by_key(|&colour| distance(colour))
+                .map_or(argb, |colour| (argb & 0xFF00_0000) | (colour & 0x00FF_FFFF))
+        }
+    }
+
+    impl Filter for Quantise {
+        fn stage(&self) -> Stage {
+            Stage::Unscaled
+        }
+
+        fn apply(&mut self, pixels: Pixels<'_>) {
+            for_each_pixel(pixels, |pixel, _, _| *pixel = Self::nearest(*pixel));
+        }
+    }
+
+    /// Darkens the bottom half of each row of texels. This needs the screen
+    /// to be scaled up at least twice as tall to show.
+    #[derive(Clone, Copy, Debug)]
+    pub struct Scanlines {
+        /// From `0.0` for no change, to `1.0` for black lines.
+        pub darkness: f32,
+    }
+
+    impl Default for Scanlines {
+        fn default() -> Self {
+            Self { darkness: 0.4 }
+        }
+    }
+
+    impl Filter for Scanlines {
+        fn stage(&self) -> Stage {
+            Stage::Scaled
+        }
+
+        fn apply(&mut self, pixels: Pixels<'_>) {
+            let keep = keep(self.darkness);
+            let screen_h = u32::from(pixels.screen.height());
+            let top = pixels.screen.y.start;
+
+            let Pixels { buffer, width, rect, .. } = pixels;
+            for y in rect.y.clone() {
+                // Which half of a texel row this is, counting from the top.
+                let half = u32::from(y - top) * 2 * u32::from(command::HEIGHT) / screen_h;
+                if half.is_multiple_of(2) {
+                    continue
+                }
+
+                let row_start = usize::from(y) * usize::from(width);
+                for pixel in &mut buffer[
+                    row_start + usize::from(rect.x.start)..row_start + usize::from(rect.x.end)
+                ] {
+                    *pixel = scale_channels(*pixel, [keep; 3]);
+                }
+            }
+        }
+    }
+
+    /// Imitates the stripes of red, green and blue phosphors on a CRT, by
+    /// dimming the other two channels in each column, in turn.
+    #[derive(Clone, Copy, Debug)]
+    pub struct ShadowMask {
+        /// From `0.0` for no change, to `1.0` for only one channel per column.
+        pub strength: f32,
+    }
+
+    impl Default for ShadowMask {
+        fn default() -> Self {
+            Self { strength: 0.3 }
+        }
+    }
+
+    impl Filter for ShadowMask {
+        fn stage(&self) -> Stage {
+            Stage::Scaled
+        }
+
+        fn apply(&mut self, pixels: Pixels<'_>) {
+            let keep = keep(self.strength);
+            let left = pixels.screen.x.start;
+
+            let Pixels { buffer, width, rect, .. } = pixels;
+            for y in rect.y.clone() {
+                let row_start = usize::from(y) * usize::from(width);
+
+                for x in rect.x.clone() {
+                    let scales = match (x - left) % 3 {
+                        0 => [256, keep, keep],
+                        1 => [keep, 256, keep],
+                        _ => [keep, keep, 256],
+                    };
+
+                    let pixel = &mut buffer[row_start + usize::from(x)];
+                    *pixel = scale_channels(*pixel, scales);
+                }
+            }
+        }
+    }
+
+    /// Darkens the screen towards the corners.
+    #[derive(Clone, Copy, Debug)]
+    pub struct Vignette {
+        /// How much the corners are darkened, from `0.0` for not at all, to
+        /// `1.0` for all the way to black.
+        pub strength: f32,
+    }
+
+    impl Default for Vignette {
+        fn default() -> Self {
+            Self { strength: 0.5 }
+        }
+    }
+
+    impl Filter for Vignette {
+        fn stage(&self) -> Stage {
+            Stage::Scaled
+        }
+
+        fn apply(&mut self, pixels: Pixels<'_>) {
+            let strength = self.strength;
+
+            for_each_pixel(pixels, |pixel, u, v| {
+                // The corners are at a squared distance of 2.
+                *pixel = scale_channels(*pixel, [keep(strength * (u * u + v * v) / 2.); 3]);
+            });
+        }
+    }
+
+    /// Bends the screen outwards, like the glass of a CRT. The parts that end
+    /// up outside of the screen are left black, like the bars.
+    #[derive(Clone, Debug)]
+    pub struct Curvature {
+        /// How much further out the corners are taken from, as a fraction of
+        /// their distance from the centre. `0.0` leaves the screen flat.
+        pub amount: f32,
+        unbent: Vec<ARGB>,
+    }
+
+    impl Curvature {
+        pub fn new(amount: f32) -> Self {
+            Self {
+                amount,
+                unbent: Vec::new(),
+            }
+        }
+    }
+
+    impl Default for Curvature {
+        fn default() -> Self {
+            Self::new(0.1)
+        }
+    }
+
+    impl Filter for Curvature {
+        fn stage(&self) -> Stage {
+            Stage::Scaled
+        }
+
+        fn moves_pixels(&self) -> bool {
+            true
+        }
+
+        fn apply(&mut self, pixels: Pixels<'_>) {
+            let screen = pixels.screen.clone();
+            let width = usize::from(pixels.width);
+            let screen_w = usize::from(screen.width());
+            let screen_h = usize::from(screen.height());
+
+            self.unbent.clear();
+            for y in screen.y.clone() {
+                let row_start = usize::from(y) * width;
+                self.unbent.extend_from_slice(&pixels.buffer[
+                    row_start + usize::from(screen.x.start)..row_start + usize::from(screen.x.end)
+                ]);
+            }
+
+            let amount = self.amount;
+            let unbent = &self.unbent;
+            for_each_pixel(pixels, |pixel, u, v| {
+                // The corners are at a squared distance of 2.
+                let scale = 1. + amount * (u * u + v * v) / 2.;
+                let (u, v) = (u * scale, v * scale);
+
+                *pixel = if u.abs() >= 1. || v.abs() >= 1. {
+                    0
+                } else {
+                    // Rounding can still take values just under 1 to the
+                    // width or height, so we clamp.
+                    let x = (((u + 1.) / 2. * screen_w as f32) as usize).min(screen_w - 1);
+                    let y = (((v + 1.) / 2. * screen_h as f32) as usize).min(screen_h - 1);
+
+                    unbent[y * screen_w + x]
+                };
+            });
+        }
+    }
+}
+
 /// Implements a 32 bit FNV-1a hash
 mod hash {
     use super::*;
@@ -65,7 +652,14 @@ mod hash {
         bytes(hash, &u16.to_ne_bytes());
     }
 
-    pub fn command(hash: &mut Cell, command: &Command) {
+    pub fn point(hash: &mut Cell, &Point { x, y, colour }: &Point) {
+        u16(hash, x.get().get());
+        u16(hash, y.get().get());
+        bytes(hash, &colour.to_ne_bytes());
+    }
+
+    /// `texture_version` is only used if the command draws from the texture.
+    pub fn command(hash: &mut Cell, command: &Command, texture_version: u32) {
         // Pattern match so we get a compile error if the fields change.
         let &Command {
             rect: Rect {
@@ -75,7 +669,23 @@ mod hash {
                 y_max,
             },
             sprite_xy,
-            colour_override,
+            modulation: Modulation {
+                replace,
+                tint,
+                alpha,
+            },
+            orientation,
+            blend_mode,
+            atlas,
+            // The points themselves are hashed into the cells they are in, by
+            // `point`, so that moving one only redraws around it.
+            points,
+            tile: command::Tile {
+                w: tile_w,
+                h: tile_h,
+                x_offset: tile_x_offset,
+                y_offset: tile_y_offset,
+            },
         } = command;
 
         u16(hash, x_min.get().get());
@@ -86,7 +696,31 @@ mod hash {
         u16(hash, sprite_xy.x.0);
         u16(hash, sprite_xy.y.0);
 
-        bytes(hash, &colour_override.to_ne_bytes());
+        u16(hash, tile_w);
+        u16(hash, tile_h);
+        u16(hash, tile_x_offset);
+        u16(hash, tile_y_offset);
+
+        match replace {
+            Some(colour) => {
+                byte(hash, 1);
+                bytes(hash, &colour.to_ne_bytes());
+            },
+            None => byte(hash, 0),
+        }
+        bytes(hash, &tint.to_ne_bytes());
+        byte(hash, alpha);
+
+        byte(hash, orientation.bits());
+
+        byte(hash, blend_mode as u8);
+
+        byte(hash, atlas.0);
+        if atlas == atlas::Id::TEXTURE {
+            bytes(hash, &texture_version.to_ne_bytes());
+        }
+
+        byte(hash, points.is_empty() as u8);
     }
 }
 
@@ -97,12 +731,116 @@ enum CurrentCells {
     B
 }
 
+/// The whole unscaled screen, as a `clip::Rect`.
+pub const UNSCALED_RECT: clip::Rect = clip::Rect {
+    x: 0..command::WIDTH,
+    y: 0..command::HEIGHT,
+};
+
+/// How the unscaled screen is fit into the frame buffer.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ScaleMode {
+    /// The largest whole multiple of the unscaled size that fits, with black
+    /// bars around it. Every texel ends up the same size.
+    #[default]
+    Integer,
+    /// As large as fits while keeping the aspect ratio, with black bars on
+    /// one pair of sides. Each pixel takes the colour of the nearest texel, so
+    /// some texels end up a pixel larger than others.
+    Fractional,
+    /// Fills the whole frame buffer, ignoring the aspect ratio. Each pixel
+    /// takes the colour of the nearest texel.
+    Stretch,
+    /// The same size as `Fractional`, but the pixels that straddle an edge
+    /// between two texels blend them, instead of picking one. This keeps the
+    /// texels looking evenly sized, without blurring them.
+    SharpBilinear,
+}
+
+impl ScaleMode {
+    pub const ALL: [Self; 4] = [
+        Self::Integer,
+        Self::Fractional,
+        Self::Stretch,
+        Self::SharpBilinear,
+    ];
+
+    /// The mode after this one in `ALL`, wrapping around at the end.
+    pub fn next(self) -> Self {
+        let i = Self::ALL.iter().position(|&mode| mode == self).unwrap_or(0);
+
+        Self::ALL[(i + 1) % Self::ALL.len()]
+    }
+
+    /// A stable name for the mode, suitable for saving in settings.
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Integer => "integer",
+            Self::Fractional => "fractional",
+            Self::Stretch => "stretch",
+            Self::SharpBilinear => "sharp-bilinear",
+        }
+    }
+
+    /// The inverse of `name`.
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|mode| mode.name() == name)
+    }
+
+    /// The part of a frame buffer of the given size that the unscaled screen
+    /// is drawn into. The size must be at least the unscaled size.
+    pub fn output_rect(self, (width, height): (clip::W, clip::H)) -> clip::Rect {
+        let (w, h): (clip::W, clip::H) = match self {
+            Self::Integer => {
+                let multiplier = core::cmp::min(
+                    width / command::WIDTH,
+                    height / command::HEIGHT,
+                );
+
+                (multiplier * command::WIDTH, multiplier * command::HEIGHT)
+            }
+            Self::Fractional | Self::SharpBilinear => {
+                let (width_32, height_32) = (u32::from(width), u32::from(height));
+                let (unscaled_w, unscaled_h) = (
+                    u32::from(command::WIDTH),
+                    u32::from(command::HEIGHT),
+                );
+
+                // Comparing `width / WIDTH` and `height / HEIGHT`, without
+                // dividing.
+                if width_32 * unscaled_h <= height_32 * unscaled_w {
+                    (width, (width_32 * unscaled_h / unscaled_w) as clip::H)
+                } else {
+                    ((height_32 * unscaled_w / unscaled_h) as clip::W, height)
+                }
+            }
+            Self::Stretch => (width, height),
+        };
+
+        let left_bar_width: clip::W = (width - w).div_ceil(2);
+        let top_bar_height: clip::H = (height - h).div_ceil(2);
+
+        clip::Rect {
+            x: left_bar_width..left_bar_width + w,
+            y: top_bar_height..top_bar_height + h,
+        }
+    }
+}
+
 pub struct FrameBuffer {
     pub buffer: Vec<ARGB>,
     pub unscaled_buffer: Box<[ARGB; command::LENGTH]>,
     pub width: clip::W,
     pub height: clip::H,
     pub cells: HashCells,
+    atlases: atlas::Atlases,
+    // The `Graphics::texture_version` of the texture that the texture atlas
+    // was last set from, if it was set by `render_graphics`.
+    graphics_texture_version: Option<u32>,
+    points: Vec<Point>,
+    scale_mode: ScaleMode,
+    pub upscaler: Upscaler,
+    filters: Vec<post::Slot>,
 }
 
 impl FrameBuffer {
@@ -115,54 +853,309 @@ impl FrameBuffer {
             width,
             height,
             cells: HashCells::default(),
+            atlases: <_>::default(),
+            graphics_texture_version: None,
+            points: Vec::new(),
+            scale_mode: ScaleMode::default(),
+            upscaler: Upscaler::default(),
+            filters: Vec::new(),
         }
     }
-}
-
-#[derive(Default)]
-pub struct HashCells {
-    current_cells: CurrentCells,
-    cells_a: hash::Cell,
-    cells_b: hash::Cell,
-}
 
-impl HashCells {
-    fn current_mut(&mut self) -> &mut hash::Cell {
-        match self.current_cells {
-            CurrentCells::A => &mut self.cells_a,
-            CurrentCells::B => &mut self.cells_b,
-        }
+    pub fn atlases(&self) -> &atlas::Atlases {
+        &self.atlases
     }
 
-    fn current_and_prev(&self) -> (&hash::Cell, &hash::Cell) {
-        match self.current_cells {
-            CurrentCells::A => (&self.cells_a, &self.cells_b),
-            CurrentCells::B => (&self.cells_b, &self.cells_a),
-        }
+    /// Sets the pixels that commands using `atlas::Id::TEXTURE` draw from on
+    /// the next render. The parts of the screen showing the texture are only
+    /// redrawn if its pixels changed.
+    pub fn set_texture(&mut self, texture: &platform_types::Texture) {
+        self.atlases.set_texture(texture);
+        self.graphics_texture_version = None;
     }
 
-    fn swap(&mut self) {
-        self.current_cells = match self.current_cells {
-            CurrentCells::A => CurrentCells::B,
-            CurrentCells::B => CurrentCells::A,
-        };
+    /// Sets the points that commands refer to with `Command::points` on the
+    /// next render.
+    pub fn set_points(&mut self, points: &[Point]) {
+        self.points.clear();
+        self.points.extend_from_slice(points);
     }
 
-    fn reset_then_hash_commands(
-        &mut self,
-        commands: &[Command],
-        (w, h): (u16, u16),
-    ) {
-        let cells = self.current_mut();
-        *cells = <_>::default();
+    /// Adds the atlas to the ones commands can refer to, and returns its id.
+    /// Returns `None` if the maximum number of atlases is already loaded.
+    pub fn load_atlas(&mut self, atlas: atlas::Atlas) -> Option<atlas::Id> {
+        let id = self.atlases.push(atlas)?;
 
-        hash::u16(cells, w);
-        hash::u16(cells, h);
+        // Commands may have already referred to this id, and been drawn as
+        // blank, so we need to redraw everything.
+        self.cells = HashCells::with_grid(self.cells.grid());
 
-        for command in commands {
-            hash::command(cells, command);
+        Some(id)
+    }
+
+    pub fn scale_mode(&self) -> ScaleMode {
+        self.scale_mode
+    }
+
+    /// Sets how the unscaled screen is fit into the buffer on the next render.
+    pub fn set_scale_mode(&mut self, scale_mode: ScaleMode) {
+        if self.scale_mode == scale_mode {
+            return
+        }
+        self.scale_mode = scale_mode;
+
+        // The bars may now be in different places, and every pixel between
+        // them may change, so we need to start over.
+        self.redraw_everything();
+    }
+
+    /// Adds the filter to the end of the ones run on each render, and returns
+    /// its id. Filters run in the order they were added.
+    pub fn add_filter(&mut self, filter: Box<dyn post::Filter>, enabled: bool) -> post::Id {
+        let id = post::Id(self.filters.len());
+
+        self.filters.push(post::Slot { filter, enabled });
+        if enabled {
+            self.redraw_everything();
+        }
+
+        id
+    }
+
+    pub fn filter_enabled(&self, id: post::Id) -> bool {
+        self.filters.get(id.0).is_some_and(|slot| slot.enabled)
+    }
+
+    /// Turns the filter on or off, from the next render onwards.
+    pub fn set_filter_enabled(&mut self, id: post::Id, enabled: bool) {
+        let Some(slot) = self.filters.get_mut(id.0) else { return };
+
+        if slot.enabled != enabled {
+            slot.enabled = enabled;
+            self.redraw_everything();
+        }
+    }
+
+    fn redraw_everything(&mut self) {
+        self.buffer.fill(0);
+        self.cells = HashCells::with_grid(self.cells.grid());
+    }
+}
+
+/// How many hash cells the unscaled screen is split into, in each direction.
+/// If the screen size does not divide evenly, some cells are a pixel larger
+/// than others.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CellGrid {
+    pub columns: u16,
+    pub rows: u16,
+}
+
+impl Default for CellGrid {
+    /// 8 by 8 cells, or fewer on screens smaller than that.
+    fn default() -> Self {
+        Self {
+            columns: core::cmp::min(8, command::WIDTH),
+            rows: core::cmp::min(8, command::HEIGHT),
+        }
+    }
+}
+
+impl CellGrid {
+    /// A single cell covering the whole screen. With this, any change causes a
+    /// redraw of everything.
+    pub const SINGLE: CellGrid = CellGrid { columns: 1, rows: 1 };
+
+    fn columns(self) -> u16 {
+        self.columns.clamp(1, command::WIDTH)
+    }
+
+    fn rows(self) -> u16 {
+        self.rows.clamp(1, command::HEIGHT)
+    }
+
+    fn len(self) -> usize {
+        usize::from(self.columns()) * usize::from(self.rows())
+    }
+
+    /// Where the `index`th of `count` cells starts, along a side `len` long.
+    /// Passing `count` gives the end of the last cell. Since `count` is at
+    /// most `len`, every cell is at least one pixel long.
+    fn edge(index: u16, count: u16, len: u16) -> u16 {
+        (u32::from(index) * u32::from(len) / u32::from(count)) as u16
+    }
+
+    /// The inverse of `edge`: which of `count` cells along a side `len` long
+    /// contains the pixel at `at`.
+    fn cell_at(at: u16, count: u16, len: u16) -> u16 {
+        let at = core::cmp::min(at, len - 1);
+
+        (((u32::from(at) + 1) * u32::from(count) - 1) / u32::from(len)) as u16
+    }
+
+    fn column_of(self, x: clip::X) -> u16 {
+        Self::cell_at(x, self.columns(), command::WIDTH)
+    }
+
+    fn row_of(self, y: clip::Y) -> u16 {
+        Self::cell_at(y, self.rows(), command::HEIGHT)
+    }
+
+    fn column_range(self, x_min: clip::X, x_max: clip::X) -> core::ops::RangeInclusive<u16> {
+        self.column_of(x_min)..=self.column_of(x_max)
+    }
+
+    fn row_range(self, y_min: clip::Y, y_max: clip::Y) -> core::ops::RangeInclusive<u16> {
+        self.row_of(y_min)..=self.row_of(y_max)
+    }
+
+    fn index(self, column: u16, row: u16) -> usize {
+        usize::from(row) * usize::from(self.columns()) + usize::from(column)
+    }
+
+    /// The index of the cell that contains the given pixel.
+    fn index_of(self, x: clip::X, y: clip::Y) -> usize {
+        self.index(self.column_of(x), self.row_of(y))
+    }
+
+    /// The part of the unscaled screen that the given cell covers.
+    fn rect(self, column: u16, row: u16) -> clip::Rect {
+        let (columns, rows) = (self.columns(), self.rows());
+
+        clip::Rect {
+            x: Self::edge(column, columns, command::WIDTH)
+                ..Self::edge(column + 1, columns, command::WIDTH),
+            y: Self::edge(row, rows, command::HEIGHT)
+                ..Self::edge(row + 1, rows, command::HEIGHT),
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct HashCells {
+    grid: CellGrid,
+    current_cells: CurrentCells,
+    cells_a: Vec<hash::Cell>,
+    cells_b: Vec<hash::Cell>,
+}
+
+impl HashCells {
+    pub fn with_grid(grid: CellGrid) -> Self {
+        Self {
+            grid,
+            ..<_>::default()
+        }
+    }
+
+    pub fn grid(&self) -> CellGrid {
+        self.grid
+    }
+
+    fn current_mut(&mut self) -> &mut Vec<hash::Cell> {
+        match self.current_cells {
+            CurrentCells::A => &mut self.cells_a,
+            CurrentCells::B => &mut self.cells_b,
+        }
+    }
+
+    fn current_and_prev(&self) -> (&[hash::Cell], &[hash::Cell]) {
+        match self.current_cells {
+            CurrentCells::A => (&self.cells_a, &self.cells_b),
+            CurrentCells::B => (&self.cells_b, &self.cells_a),
+        }
+    }
+
+    fn swap(&mut self) {
+        self.current_cells = match self.current_cells {
+            CurrentCells::A => CurrentCells::B,
+            CurrentCells::B => CurrentCells::A,
+        };
+    }
+
+    fn reset_then_hash_commands(
+        &mut self,
+        commands: &[Command],
+        points: &[Point],
+        (w, h): (u16, u16),
+        texture_version: u32,
+    ) {
+        let grid = self.grid;
+        let cells = self.current_mut();
+        cells.clear();
+        cells.resize(grid.len(), <_>::default());
+
+        for cell in cells.iter_mut() {
+            hash::u16(cell, w);
+            hash::u16(cell, h);
+        }
+
+        for command in commands {
+            let Rect { x_min, y_min, x_max, y_max } = command.rect;
+
+            for row in grid.row_range(y_min.into(), y_max.into()) {
+                for column in grid.column_range(x_min.into(), x_max.into()) {
+                    hash::command(
+                        &mut cells[grid.index(column, row)],
+                        command,
+                        texture_version,
+                    );
+                }
+            }
+
+            for point in points_of(command, points) {
+                hash::point(
+                    &mut cells[grid.index_of(point.x.into(), point.y.into())],
+                    point,
+                );
+            }
+        }
+    }
+
+    /// Returns the parts of the unscaled screen whose cells changed since the
+    /// last swap. Adjacent changed cells are merged into larger rects.
+    fn dirty_rects(&self) -> Vec<clip::Rect> {
+        let grid = self.grid;
+        let (cells, cells_prev) = self.current_and_prev();
+
+        let mut rects: Vec<clip::Rect> = Vec::new();
+        for row in 0..grid.rows() {
+            let mut run: Option<clip::Rect> = None;
+
+            for column in 0..grid.columns() {
+                let i = grid.index(column, row);
+                let is_dirty = cells.get(i) != cells_prev.get(i);
+
+                if is_dirty {
+                    let cell_rect = grid.rect(column, row);
+                    match &mut run {
+                        Some(r) => r.x.end = cell_rect.x.end,
+                        None => run = Some(cell_rect),
+                    }
+                } else if let Some(r) = run.take() {
+                    push_merged(&mut rects, r);
+                }
+            }
+
+            if let Some(r) = run {
+                push_merged(&mut rects, r);
+            }
+        }
+
+        rects
+    }
+}
+
+/// Pushes the rect, first merging it with an existing rect that it directly
+/// continues downward, if there is one.
+fn push_merged(rects: &mut Vec<clip::Rect>, rect: clip::Rect) {
+    for existing in rects.iter_mut() {
+        if existing.x == rect.x && existing.y.end == rect.y.start {
+            existing.y.end = rect.y.end;
+            return
         }
     }
+
+    rects.push(rect);
 }
 
 #[cfg(test)]
@@ -174,9 +1167,9 @@ mod reset_then_hash_commands_around_a_swap_produces_identical_current_and_prev_c
     fn on_the_empty_slice() {
         let mut h_c = HashCells::default();
 
-        h_c.reset_then_hash_commands(&[], (CELLS_W, CELLS_H));
+        h_c.reset_then_hash_commands(&[], &[], (CELLS_W, CELLS_H), 0);
         h_c.swap();
-        h_c.reset_then_hash_commands(&[], (CELLS_W, CELLS_H));
+        h_c.reset_then_hash_commands(&[], &[], (CELLS_W, CELLS_H), 0);
 
         let (current, prev) = h_c.current_and_prev();
 
@@ -199,9 +1192,9 @@ mod reset_then_hash_commands_around_a_swap_produces_identical_current_and_prev_c
 
         let commands = &[command];
 
-        h_c.reset_then_hash_commands(commands, (CELLS_W, CELLS_H));
+        h_c.reset_then_hash_commands(commands, &[], (CELLS_W, CELLS_H), 0);
         h_c.swap();
-        h_c.reset_then_hash_commands(commands, (CELLS_W, CELLS_H));
+        h_c.reset_then_hash_commands(commands, &[], (CELLS_W, CELLS_H), 0);
 
         let (current, prev) = h_c.current_and_prev();
 
@@ -209,376 +1202,899 @@ mod reset_then_hash_commands_around_a_swap_produces_identical_current_and_prev_c
     }
 }
 
-#[derive(Clone, Copy, PartialEq, Eq)]
-pub enum NeedsRedraw {
-    No,
-    Yes
-}
-
-#[cfg(all(
-    target_arch = "x86_64",
-    target_feature = "sse",
-    target_feature = "sse2",
-))]
-mod wide {
+#[cfg(test)]
+mod cell_grid_works {
     use super::*;
-    pub const WIDTH: unscaled::Inner = 4;
 
-    /// SAFETY: The pointers produced by the code generated by these macros must
-    /// be valid to read 128 bytes from.
-    /// We omit unsafe in the macro's code in order to encourage the user to notice
-    /// the invariants they are expected to uphold.
-    #[macro_export]
-    macro_rules! _load {
-        (
-            $addr: expr $(,)?
-        ) => ({
-            use core::arch::x86_64::__m128i as V;
+    /// None of these divide the default screen size evenly.
+    const UNEVEN_GRIDS: [CellGrid; 4] = [
+        CellGrid { columns: 1, rows: 200 },
+        CellGrid { columns: 7, rows: 3 },
+        CellGrid { columns: 13, rows: 11 },
+        CellGrid { columns: u16::MAX, rows: u16::MAX },
+    ];
 
-            let addr: *const _ = $addr;
-            core::arch::x86_64::_mm_loadu_si128(
-                addr as *const V
-            )
-        });
-        (
-            $addr: expr,
-            $index: expr $(,)?
-        ) => ({
-            use core::arch::x86_64::__m128i as V;
+    #[test]
+    fn the_default_grid_fits_on_the_screen() {
+        let grid = CellGrid::default();
 
-            let addr: *const _ = $addr;
-            core::arch::x86_64::_mm_loadu_si128(
-                addr.add($index) as *const V
-            )
-        });
+        assert!(grid.columns <= command::WIDTH);
+        assert!(grid.rows <= command::HEIGHT);
     }
-    pub use _load as load;
-
-    /// SAFETY: The pointers produced by the code generated by these macros must
-    /// be valid to write 128 bytes to.
-    /// We omit unsafe in the macro's code in order to encourage the user to notice
-    /// the invariants they are expected to uphold.
-    #[macro_export]
-    macro_rules! _store {
-        (
-            $vector: expr,
-            $addr: expr $(,)?
-        ) => ({
-            use core::arch::x86_64::__m128i as V;
 
-            let vector: V = $vector;
-            let addr: *mut _ = $addr;
-            core::arch::x86_64::_mm_storeu_si128(
-                addr as *mut V,
-                vector,
-            )
-        });
-        (
-            $vector: expr,
-            $addr: expr,
-            $index: expr $(,)?
-        ) => ({
-            use core::arch::x86_64::__m128i as V;
-            let vector: V = $vector;
+    #[test]
+    fn cells_cover_the_screen_without_gaps_or_overlaps_on_these_uneven_grids() {
+        for grid in UNEVEN_GRIDS {
+            for row in 0..grid.rows() {
+                for column in 0..grid.columns() {
+                    let rect = grid.rect(column, row);
+                    assert!(rect.x.start < rect.x.end, "{grid:?} {rect:?}");
+                    assert!(rect.y.start < rect.y.end, "{grid:?} {rect:?}");
+
+                    if column + 1 < grid.columns() {
+                        assert_eq!(rect.x.end, grid.rect(column + 1, row).x.start);
+                    }
+                    if row + 1 < grid.rows() {
+                        assert_eq!(rect.y.end, grid.rect(column, row + 1).y.start);
+                    }
+                }
+            }
 
-            let addr: *mut _ = $addr;
-            core::arch::x86_64::_mm_storeu_si128(
-                addr.add($index) as *mut V,
-                vector,
-            )
-        });
+            assert_eq!(grid.rect(0, 0).x.start, 0);
+            assert_eq!(grid.rect(0, 0).y.start, 0);
+            assert_eq!(grid.rect(grid.columns() - 1, 0).x.end, command::WIDTH);
+            assert_eq!(grid.rect(0, grid.rows() - 1).y.end, command::HEIGHT);
+        }
     }
-    pub use _store as store;
 
-    // SAFETY: This operation is only unsafe because it uses intrinsics, and we wrap
-    // this module with `cfg`, so this is safe to use in all cases where it compiles.
-    #[macro_export]
-    macro_rules! _pick_via_mask {
-        (
-            $zero_case: expr,
-            $one_case: expr,
-            $mask: expr $(,)?
-        ) => ({
-            let mask = $mask;
-            #[allow(unused_unsafe)]
-            unsafe {
-                core::arch::x86_64::_mm_or_si128(
-                    core::arch::x86_64::_mm_and_si128(
-                        mask,
-                        $one_case,
-                    ),
-                    core::arch::x86_64::_mm_andnot_si128(
-                        mask,
-                        $zero_case,
-                    )
-                )
+    #[test]
+    fn each_pixel_is_in_the_cell_it_is_hashed_into_on_these_uneven_grids() {
+        for grid in UNEVEN_GRIDS {
+            for x in 0..command::WIDTH {
+                assert!(grid.rect(grid.column_of(x), 0).x.contains(&x), "{grid:?} {x}");
             }
-        });
-    }
-    pub use _pick_via_mask as pick_via_mask;
-
-    #[macro_export]
-    macro_rules! _i32x4 {
-        (
-            $e0: expr,
-            $e1: expr,
-            $e2: expr,
-            $e3: expr $(,)?
-        ) => ({#[allow(unused_unsafe)]
-            unsafe {
-                core::arch::x86_64::_mm_set_epi32(
-                    $e3,
-                    $e2,
-                    $e1,
-                    $e0,
-                )
+            for y in 0..command::HEIGHT {
+                assert!(grid.rect(0, grid.row_of(y)).y.contains(&y), "{grid:?} {y}");
             }
-        });
+        }
     }
-    pub use _i32x4 as i32x4;
 
-    #[macro_export]
-    macro_rules! _and {
-        (
-            $a: expr,
-            $b: expr $(,)?
-        ) => ({#[allow(unused_unsafe)]
-            unsafe {
-                core::arch::x86_64::_mm_and_si128($a, $b)
-            }
-        });
-    }
-    pub use _and as and;
+    #[test]
+    fn rendering_with_dirty_rects_on_these_uneven_grids_matches_a_full_redraw() {
+        let mut rng = xs::from_seed([0xCE; 16]);
+        let size = (command::WIDTH, command::HEIGHT);
 
-    #[macro_export]
-    macro_rules! _and_not {
-        (
-            $just_anded: expr,
-            $notted: expr $(,)?
-        ) => ({#[allow(unused_unsafe)]
-            unsafe {
-                // "Compute the bitwise NOT of 128 bits (representing integer data) in
-                // `a` and then AND with `b`, and store the result in dst."
-                // I prefer to not the second operand, so `a` and `b` are switched here.
-                core::arch::x86_64::_mm_andnot_si128($notted, $just_anded)
-            }
-        });
-    }
-    pub use _and_not as and_not;
+        for grid in UNEVEN_GRIDS {
+            let commands = arb::commands(&mut rng);
+            let mut frame_buffer = FrameBuffer::from_size(size);
+            frame_buffer.cells = HashCells::with_grid(grid);
+            let _ = render(&mut frame_buffer, &commands);
 
-    #[macro_export]
-    macro_rules! _or {
-        (
-            $a: expr,
-            $b: expr $(,)?
-        ) => ({#[allow(unused_unsafe)]
-            unsafe {
-                core::arch::x86_64::_mm_or_si128($a, $b)
-            }
-        });
+            let mut changed = commands.clone();
+            let i = xs::range(&mut rng, 0..changed.len() as u32) as usize;
+            changed[i] = arb::command(&mut rng);
+
+            let _ = render(&mut frame_buffer, &changed);
+
+            assert_eq!(frame_buffer.buffer, render_headless(&changed, size).buffer, "{grid:?}");
+        }
     }
-    pub use _or as or;
+}
 
-    #[macro_export]
-    macro_rules! _left_shift_32 {
-        (
-            $a: expr,
-            $imm8: expr $(,)?
-        ) => ({#[allow(unused_unsafe)]
-            unsafe {
-                core::arch::x86_64::_mm_slli_epi32($a, $imm8)
-            }
-        });
+#[cfg(test)]
+mod dirty_rects_works {
+    use super::*;
+
+    fn point_command(x: unscaled::Inner, y: unscaled::Inner) -> Command {
+        Command {
+            rect: command::Rect::from_unscaled(unscaled::Rect {
+                x: unscaled::X(x),
+                y: unscaled::Y(y),
+                w: unscaled::W(1),
+                h: unscaled::H(1),
+            }),
+            modulation: Modulation::replace(colours::RED),
+            ..<_>::default()
+        }
     }
-    pub use _left_shift_32 as left_shift_32;
 
-    #[macro_export]
-    macro_rules! _right_shift_32 {
-        (
-            $a: expr,
-            $imm8: expr $(,)?
-        ) => ({#[allow(unused_unsafe)]
-            unsafe {
-                core::arch::x86_64::_mm_srli_epi32($a, $imm8)
-            }
-        });
+    const SIZE: (u16, u16) = (command::WIDTH, command::HEIGHT);
+
+    #[test]
+    fn when_nothing_was_hashed_before() {
+        let mut h_c = HashCells::with_grid(CellGrid { columns: 4, rows: 2 });
+
+        h_c.reset_then_hash_commands(&[], &[], SIZE, 0);
+
+        assert_eq!(h_c.dirty_rects(), vec![UNSCALED_RECT]);
     }
-    pub use _right_shift_32 as right_shift_32;
 
-    #[macro_export]
-    macro_rules! _f32_to_u32 {
-        (
-            $a: expr $(,)?
-        ) => ({#[allow(unused_unsafe)]
-            unsafe {
-                core::arch::x86_64::_mm_cvtps_epi32($a)
-            }
-        });
+    #[test]
+    fn when_a_single_point_moves_within_a_cell() {
+        let grid = CellGrid { columns: 4, rows: 2 };
+        let mut h_c = HashCells::with_grid(grid);
+
+        h_c.reset_then_hash_commands(&[point_command(1, 1)], &[], SIZE, 0);
+        h_c.swap();
+        h_c.reset_then_hash_commands(&[point_command(2, 2)], &[], SIZE, 0);
+
+        assert_eq!(h_c.dirty_rects(), vec![grid.rect(0, 0)]);
     }
-    pub use _f32_to_u32 as f32_to_u32;
 
-    #[macro_export]
-    macro_rules! _u32_to_f32 {
-        (
-            $a: expr $(,)?
-        ) => ({#[allow(unused_unsafe)]
-            unsafe {
-                core::arch::x86_64::_mm_cvtepi32_ps($a)
-            }
-        });
+    #[test]
+    fn when_a_single_point_moves_to_a_non_adjacent_cell() {
+        let grid = CellGrid { columns: 4, rows: 2 };
+        let mut h_c = HashCells::with_grid(grid);
+
+        h_c.reset_then_hash_commands(&[point_command(1, 1)], &[], SIZE, 0);
+        h_c.swap();
+        h_c.reset_then_hash_commands(
+            &[point_command(command::WIDTH - 1, command::HEIGHT - 1)],
+            &[],
+            SIZE,
+            0,
+        );
+
+        assert_eq!(h_c.dirty_rects(), vec![grid.rect(0, 0), grid.rect(3, 1)]);
     }
-    pub use _u32_to_f32 as u32_to_f32;
 
-    #[macro_export]
-    macro_rules! _i32 {
-        (
-            $a: expr $(,)?
-        ) => ({#[allow(unused_unsafe)]
-            unsafe {
-                core::arch::x86_64::_mm_set1_epi32($a)
-            }
-        });
+    #[test]
+    fn when_a_column_of_cells_changes() {
+        let grid = CellGrid { columns: 4, rows: 2 };
+        let mut h_c = HashCells::with_grid(grid);
+
+        h_c.reset_then_hash_commands(&[], &[], SIZE, 0);
+        h_c.swap();
+        h_c.reset_then_hash_commands(
+            &[point_command(1, 1), point_command(1, command::HEIGHT - 1)],
+            &[],
+            SIZE,
+            0,
+        );
+
+        assert_eq!(
+            h_c.dirty_rects(),
+            vec![clip::Rect {
+                x: grid.rect(0, 0).x,
+                y: 0..command::HEIGHT,
+            }]
+        );
     }
-    pub use _i32 as i32;
 
-    #[macro_export]
-    macro_rules! _f32 {
-        (
-            $a: expr $(,)?
-        ) => ({#[allow(unused_unsafe)]
-            unsafe {
-                core::arch::x86_64::_mm_set_ps1($a)
-            }
-        });
+    #[test]
+    fn when_one_point_of_a_full_screen_points_command_moves() {
+        let grid = CellGrid { columns: 4, rows: 2 };
+        let mut h_c = HashCells::with_grid(grid);
+
+        let points_command = Command {
+            rect: command::Rect::from_unscaled(unscaled::Rect {
+                x: unscaled::X(0),
+                y: unscaled::Y(0),
+                w: command::WIDTH_W,
+                h: command::HEIGHT_H,
+            }),
+            points: command::Points { start: 0, end: 2 },
+            ..<_>::default()
+        };
+        let point = |x, y| Point {
+            x: command::X::clipped_inner(x),
+            y: command::Y::clipped_inner(y),
+            colour: colours::RED,
+        };
+
+        h_c.reset_then_hash_commands(&[points_command], &[point(1, 1), point(300, 300)], SIZE, 0);
+        h_c.swap();
+        h_c.reset_then_hash_commands(&[points_command], &[point(2, 2), point(300, 300)], SIZE, 0);
+
+        assert_eq!(h_c.dirty_rects(), vec![grid.rect(0, 0)]);
     }
-    pub use _f32 as f32;
+}
 
-    #[macro_export]
-    macro_rules! _add_f32 {
-        (
-            $a: expr,
-            $b: expr $(,)?
-        ) => ({#[allow(unused_unsafe)]
-            unsafe {
-                core::arch::x86_64::_mm_add_ps($a, $b)
-            }
-        });
+#[cfg(test)]
+mod render_with_dirty_rects {
+    use super::*;
+    use xs::Xs;
+
+    const SIZE: (clip::W, clip::H) = (command::WIDTH * 2 + 3, command::HEIGHT * 2 + 1);
+
+    fn moved(rng: &mut Xs, commands: &[Command]) -> Vec<Command> {
+        let mut output = commands.to_vec();
+        let i = xs::range(rng, 0..output.len() as u32) as usize;
+        output[i] = arb::command(rng);
+        output
     }
-    pub use _add_f32 as add_f32;
 
-    #[macro_export]
-    macro_rules! _add_i32 {
-        (
-            $a: expr,
-            $b: expr $(,)?
-        ) => ({#[allow(unused_unsafe)]
-            unsafe {
-                core::arch::x86_64::_mm_add_epi32($a, $b)
-            }
-        });
+    #[test]
+    fn does_not_redraw_if_nothing_changed() {
+        let commands = arb::commands(&mut xs::from_seed([0xD1; 16]));
+
+        let mut frame_buffer = render_headless(&commands, SIZE);
+
+        assert_eq!(render(&mut frame_buffer, &commands), NeedsRedraw::No);
     }
-    pub use _add_i32 as add_i32;
 
-    #[macro_export]
-    macro_rules! _sub {
-        (
-            $a: expr,
-            $b: expr $(,)?
-        ) => ({#[allow(unused_unsafe)]
-            unsafe {
-                core::arch::x86_64::_mm_sub_ps($a, $b)
-            }
-        });
+    #[test]
+    fn produces_the_same_pixels_as_a_full_redraw() {
+        let mut rng = xs::from_seed([0xD2; 16]);
+
+        for _ in 0..16 {
+            let commands = arb::commands(&mut rng);
+            let mut frame_buffer = render_headless(&commands, SIZE);
+
+            let changed = moved(&mut rng, &commands);
+
+            render(&mut frame_buffer, &changed);
+
+            let expected = render_headless(&changed, SIZE);
+
+            assert_eq!(expected.buffer, frame_buffer.buffer);
+        }
     }
-    pub use _sub as sub;
+}
 
-    #[macro_export]
-    macro_rules! _mul {
-        (
-            $a: expr,
-            $b: expr $(,)?
-        ) => ({#[allow(unused_unsafe)]
-            unsafe {
-                core::arch::x86_64::_mm_mul_ps($a, $b)
-            }
-        });
+#[cfg(test)]
+mod scale_modes_work {
+    use super::*;
+
+    fn rendered_with(
+        scale_mode: ScaleMode,
+        commands: &[Command],
+        size: (clip::W, clip::H),
+    ) -> FrameBuffer {
+        let mut frame_buffer = FrameBuffer::from_size(size);
+        frame_buffer.set_scale_mode(scale_mode);
+        let _ = render(&mut frame_buffer, commands);
+        frame_buffer
     }
-    pub use _mul as mul;
 
-    #[macro_export]
-    macro_rules! _recip {
-        (
-            $a: expr $(,)?
-        ) => ({#[allow(unused_unsafe)]
-            unsafe {
-                core::arch::x86_64::_mm_rcp_ps($a)
-            }
-        });
+    /// A command that covers the unscaled screen left of `width` in white.
+    fn white_left_of(width: u16) -> [Command; 1] {
+        [Command {
+            rect: Rect::from_unscaled(unscaled::Rect {
+                x: unscaled::X(0),
+                y: unscaled::Y(0),
+                w: unscaled::W(width),
+                h: unscaled::H(command::HEIGHT),
+            }),
+            atlas: atlas::Id(1),
+            tile: command::Tile { w: 1, h: 1, ..<_>::default() },
+            ..<_>::default()
+        }]
     }
-    pub use _recip as recip;
 
-    #[macro_export]
-    macro_rules! _sqrt {
-        (
-            $a: expr $(,)?
-        ) => ({#[allow(unused_unsafe)]
-            unsafe {
-                core::arch::x86_64::_mm_sqrt_ps($a)
-            }
-        });
+    fn white_pixel() -> atlas::Atlas {
+        atlas::Atlas::from_pixels(1, 1, vec![colours::WHITE]).unwrap()
     }
-    pub use _sqrt as sqrt;
 
-    #[macro_export]
-    macro_rules! _lt_mask_32 {
-        (
-            $lhs: expr,
-            $rhs: expr $(,)?
-        ) => ({#[allow(unused_unsafe)]
-            unsafe {
-                core::arch::x86_64::_mm_cmplt_epi32($lhs, $rhs)
-            }
-        });
+    fn red(argb: ARGB) -> ARGB {
+        (argb >> 16) & 0xFF
     }
-    pub use _lt_mask_32 as lt_mask_32;
 
-    #[macro_export]
-    macro_rules! _eq_mask_u32 {
-        (
-            $lhs: expr,
-            $rhs: expr $(,)?
-        ) => ({#[allow(unused_unsafe)]
-            unsafe {
-                core::arch::x86_64::_mm_cmpeq_epi32($lhs, $rhs)
-            }
-        });
+    #[test]
+    fn names_round_trip() {
+        for mode in ScaleMode::ALL {
+            assert_eq!(ScaleMode::from_name(mode.name()), Some(mode));
+        }
+
+        assert_eq!(ScaleMode::from_name(""), None);
     }
-    pub use _eq_mask_u32 as eq_mask_u32;
 
-    #[macro_export]
-    macro_rules! _gt_mask_32 {
-        (
-            $lhs: expr,
-            $rhs: expr $(,)?
-        ) => ({#[allow(unused_unsafe)]
-            unsafe {
-                core::arch::x86_64::_mm_cmpgt_epi32($lhs, $rhs)
-            }
-        });
+    #[test]
+    fn next_visits_every_mode() {
+        let mut mode = ScaleMode::default();
+
+        for expected in ScaleMode::ALL.into_iter().cycle().skip(1).take(ScaleMode::ALL.len()) {
+            mode = mode.next();
+            assert_eq!(mode, expected);
+        }
     }
-    pub use _gt_mask_32 as gt_mask_32;
-}
 
-#[cfg(all(
-    target_arch = "wasm32",
-    target_feature = "simd128",
-))]
-mod wide {
-    use super::*;
-    pub const WIDTH: unscaled::Inner = 4;
+    #[test]
+    fn output_rect_on_these_examples() {
+        let size = (command::WIDTH * 3, command::HEIGHT * 2);
 
-    /// SAFETY: The pointers produced by the code generated by these macros must
-    /// be valid to read 128 bytes from.
+        assert_eq!(
+            ScaleMode::Integer.output_rect(size),
+            clip::Rect {
+                x: command::WIDTH.div_ceil(2)..command::WIDTH.div_ceil(2) + command::WIDTH * 2,
+                y: 0..command::HEIGHT * 2,
+            },
+        );
+        assert_eq!(
+            ScaleMode::Fractional.output_rect(size),
+            ScaleMode::Integer.output_rect(size),
+        );
+        assert_eq!(
+            ScaleMode::Stretch.output_rect(size),
+            clip::Rect { x: 0..size.0, y: 0..size.1 },
+        );
+
+        let taller = (command::WIDTH * 3 / 2, command::HEIGHT * 2);
+        let top_bar_height = (taller.1 - command::HEIGHT * 3 / 2).div_ceil(2);
+
+        assert_eq!(
+            ScaleMode::Fractional.output_rect(taller),
+            clip::Rect {
+                x: 0..taller.0,
+                y: top_bar_height..top_bar_height + command::HEIGHT * 3 / 2,
+            },
+        );
+    }
+
+    #[test]
+    fn every_mode_matches_integer_at_whole_multiples() {
+        let commands = arb::commands(&mut xs::from_seed([0x5C; 16]));
+        let size = (command::WIDTH * 2, command::HEIGHT * 2);
+
+        let expected = rendered_with(ScaleMode::Integer, &commands, size);
+
+        for mode in ScaleMode::ALL {
+            assert_eq!(
+                rendered_with(mode, &commands, size).buffer,
+                expected.buffer,
+                "{mode:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn fractional_picks_the_nearest_texel() {
+        let commands = arb::commands(&mut xs::from_seed([0x5D; 16]));
+        let size = (command::WIDTH * 3 / 2, command::HEIGHT * 3 / 2);
+
+        let frame_buffer = rendered_with(ScaleMode::Fractional, &commands, size);
+        let output_rect = ScaleMode::Fractional.output_rect(size);
+
+        let scale = f32::from(output_rect.width()) / f32::from(command::WIDTH);
+        for y in output_rect.y.clone() {
+            for x in output_rect.x.clone() {
+                let src_x = ((f32::from(x - output_rect.x.start) + 0.5) / scale) as usize;
+                let src_y = ((f32::from(y - output_rect.y.start) + 0.5) / scale) as usize;
+
+                assert_eq!(
+                    frame_buffer.buffer[usize::from(y) * usize::from(size.0) + usize::from(x)],
+                    frame_buffer.unscaled_buffer[src_y * usize::from(command::WIDTH) + src_x],
+                    "{x}, {y}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn sharp_bilinear_blends_only_the_pixels_on_texel_edges() {
+        // An odd width puts the edge in the middle of a pixel at 1.5 times
+        // scale.
+        let edge = (command::WIDTH / 2) | 1;
+        let commands = white_left_of(edge);
+        let size = (command::WIDTH * 3 / 2, command::HEIGHT * 3 / 2);
+
+        let rendered = |scale_mode| {
+            let mut frame_buffer = FrameBuffer::from_size(size);
+            frame_buffer.load_atlas(white_pixel()).unwrap();
+            frame_buffer.set_scale_mode(scale_mode);
+            let _ = render(&mut frame_buffer, &commands);
+            frame_buffer
+        };
+
+        let nearest = rendered(ScaleMode::Fractional);
+        let sharp = rendered(ScaleMode::SharpBilinear);
+
+        let row = usize::from(size.1 / 2) * usize::from(size.0);
+        let edge_x = row + usize::from(edge) * 3 / 2;
+
+        for x in row..row + usize::from(size.0) {
+            if x == edge_x {
+                assert!(red(sharp.buffer[x]) > red(colours::BLACK), "{x}");
+                assert!(red(sharp.buffer[x]) < red(colours::WHITE), "{x}");
+            } else {
+                assert_eq!(sharp.buffer[x], nearest.buffer[x], "{x}");
+            }
+        }
+    }
+
+    #[test]
+    fn with_dirty_rects_produces_the_same_pixels_as_a_full_redraw() {
+        let mut rng = xs::from_seed([0x5E; 16]);
+        let size = (command::WIDTH * 5 / 3, command::HEIGHT * 7 / 4);
+
+        for mode in ScaleMode::ALL {
+            for _ in 0..4 {
+                let commands = arb::commands(&mut rng);
+                let mut frame_buffer = rendered_with(mode, &commands, size);
+
+                let mut changed = commands.clone();
+                let i = xs::range(&mut rng, 0..changed.len() as u32) as usize;
+                changed[i] = arb::command(&mut rng);
+
+                render(&mut frame_buffer, &changed);
+
+                let expected = rendered_with(mode, &changed, size);
+
+                assert_eq!(expected.buffer, frame_buffer.buffer, "{mode:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn set_scale_mode_redraws_everything() {
+        let commands = arb::commands(&mut xs::from_seed([0x5F; 16]));
+        let size = (command::WIDTH * 5 / 3, command::HEIGHT * 7 / 4);
+
+        let mut frame_buffer = rendered_with(ScaleMode::Integer, &commands, size);
+
+        frame_buffer.set_scale_mode(ScaleMode::Stretch);
+        assert_ne!(render(&mut frame_buffer, &commands), NeedsRedraw::No);
+        assert_eq!(
+            frame_buffer.buffer,
+            rendered_with(ScaleMode::Stretch, &commands, size).buffer
+        );
+
+        frame_buffer.set_scale_mode(ScaleMode::Integer);
+        assert_ne!(render(&mut frame_buffer, &commands), NeedsRedraw::No);
+        assert_eq!(
+            frame_buffer.buffer,
+            rendered_with(ScaleMode::Integer, &commands, size).buffer
+        );
+    }
+}
+
+#[cfg(test)]
+mod upscalers_work {
+    use super::*;
+
+    const THREADED: Upscaler = Upscaler::Threaded(
+        match core::num::NonZeroUsize::new(3) {
+            Some(n) => n,
+            None => unreachable!(),
+        }
+    );
+
+    fn rendered_with(
+        upscaler: Upscaler,
+        commands: &[Command],
+        size: (clip::W, clip::H),
+    ) -> FrameBuffer {
+        let mut frame_buffer = FrameBuffer::from_size(size);
+        frame_buffer.upscaler = upscaler;
+        let _ = render(&mut frame_buffer, commands);
+        frame_buffer
+    }
+
+    #[test]
+    fn producing_the_same_pixels_as_scalar_at_each_multiplier() {
+        let mut rng = xs::from_seed([0x05; 16]);
+
+        for multiplier in 1..=6 {
+            let commands = arb::commands(&mut rng);
+            // Odd sized bars, to check the offsets.
+            let size = (command::WIDTH * multiplier + 3, command::HEIGHT * multiplier + 1);
+
+            let expected = rendered_with(Upscaler::Scalar, &commands, size);
+
+            for upscaler in [Upscaler::Wide, THREADED] {
+                assert_eq!(
+                    rendered_with(upscaler, &commands, size).buffer,
+                    expected.buffer,
+                    "{upscaler:?} at {multiplier}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn fill_wide_producing_the_same_pixels_as_fill_scalar_on_rows_of_each_length() {
+        for multiplier in 1..=6 {
+            for len in 0..=9 {
+                let src: Vec<ARGB> = (0..len).map(|i| 0xFF00_0000 | i).collect();
+                let mut expected = vec![0; src.len() * multiplier];
+                let mut actual = expected.clone();
+
+                fill_scalar(&src, &mut expected, multiplier);
+                fill_wide(&src, &mut actual, multiplier);
+
+                assert_eq!(actual, expected, "{len} pixels at {multiplier}");
+            }
+        }
+    }
+
+    #[test]
+    fn threaded_with_dirty_rects_produces_the_same_pixels_as_a_full_redraw() {
+        let mut rng = xs::from_seed([0x06; 16]);
+        let size = (command::WIDTH * 4, command::HEIGHT * 4);
+
+        for _ in 0..4 {
+            let commands = arb::commands(&mut rng);
+            let mut frame_buffer = rendered_with(THREADED, &commands, size);
+
+            let mut changed = commands.clone();
+            let i = xs::range(&mut rng, 0..changed.len() as u32) as usize;
+            changed[i] = arb::command(&mut rng);
+
+            render(&mut frame_buffer, &changed);
+
+            assert_eq!(
+                frame_buffer.buffer,
+                rendered_with(Upscaler::Scalar, &changed, size).buffer
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod post_processing_works {
+    use super::*;
+    use platform_types::PALETTE;
+
+    const SIZE: (clip::W, clip::H) = (command::WIDTH * 2 + 3, command::HEIGHT * 2 + 1);
+
+    fn rendered_with(
+        filters: Vec<Box<dyn post::Filter>>,
+        commands: &[Command],
+    ) -> FrameBuffer {
+        let mut frame_buffer = FrameBuffer::from_size(SIZE);
+        for filter in filters {
+            frame_buffer.add_filter(filter, true);
+        }
+        let _ = render(&mut frame_buffer, commands);
+        frame_buffer
+    }
+
+    fn channels(argb: ARGB) -> [ARGB; 3] {
+        [(argb >> 16) & 0xFF, (argb >> 8) & 0xFF, argb & 0xFF]
+    }
+
+    fn is_no_brighter(filtered: ARGB, unfiltered: ARGB) -> bool {
+        channels(filtered).into_iter()
+            .zip(channels(unfiltered))
+            .all(|(f, u)| f <= u)
+    }
+
+    #[test]
+    fn quantise_leaves_palette_colours_alone() {
+        for colour in PALETTE {
+            assert_eq!(post::Quantise::nearest(colour), colour);
+        }
+    }
+
+    #[test]
+    fn quantise_leaves_only_palette_colours() {
+        let commands = arb::commands(&mut xs::from_seed([0x9A; 16]));
+
+        let frame_buffer = rendered_with(vec![Box::new(post::Quantise)], &commands);
+
+        for &pixel in frame_buffer.unscaled_buffer.iter() {
+            assert!(
+                PALETTE.iter().any(|&colour| channels(colour) == channels(pixel)),
+                "{pixel:08X}"
+            );
+        }
+    }
+
+    #[test]
+    fn scanlines_darken_only_the_bottom_half_of_each_texel_row() {
+        let commands = arb::commands(&mut xs::from_seed([0x9B; 16]));
+
+        let unfiltered = rendered_with(vec![], &commands);
+        let filtered = rendered_with(vec![Box::new(post::Scanlines::default())], &commands);
+
+        let screen = ScaleMode::Integer.output_rect(SIZE);
+        for y in screen.y.clone() {
+            let row_start = usize::from(y) * usize::from(SIZE.0);
+            let row = row_start + usize::from(screen.x.start)..row_start + usize::from(screen.x.end);
+
+            if (y - screen.y.start).is_multiple_of(2) {
+                assert_eq!(filtered.buffer[row.clone()], unfiltered.buffer[row], "{y}");
+            } else {
+                for i in row {
+                    assert!(is_no_brighter(filtered.buffer[i], unfiltered.buffer[i]), "{y}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn shadow_mask_keeps_one_channel_per_column() {
+        let commands = arb::commands(&mut xs::from_seed([0x9C; 16]));
+
+        let unfiltered = rendered_with(vec![], &commands);
+        let filtered = rendered_with(vec![Box::new(post::ShadowMask::default())], &commands);
+
+        let screen = ScaleMode::Integer.output_rect(SIZE);
+        for y in screen.y.clone() {
+            for x in screen.x.clone() {
+                let i = usize::from(y) * usize::from(SIZE.0) + usize::from(x);
+                let kept = usize::from((x - screen.x.start) % 3);
+
+                assert_eq!(
+                    channels(filtered.buffer[i])[kept],
+                    channels(unfiltered.buffer[i])[kept],
+                );
+                assert!(is_no_brighter(filtered.buffer[i], unfiltered.buffer[i]));
+            }
+        }
+    }
+
+    #[test]
+    fn vignette_darkens_the_corners_more_than_the_centre() {
+        // With no commands, the whole screen is the background colour.
+        let filtered = rendered_with(vec![Box::new(post::Vignette::default())], &[]);
+
+        let screen = ScaleMode::Integer.output_rect(SIZE);
+        let at = |x: clip::X, y: clip::Y| {
+            filtered.buffer[usize::from(y) * usize::from(SIZE.0) + usize::from(x)]
+        };
+        let centre = at(
+            (screen.x.start + screen.x.end) / 2,
+            (screen.y.start + screen.y.end) / 2,
+        );
+        let corner = at(screen.x.start, screen.y.start);
+
+        assert_eq!(centre, colours::BLACK);
+        assert!(channels(corner)[0] < channels(centre)[0]);
+    }
+
+    #[test]
+    fn curvature_blanks_the_corners_but_not_the_centre() {
+        let commands = arb::commands(&mut xs::from_seed([0x9D; 16]));
+
+        let unfiltered = rendered_with(vec![], &commands);
+        let filtered = rendered_with(vec![Box::new(post::Curvature::default())], &commands);
+
+        let screen = ScaleMode::Integer.output_rect(SIZE);
+        let index = |x: clip::X, y: clip::Y| usize::from(y) * usize::from(SIZE.0) + usize::from(x);
+
+        let centre = index(
+            (screen.x.start + screen.x.end) / 2,
+            (screen.y.start + screen.y.end) / 2,
+        );
+        assert_eq!(filtered.buffer[centre], unfiltered.buffer[centre]);
+
+        for (x, y) in [
+            (screen.x.start, screen.y.start),
+            (screen.x.end - 1, screen.y.start),
+            (screen.x.start, screen.y.end - 1),
+            (screen.x.end - 1, screen.y.end - 1),
+        ] {
+            assert_eq!(filtered.buffer[index(x, y)], 0);
+        }
+    }
+
+    #[test]
+    fn curvature_stays_inside_the_screen_at_any_amount() {
+        use post::Filter;
+
+        // On a screen two pixels across, the outer pixels are taken from just
+        // inside the edge with an amount just under 4.
+        let just_under_4 = (1..64).map(|i| f32::from_bits(4f32.to_bits() - i));
+        let amounts = [0., 0.1, 1., 4., f32::MAX, f32::INFINITY, -1., f32::MIN, f32::NAN];
+
+        for amount in amounts.into_iter().chain(just_under_4) {
+            for (w, h) in [(1, 1), (2, 2), (2, 3), (3, 2), (5, 7)] {
+                let rect = clip::Rect { x: 0..w, y: 0..h };
+                let mut buffer = vec![colours::WHITE; usize::from(w) * usize::from(h)];
+
+                post::Curvature::new(amount).apply(post::Pixels {
+                    buffer: &mut buffer,
+                    width: w,
+                    screen: rect.clone(),
+                    rect,
+                });
+            }
+        }
+    }
+
+    #[test]
+    fn with_dirty_rects_produces_the_same_pixels_as_a_full_redraw() {
+        let mut rng = xs::from_seed([0x9E; 16]);
+
+        fn in_place() -> Vec<Box<dyn post::Filter>> {
+            vec![
+                Box::new(post::Quantise),
+                Box::new(post::Scanlines::default()),
+                Box::new(post::ShadowMask::default()),
+                Box::new(post::Vignette::default()),
+            ]
+        }
+
+        fn all() -> Vec<Box<dyn post::Filter>> {
+            let mut filters = in_place();
+            filters.push(Box::new(post::Curvature::default()));
+            filters
+        }
+
+        for filters in [in_place as fn() -> _, all] {
+            for _ in 0..4 {
+                let commands = arb::commands(&mut rng);
+                let mut frame_buffer = rendered_with(filters(), &commands);
+
+                let mut changed = commands.clone();
+                let i = xs::range(&mut rng, 0..changed.len() as u32) as usize;
+                changed[i] = arb::command(&mut rng);
+
+                render(&mut frame_buffer, &changed);
+
+                assert_eq!(frame_buffer.buffer, rendered_with(filters(), &changed).buffer);
+            }
+        }
+    }
+
+    #[test]
+    fn turning_a_filter_off_redraws_without_it() {
+        let commands = arb::commands(&mut xs::from_seed([0x9F; 16]));
+
+        let mut frame_buffer = FrameBuffer::from_size(SIZE);
+        let id = frame_buffer.add_filter(Box::new(post::Curvature::default()), false);
+        assert!(!frame_buffer.filter_enabled(id));
+
+        let _ = render(&mut frame_buffer, &commands);
+        let unfiltered = frame_buffer.buffer.clone();
+
+        frame_buffer.set_filter_enabled(id, true);
+        assert_ne!(render(&mut frame_buffer, &commands), NeedsRedraw::No);
+        assert_ne!(frame_buffer.buffer, unfiltered);
+
+        frame_buffer.set_filter_enabled(id, false);
+        assert_ne!(render(&mut frame_buffer, &commands), NeedsRedraw::No);
+        assert_eq!(frame_buffer.buffer, unfiltered);
+    }
+}
+
+#[cfg(test)]
+mod load_atlas_works {
+    use super::*;
+
+    const SIZE: (clip::W, clip::H) = (command::WIDTH, command::HEIGHT);
+
+    fn single_pixel_from(atlas: atlas::Id) -> [Command; 1] {
+        [Command {
+            rect: Rect::from_unscaled(unscaled::Rect {
+                x: unscaled::X(0),
+                y: unscaled::Y(0),
+                w: unscaled::W(1),
+                h: unscaled::H(1),
+            }),
+            atlas,
+            ..<_>::default()
+        }]
+    }
+
+    fn red_pixel() -> atlas::Atlas {
+        atlas::Atlas::from_pixels(1, 1, vec![colours::RED]).unwrap()
+    }
+
+    #[test]
+    fn on_this_single_pixel_atlas() {
+        let mut frame_buffer = FrameBuffer::from_size(SIZE);
+        let id = frame_buffer.load_atlas(red_pixel()).unwrap();
+
+        assert_ne!(id, atlas::Id::BUILT_IN);
+
+        let _ = render(&mut frame_buffer, &single_pixel_from(id));
+
+        assert_eq!(frame_buffer.buffer[0], colours::RED);
+    }
+
+    #[test]
+    fn when_the_commands_referred_to_the_atlas_before_it_was_loaded() {
+        let commands = single_pixel_from(atlas::Id(1));
+
+        let mut frame_buffer = render_headless(&commands, SIZE);
+        assert_eq!(frame_buffer.buffer[0], colours::BLACK);
+
+        frame_buffer.load_atlas(red_pixel()).unwrap();
+
+        assert_ne!(render(&mut frame_buffer, &commands), NeedsRedraw::No);
+        assert_eq!(frame_buffer.buffer[0], colours::RED);
+    }
+}
+
+#[cfg(test)]
+mod set_texture_works {
+    use super::*;
+    use platform_types::Texture;
+
+    const SIZE: (clip::W, clip::H) = (command::WIDTH, command::HEIGHT);
+
+    fn whole_texture() -> [Command; 1] {
+        [Command {
+            rect: Rect::from_unscaled(unscaled::Rect {
+                x: unscaled::X(0),
+                y: unscaled::Y(0),
+                w: unscaled::W(4),
+                h: unscaled::H(4),
+            }),
+            atlas: atlas::Id::TEXTURE,
+            ..<_>::default()
+        }]
+    }
+
+    fn rendered_with(texture: &Texture) -> FrameBuffer {
+        let mut frame_buffer = FrameBuffer::from_size(SIZE);
+        frame_buffer.set_texture(texture);
+        let _ = render(&mut frame_buffer, &whole_texture());
+        frame_buffer
+    }
+
+    #[test]
+    fn on_this_single_pixel() {
+        let mut texture = Texture::new(4, 4);
+        texture.set(1, 2, colours::RED);
+
+        let frame_buffer = rendered_with(&texture);
+
+        assert_eq!(frame_buffer.buffer[2 * usize::from(command::WIDTH) + 1], colours::RED);
+    }
+
+    #[test]
+    fn without_redrawing_if_the_pixels_did_not_change() {
+        let mut texture = Texture::new(4, 4);
+        texture.set(1, 2, colours::RED);
+
+        let mut frame_buffer = rendered_with(&texture);
+
+        // A different allocation, with the same pixels.
+        frame_buffer.set_texture(&texture.clone());
+
+        assert_eq!(render(&mut frame_buffer, &whole_texture()), NeedsRedraw::No);
+    }
+
+    #[test]
+    fn when_only_the_pixels_changed() {
+        let mut texture = Texture::new(4, 4);
+
+        let mut frame_buffer = rendered_with(&texture);
+
+        texture.set(1, 2, colours::RED);
+        frame_buffer.set_texture(&texture);
+
+        assert_ne!(render(&mut frame_buffer, &whole_texture()), NeedsRedraw::No);
+        assert_eq!(frame_buffer.buffer, rendered_with(&texture).buffer);
+    }
+
+    #[test]
+    fn from_graphics_only_when_the_texture_version_changed() {
+        fn graphics<'a>(
+            commands: &'a [Command],
+            texture: &'a Texture,
+            texture_version: u32,
+        ) -> Graphics<'a> {
+            Graphics { commands, points: &[], texture, texture_version }
+        }
+
+        let commands = whole_texture();
+
+        let mut texture = Texture::new(4, 4);
+        let mut frame_buffer = FrameBuffer::from_size(SIZE);
+        let _ = render_graphics(&mut frame_buffer, graphics(&commands, &texture, 0));
+
+        // The pixels are not looked at, since the version is the same.
+        texture.set(1, 2, colours::RED);
+        assert_eq!(render_graphics(&mut frame_buffer, graphics(&commands, &texture, 0)), NeedsRedraw::No);
+
+        assert_ne!(render_graphics(&mut frame_buffer, graphics(&commands, &texture, 1)), NeedsRedraw::No);
+        assert_eq!(frame_buffer.buffer, rendered_with(&texture).buffer);
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum NeedsRedraw {
+    No,
+    /// Contains the parts of `FrameBuffer::buffer` that changed, so that only
+    /// those parts need to be presented.
+    Yes(Vec<clip::Rect>),
+}
+
+#[cfg(all(
+    target_arch = "x86_64",
+    target_feature = "sse",
+    target_feature = "sse2",
+))]
+mod wide {
+    use super::*;
+    pub const WIDTH: unscaled::Inner = 4;
+
+    /// SAFETY: The pointers produced by the code generated by these macros must
+    /// be valid to read 128 bytes from.
     /// We omit unsafe in the macro's code in order to encourage the user to notice
     /// the invariants they are expected to uphold.
     #[macro_export]
@@ -586,11 +2102,10 @@ mod wide {
         (
             $addr: expr $(,)?
         ) => ({
-            
-            use core::arch::wasm32::v128 as V;
+            use core::arch::x86_64::__m128i as V;
 
             let addr: *const _ = $addr;
-            core::arch::wasm32::v128_load(
+            core::arch::x86_64::_mm_loadu_si128(
                 addr as *const V
             )
         });
@@ -598,10 +2113,10 @@ mod wide {
             $addr: expr,
             $index: expr $(,)?
         ) => ({
-            use core::arch::wasm32::v128 as V;
+            use core::arch::x86_64::__m128i as V;
 
             let addr: *const _ = $addr;
-            core::arch::wasm32::v128_load(
+            core::arch::x86_64::_mm_loadu_si128(
                 addr.add($index) as *const V
             )
         });
@@ -618,11 +2133,11 @@ mod wide {
             $vector: expr,
             $addr: expr $(,)?
         ) => ({
-            use core::arch::wasm32::v128 as V;
+            use core::arch::x86_64::__m128i as V;
 
             let vector: V = $vector;
             let addr: *mut _ = $addr;
-            core::arch::wasm32::v128_store(
+            core::arch::x86_64::_mm_storeu_si128(
                 addr as *mut V,
                 vector,
             )
@@ -632,11 +2147,11 @@ mod wide {
             $addr: expr,
             $index: expr $(,)?
         ) => ({
-            use core::arch::wasm32::v128 as V;
+            use core::arch::x86_64::__m128i as V;
             let vector: V = $vector;
 
             let addr: *mut _ = $addr;
-            core::arch::wasm32::v128_store(
+            core::arch::x86_64::_mm_storeu_si128(
                 addr.add($index) as *mut V,
                 vector,
             )
@@ -656,335 +2171,3043 @@ mod wide {
             let mask = $mask;
             #[allow(unused_unsafe)]
             unsafe {
-                core::arch::wasm32::v128_or(
-                    core::arch::wasm32::v128_and(
+                core::arch::x86_64::_mm_or_si128(
+                    core::arch::x86_64::_mm_and_si128(
                         mask,
                         $one_case,
                     ),
-                    core::arch::wasm32::v128_andnot(
-                        $zero_case,
+                    core::arch::x86_64::_mm_andnot_si128(
                         mask,
+                        $zero_case,
                     )
                 )
             }
         });
     }
-    pub use _pick_via_mask as pick_via_mask;
+    pub use _pick_via_mask as pick_via_mask;
+
+    #[macro_export]
+    macro_rules! _i32x4 {
+        (
+            $e0: expr,
+            $e1: expr,
+            $e2: expr,
+            $e3: expr $(,)?
+        ) => ({#[allow(unused_unsafe)]
+            unsafe {
+                core::arch::x86_64::_mm_set_epi32(
+                    $e3,
+                    $e2,
+                    $e1,
+                    $e0,
+                )
+            }
+        });
+    }
+    pub use _i32x4 as i32x4;
+
+    #[macro_export]
+    macro_rules! _and {
+        (
+            $a: expr,
+            $b: expr $(,)?
+        ) => ({#[allow(unused_unsafe)]
+            unsafe {
+                core::arch::x86_64::_mm_and_si128($a, $b)
+            }
+        });
+    }
+    pub use _and as and;
+
+    #[macro_export]
+    macro_rules! _and_not {
+        (
+            $just_anded: expr,
+            $notted: expr $(,)?
+        ) => ({#[allow(unused_unsafe)]
+            unsafe {
+                // "Compute the bitwise NOT of 128 bits (representing integer data) in
+                // `a` and then AND with `b`, and store the result in dst."
+                // I prefer to not the second operand, so `a` and `b` are switched here.
+                core::arch::x86_64::_mm_andnot_si128($notted, $just_anded)
+            }
+        });
+    }
+    pub use _and_not as and_not;
+
+    #[macro_export]
+    macro_rules! _or {
+        (
+            $a: expr,
+            $b: expr $(,)?
+        ) => ({#[allow(unused_unsafe)]
+            unsafe {
+                core::arch::x86_64::_mm_or_si128($a, $b)
+            }
+        });
+    }
+    pub use _or as or;
+
+    #[macro_export]
+    macro_rules! _left_shift_32 {
+        (
+            $a: expr,
+            $imm8: expr $(,)?
+        ) => ({#[allow(unused_unsafe)]
+            unsafe {
+                core::arch::x86_64::_mm_slli_epi32($a, $imm8)
+            }
+        });
+    }
+    pub use _left_shift_32 as left_shift_32;
+
+    #[macro_export]
+    macro_rules! _right_shift_32 {
+        (
+            $a: expr,
+            $imm8: expr $(,)?
+        ) => ({#[allow(unused_unsafe)]
+            unsafe {
+                core::arch::x86_64::_mm_srli_epi32($a, $imm8)
+            }
+        });
+    }
+    pub use _right_shift_32 as right_shift_32;
+
+    #[macro_export]
+    macro_rules! _f32_to_u32 {
+        (
+            $a: expr $(,)?
+        ) => ({#[allow(unused_unsafe)]
+            unsafe {
+                core::arch::x86_64::_mm_cvtps_epi32($a)
+            }
+        });
+    }
+    pub use _f32_to_u32 as f32_to_u32;
+
+    #[macro_export]
+    macro_rules! _u32_to_f32 {
+        (
+            $a: expr $(,)?
+        ) => ({#[allow(unused_unsafe)]
+            unsafe {
+                core::arch::x86_64::_mm_cvtepi32_ps($a)
+            }
+        });
+    }
+    pub use _u32_to_f32 as u32_to_f32;
+
+    #[macro_export]
+    macro_rules! _i32 {
+        (
+            $a: expr $(,)?
+        ) => ({#[allow(unused_unsafe)]
+            unsafe {
+                core::arch::x86_64::_mm_set1_epi32($a)
+            }
+        });
+    }
+    pub use _i32 as i32;
+
+    #[macro_export]
+    macro_rules! _f32 {
+        (
+            $a: expr $(,)?
+        ) => ({#[allow(unused_unsafe)]
+            unsafe {
+                core::arch::x86_64::_mm_set_ps1($a)
+            }
+        });
+    }
+    pub use _f32 as f32;
+
+    #[macro_export]
+    macro_rules! _add_f32 {
+        (
+            $a: expr,
+            $b: expr $(,)?
+        ) => ({#[allow(unused_unsafe)]
+            unsafe {
+                core::arch::x86_64::_mm_add_ps($a, $b)
+            }
+        });
+    }
+    pub use _add_f32 as add_f32;
+
+    #[macro_export]
+    macro_rules! _add_i32 {
+        (
+            $a: expr,
+            $b: expr $(,)?
+        ) => ({#[allow(unused_unsafe)]
+            unsafe {
+                core::arch::x86_64::_mm_add_epi32($a, $b)
+            }
+        });
+    }
+    pub use _add_i32 as add_i32;
+
+    #[macro_export]
+    macro_rules! _sub {
+        (
+            $a: expr,
+            $b: expr $(,)?
+        ) => ({#[allow(unused_unsafe)]
+            unsafe {
+                core::arch::x86_64::_mm_sub_ps($a, $b)
+            }
+        });
+    }
+    pub use _sub as sub;
+
+    #[macro_export]
+    macro_rules! _mul {
+        (
+            $a: expr,
+            $b: expr $(,)?
+        ) => ({#[allow(unused_unsafe)]
+            unsafe {
+                core::arch::x86_64::_mm_mul_ps($a, $b)
+            }
+        });
+    }
+    pub use _mul as mul;
+
+    #[macro_export]
+    macro_rules! _recip {
+        (
+            $a: expr $(,)?
+        ) => ({#[allow(unused_unsafe)]
+            unsafe {
+                // We use a full divide instead of `_mm_rcp_ps`, because the
+                // approximation it gives varies between CPUs, and we want every
+                // backend to produce the same pixels.
+                core::arch::x86_64::_mm_div_ps(
+                    core::arch::x86_64::_mm_set_ps1(1.),
+                    $a
+                )
+            }
+        });
+    }
+    pub use _recip as recip;
+
+    #[macro_export]
+    macro_rules! _sqrt {
+        (
+            $a: expr $(,)?
+        ) => ({#[allow(unused_unsafe)]
+            unsafe {
+                core::arch::x86_64::_mm_sqrt_ps($a)
+            }
+        });
+    }
+    pub use _sqrt as sqrt;
+
+    #[macro_export]
+    macro_rules! _min {
+        (
+            $a: expr,
+            $b: expr $(,)?
+        ) => ({#[allow(unused_unsafe)]
+            unsafe {
+                core::arch::x86_64::_mm_min_ps($a, $b)
+            }
+        });
+    }
+    pub use _min as min;
+
+    #[macro_export]
+    macro_rules! _lt_mask_32 {
+        (
+            $lhs: expr,
+            $rhs: expr $(,)?
+        ) => ({#[allow(unused_unsafe)]
+            unsafe {
+                core::arch::x86_64::_mm_cmplt_epi32($lhs, $rhs)
+            }
+        });
+    }
+    pub use _lt_mask_32 as lt_mask_32;
+
+    #[macro_export]
+    macro_rules! _eq_mask_u32 {
+        (
+            $lhs: expr,
+            $rhs: expr $(,)?
+        ) => ({#[allow(unused_unsafe)]
+            unsafe {
+                core::arch::x86_64::_mm_cmpeq_epi32($lhs, $rhs)
+            }
+        });
+    }
+    pub use _eq_mask_u32 as eq_mask_u32;
+
+    #[macro_export]
+    macro_rules! _gt_mask_32 {
+        (
+            $lhs: expr,
+            $rhs: expr $(,)?
+        ) => ({#[allow(unused_unsafe)]
+            unsafe {
+                core::arch::x86_64::_mm_cmpgt_epi32($lhs, $rhs)
+            }
+        });
+    }
+    pub use _gt_mask_32 as gt_mask_32;
+
+    /// Returns a vector whose lanes are the lanes of `$v` at the given indices.
+    /// The indices must be literals from 0 to 3.
+    #[macro_export]
+    macro_rules! _shuffle_32 {
+        (
+            $v: expr,
+            $i0: literal,
+            $i1: literal,
+            $i2: literal,
+            $i3: literal $(,)?
+        ) => ({
+            const IMM8: i32 = $i0 | ($i1 << 2) | ($i2 << 4) | ($i3 << 6);
+            let v = $v;
+            #[allow(unused_unsafe)]
+            unsafe {
+                core::arch::x86_64::_mm_shuffle_epi32::<IMM8>(v)
+            }
+        });
+    }
+    pub use _shuffle_32 as shuffle_32;
+}
+
+#[cfg(all(
+    target_arch = "wasm32",
+    target_feature = "simd128",
+))]
+mod wide {
+    use super::*;
+    pub const WIDTH: unscaled::Inner = 4;
+
+    /// SAFETY: The pointers produced by the code generated by these macros must
+    /// be valid to read 128 bytes from.
+    /// We omit unsafe in the macro's code in order to encourage the user to notice
+    /// the invariants they are expected to uphold.
+    #[macro_export]
+    macro_rules! _load {
+        (
+            $addr: expr $(,)?
+        ) => ({
+            
+            use core::arch::wasm32::v128 as V;
+
+            let addr: *const _ = $addr;
+            core::arch::wasm32::v128_load(
+                addr as *const V
+            )
+        });
+        (
+            $addr: expr,
+            $index: expr $(,)?
+        ) => ({
+            use core::arch::wasm32::v128 as V;
+
+            let addr: *const _ = $addr;
+            core::arch::wasm32::v128_load(
+                addr.add($index) as *const V
+            )
+        });
+    }
+    pub use _load as load;
+
+    /// SAFETY: The pointers produced by the code generated by these macros must
+    /// be valid to write 128 bytes to.
+    /// We omit unsafe in the macro's code in order to encourage the user to notice
+    /// the invariants they are expected to uphold.
+    #[macro_export]
+    macro_rules! _store {
+        (
+            $vector: expr,
+            $addr: expr $(,)?
+        ) => ({
+            use core::arch::wasm32::v128 as V;
+
+            let vector: V = $vector;
+            let addr: *mut _ = $addr;
+            core::arch::wasm32::v128_store(
+                addr as *mut V,
+                vector,
+            )
+        });
+        (
+            $vector: expr,
+            $addr: expr,
+            $index: expr $(,)?
+        ) => ({
+            use core::arch::wasm32::v128 as V;
+            let vector: V = $vector;
+
+            let addr: *mut _ = $addr;
+            core::arch::wasm32::v128_store(
+                addr.add($index) as *mut V,
+                vector,
+            )
+        });
+    }
+    pub use _store as store;
+
+    // SAFETY: This operation is only unsafe because it uses intrinsics, and we wrap
+    // this module with `cfg`, so this is safe to use in all cases where it compiles.
+    #[macro_export]
+    macro_rules! _pick_via_mask {
+        (
+            $zero_case: expr,
+            $one_case: expr,
+            $mask: expr $(,)?
+        ) => ({
+            let mask = $mask;
+            #[allow(unused_unsafe)]
+            unsafe {
+                core::arch::wasm32::v128_or(
+                    core::arch::wasm32::v128_and(
+                        mask,
+                        $one_case,
+                    ),
+                    core::arch::wasm32::v128_andnot(
+                        $zero_case,
+                        mask,
+                    )
+                )
+            }
+        });
+    }
+    pub use _pick_via_mask as pick_via_mask;
+
+    #[macro_export]
+    macro_rules! _i32x4 {
+        (
+            $e0: expr,
+            $e1: expr,
+            $e2: expr,
+            $e3: expr $(,)?
+        ) => ({#[allow(unused_unsafe)]
+            unsafe {
+                core::arch::wasm32::i32x4(
+                    $e0,
+                    $e1,
+                    $e2,
+                    $e3,
+                )
+            }
+        });
+    }
+    pub use _i32x4 as i32x4;
+
+    #[macro_export]
+    macro_rules! _and {
+        (
+            $a: expr,
+            $b: expr $(,)?
+        ) => ({#[allow(unused_unsafe)]
+            unsafe {
+                core::arch::wasm32::v128_and($a, $b)
+            }
+        });
+    }
+    pub use _and as and;
+
+    #[macro_export]
+    macro_rules! _and_not {
+        (
+            $just_anded: expr,
+            $notted: expr $(,)?
+        ) => ({#[allow(unused_unsafe)]
+            unsafe {
+                core::arch::wasm32::v128_andnot($just_anded, $notted)
+            }
+        });
+    }
+    pub use _and_not as and_not;
+
+    #[macro_export]
+    macro_rules! _or {
+        (
+            $a: expr,
+            $b: expr $(,)?
+        ) => ({#[allow(unused_unsafe)]
+            unsafe {
+                core::arch::wasm32::v128_or($a, $b)
+            }
+        });
+    }
+    pub use _or as or;
+
+    #[macro_export]
+    macro_rules! _left_shift_32 {
+        (
+            $a: expr,
+            $imm8: expr $(,)?
+        ) => ({#[allow(unused_unsafe)]
+            unsafe {
+                core::arch::wasm32::i32x4_shl($a, $imm8)
+            }
+        });
+    }
+    pub use _left_shift_32 as left_shift_32;
+
+    #[macro_export]
+    macro_rules! _right_shift_32 {
+        (
+            $a: expr,
+            $imm8: expr $(,)?
+        ) => ({#[allow(unused_unsafe)]
+            unsafe {
+                core::arch::wasm32::u32x4_shr($a, $imm8)
+            }
+        });
+    }
+    pub use _right_shift_32 as right_shift_32;
+
+    #[macro_export]
+    macro_rules! _f32_to_u32 {
+        (
+            $a: expr $(,)?
+        ) => ({#[allow(unused_unsafe)]
+            unsafe {
+                // Round to nearest, with ties to even, before truncating, to
+                // match `_mm_cvtps_epi32`.
+                core::arch::wasm32::u32x4_trunc_sat_f32x4(
+                    core::arch::wasm32::f32x4_nearest($a)
+                )
+            }
+        });
+    }
+    pub use _f32_to_u32 as f32_to_u32;
+
+    #[macro_export]
+    macro_rules! _u32_to_f32 {
+        (
+            $a: expr $(,)?
+        ) => ({#[allow(unused_unsafe)]
+            unsafe {
+                core::arch::wasm32::f32x4_convert_u32x4($a)
+            }
+        });
+    }
+    pub use _u32_to_f32 as u32_to_f32;
+
+    #[macro_export]
+    macro_rules! _i32 {
+        (
+            $a: expr $(,)?
+        ) => ({#[allow(unused_unsafe)]
+            unsafe {
+                core::arch::wasm32::i32x4_splat($a)
+            }
+        });
+    }
+    pub use _i32 as i32;
+
+    #[macro_export]
+    macro_rules! _f32 {
+        (
+            $a: expr $(,)?
+        ) => ({#[allow(unused_unsafe)]
+            unsafe {
+                core::arch::wasm32::f32x4_splat($a)
+            }
+        });
+    }
+    pub use _f32 as f32;
+
+    #[macro_export]
+    macro_rules! _add_f32 {
+        (
+            $a: expr,
+            $b: expr $(,)?
+        ) => ({#[allow(unused_unsafe)]
+            unsafe {
+                core::arch::wasm32::f32x4_add($a, $b)
+            }
+        });
+    }
+    pub use _add_f32 as add_f32;
+
+    #[macro_export]
+    macro_rules! _add_i32 {
+        (
+            $a: expr,
+            $b: expr $(,)?
+        ) => ({#[allow(unused_unsafe)]
+            unsafe {
+                core::arch::wasm32::i32x4_add($a, $b)
+            }
+        });
+    }
+    pub use _add_i32 as add_i32;
+
+    #[macro_export]
+    macro_rules! _sub {
+        (
+            $a: expr,
+            $b: expr $(,)?
+        ) => ({#[allow(unused_unsafe)]
+            unsafe {
+                core::arch::wasm32::f32x4_sub($a, $b)
+            }
+        });
+    }
+    pub use _sub as sub;
+
+    #[macro_export]
+    macro_rules! _mul {
+        (
+            $a: expr,
+            $b: expr $(,)?
+        ) => ({#[allow(unused_unsafe)]
+            unsafe {
+                core::arch::wasm32::f32x4_mul($a, $b)
+            }
+        });
+    }
+    pub use _mul as mul;
+
+    #[macro_export]
+    macro_rules! _recip {
+        (
+            $a: expr $(,)?
+        ) => ({#[allow(unused_unsafe)]
+            unsafe {
+                // As of this writing, there's no equivalent wasm instrinsic to
+                // `_mm_rcp_ps`. But the other backends divide too, so that every
+                // backend produces the same pixels.
+                core::arch::wasm32::f32x4_div(
+                    core::arch::wasm32::f32x4_splat(1.),
+                    $a
+                )
+            }
+        });
+    }
+    pub use _recip as recip;
+
+    #[macro_export]
+    macro_rules! _sqrt {
+        (
+            $a: expr $(,)?
+        ) => ({#[allow(unused_unsafe)]
+            unsafe {
+                core::arch::wasm32::f32x4_sqrt($a)
+            }
+        });
+    }
+    pub use _sqrt as sqrt;
+
+    #[macro_export]
+    macro_rules! _min {
+        (
+            $a: expr,
+            $b: expr $(,)?
+        ) => ({#[allow(unused_unsafe)]
+            unsafe {
+                core::arch::wasm32::f32x4_min($a, $b)
+            }
+        });
+    }
+    pub use _min as min;
+
+    #[macro_export]
+    macro_rules! _lt_mask_32 {
+        (
+            $lhs: expr,
+            $rhs: expr $(,)?
+        ) => ({#[allow(unused_unsafe)]
+            unsafe {
+                core::arch::wasm32::i32x4_lt($lhs, $rhs)
+            }
+        });
+    }
+    pub use _lt_mask_32 as lt_mask_32;
+
+    #[macro_export]
+    macro_rules! _eq_mask_u32 {
+        (
+            $lhs: expr,
+            $rhs: expr $(,)?
+        ) => ({#[allow(unused_unsafe)]
+            unsafe {
+                core::arch::wasm32::i32x4_eq($lhs, $rhs)
+            }
+        });
+    }
+    pub use _eq_mask_u32 as eq_mask_u32;
+
+    #[macro_export]
+    macro_rules! _gt_mask_32 {
+        (
+            $lhs: expr,
+            $rhs: expr $(,)?
+        ) => ({#[allow(unused_unsafe)]
+            unsafe {
+                core::arch::wasm32::i32x4_gt($lhs, $rhs)
+            }
+        });
+    }
+    pub use _gt_mask_32 as gt_mask_32;
+
+    /// Returns a vector whose lanes are the lanes of `$v` at the given indices.
+    /// The indices must be literals from 0 to 3.
+    #[macro_export]
+    macro_rules! _shuffle_32 {
+        (
+            $v: expr,
+            $i0: literal,
+            $i1: literal,
+            $i2: literal,
+            $i3: literal $(,)?
+        ) => ({
+            let v = $v;
+            #[allow(unused_unsafe)]
+            unsafe {
+                core::arch::wasm32::i32x4_shuffle::<$i0, $i1, $i2, $i3>(v, v)
+            }
+        });
+    }
+    pub use _shuffle_32 as shuffle_32;
+}
+
+#[cfg(all(
+    target_arch = "aarch64",
+    target_feature = "neon",
+))]
+mod wide {
+    use super::*;
+    pub const WIDTH: unscaled::Inner = 4;
+
+    /// SAFETY: The pointers produced by the code generated by these macros must
+    /// be valid to read 128 bytes from.
+    /// We omit unsafe in the macro's code in order to encourage the user to notice
+    /// the invariants they are expected to uphold.
+    #[macro_export]
+    macro_rules! _load {
+        (
+            $addr: expr $(,)?
+        ) => ({
+            let addr: *const _ = $addr;
+            core::arch::aarch64::vld1q_u32(
+                addr as *const u32
+            )
+        });
+        (
+            $addr: expr,
+            $index: expr $(,)?
+        ) => ({
+            let addr: *const _ = $addr;
+            core::arch::aarch64::vld1q_u32(
+                addr.add($index) as *const u32
+            )
+        });
+    }
+    pub use _load as load;
+
+    /// SAFETY: The pointers produced by the code generated by these macros must
+    /// be valid to write 128 bytes to.
+    /// We omit unsafe in the macro's code in order to encourage the user to notice
+    /// the invariants they are expected to uphold.
+    #[macro_export]
+    macro_rules! _store {
+        (
+            $vector: expr,
+            $addr: expr $(,)?
+        ) => ({
+            use core::arch::aarch64::uint32x4_t as V;
+
+            let vector: V = $vector;
+            let addr: *mut _ = $addr;
+            core::arch::aarch64::vst1q_u32(
+                addr as *mut u32,
+                vector,
+            )
+        });
+        (
+            $vector: expr,
+            $addr: expr,
+            $index: expr $(,)?
+        ) => ({
+            use core::arch::aarch64::uint32x4_t as V;
+            let vector: V = $vector;
+
+            let addr: *mut _ = $addr;
+            core::arch::aarch64::vst1q_u32(
+                addr.add($index) as *mut u32,
+                vector,
+            )
+        });
+    }
+    pub use _store as store;
+
+    // SAFETY: This operation is only unsafe because it uses intrinsics, and we wrap
+    // this module with `cfg`, so this is safe to use in all cases where it compiles.
+    #[macro_export]
+    macro_rules! _pick_via_mask {
+        (
+            $zero_case: expr,
+            $one_case: expr,
+            $mask: expr $(,)?
+        ) => ({
+            let mask = $mask;
+            #[allow(unused_unsafe)]
+            unsafe {
+                // "Bitwise Select. This instruction sets each bit in the destination
+                // SIMD&FP register to the corresponding bit from the first source
+                // SIMD&FP register when the original destination bit was 1,
+                // otherwise from the second source SIMD&FP register."
+                core::arch::aarch64::vbslq_u32(
+                    mask,
+                    $one_case,
+                    $zero_case,
+                )
+            }
+        });
+    }
+    pub use _pick_via_mask as pick_via_mask;
+
+    #[macro_export]
+    macro_rules! _i32x4 {
+        (
+            $e0: expr,
+            $e1: expr,
+            $e2: expr,
+            $e3: expr $(,)?
+        ) => ({#[allow(unused_unsafe)]
+            unsafe {
+                let array: [i32; 4] = [
+                    $e0,
+                    $e1,
+                    $e2,
+                    $e3,
+                ];
+                core::arch::aarch64::vld1q_u32(
+                    array.as_ptr() as *const u32
+                )
+            }
+        });
+    }
+    pub use _i32x4 as i32x4;
+
+    #[macro_export]
+    macro_rules! _and {
+        (
+            $a: expr,
+            $b: expr $(,)?
+        ) => ({#[allow(unused_unsafe)]
+            unsafe {
+                core::arch::aarch64::vandq_u32($a, $b)
+            }
+        });
+    }
+    pub use _and as and;
+
+    #[macro_export]
+    macro_rules! _and_not {
+        (
+            $just_anded: expr,
+            $notted: expr $(,)?
+        ) => ({#[allow(unused_unsafe)]
+            unsafe {
+                // "Bitwise bit Clear" computes `a & !b`, which is already the
+                // operand order we want.
+                core::arch::aarch64::vbicq_u32($just_anded, $notted)
+            }
+        });
+    }
+    pub use _and_not as and_not;
+
+    #[macro_export]
+    macro_rules! _or {
+        (
+            $a: expr,
+            $b: expr $(,)?
+        ) => ({#[allow(unused_unsafe)]
+            unsafe {
+                core::arch::aarch64::vorrq_u32($a, $b)
+            }
+        });
+    }
+    pub use _or as or;
+
+    #[macro_export]
+    macro_rules! _left_shift_32 {
+        (
+            $a: expr,
+            $imm8: expr $(,)?
+        ) => ({#[allow(unused_unsafe)]
+            unsafe {
+                core::arch::aarch64::vshlq_n_u32::<{ $imm8 }>($a)
+            }
+        });
+    }
+    pub use _left_shift_32 as left_shift_32;
+
+    #[macro_export]
+    macro_rules! _right_shift_32 {
+        (
+            $a: expr,
+            $imm8: expr $(,)?
+        ) => ({#[allow(unused_unsafe)]
+            unsafe {
+                core::arch::aarch64::vshrq_n_u32::<{ $imm8 }>($a)
+            }
+        });
+    }
+    pub use _right_shift_32 as right_shift_32;
+
+    #[macro_export]
+    macro_rules! _f32_to_u32 {
+        (
+            $a: expr $(,)?
+        ) => ({#[allow(unused_unsafe)]
+            unsafe {
+                // Round to nearest, with ties to even, to match `_mm_cvtps_epi32`.
+                // That treats the lanes as signed, so we do too, to get the
+                // same results for negative and out of range values.
+                core::arch::aarch64::vreinterpretq_u32_s32(
+                    core::arch::aarch64::vcvtnq_s32_f32($a)
+                )
+            }
+        });
+    }
+    pub use _f32_to_u32 as f32_to_u32;
+
+    #[macro_export]
+    macro_rules! _u32_to_f32 {
+        (
+            $a: expr $(,)?
+        ) => ({#[allow(unused_unsafe)]
+            unsafe {
+                // Signed, like `_mm_cvtepi32_ps`.
+                core::arch::aarch64::vcvtq_f32_s32(
+                    core::arch::aarch64::vreinterpretq_s32_u32($a)
+                )
+            }
+        });
+    }
+    pub use _u32_to_f32 as u32_to_f32;
+
+    #[macro_export]
+    macro_rules! _i32 {
+        (
+            $a: expr $(,)?
+        ) => ({#[allow(unused_unsafe)]
+            unsafe {
+                let a: i32 = $a;
+                core::arch::aarch64::vdupq_n_u32(a as u32)
+            }
+        });
+    }
+    pub use _i32 as i32;
+
+    #[macro_export]
+    macro_rules! _f32 {
+        (
+            $a: expr $(,)?
+        ) => ({#[allow(unused_unsafe)]
+            unsafe {
+                core::arch::aarch64::vdupq_n_f32($a)
+            }
+        });
+    }
+    pub use _f32 as f32;
+
+    #[macro_export]
+    macro_rules! _add_f32 {
+        (
+            $a: expr,
+            $b: expr $(,)?
+        ) => ({#[allow(unused_unsafe)]
+            unsafe {
+                core::arch::aarch64::vaddq_f32($a, $b)
+            }
+        });
+    }
+    pub use _add_f32 as add_f32;
+
+    #[macro_export]
+    macro_rules! _add_i32 {
+        (
+            $a: expr,
+            $b: expr $(,)?
+        ) => ({#[allow(unused_unsafe)]
+            unsafe {
+                core::arch::aarch64::vaddq_u32($a, $b)
+            }
+        });
+    }
+    pub use _add_i32 as add_i32;
+
+    #[macro_export]
+    macro_rules! _sub {
+        (
+            $a: expr,
+            $b: expr $(,)?
+        ) => ({#[allow(unused_unsafe)]
+            unsafe {
+                core::arch::aarch64::vsubq_f32($a, $b)
+            }
+        });
+    }
+    pub use _sub as sub;
+
+    #[macro_export]
+    macro_rules! _mul {
+        (
+            $a: expr,
+            $b: expr $(,)?
+        ) => ({#[allow(unused_unsafe)]
+            unsafe {
+                core::arch::aarch64::vmulq_f32($a, $b)
+            }
+        });
+    }
+    pub use _mul as mul;
+
+    #[macro_export]
+    macro_rules! _recip {
+        (
+            $a: expr $(,)?
+        ) => ({#[allow(unused_unsafe)]
+            unsafe {
+                // We use a full divide instead of `vrecpeq_f32`, because that is
+                // only accurate to about 8 bits, and we want every backend to
+                // produce the same pixels.
+                core::arch::aarch64::vdivq_f32(
+                    core::arch::aarch64::vdupq_n_f32(1.),
+                    $a
+                )
+            }
+        });
+    }
+    pub use _recip as recip;
+
+    #[macro_export]
+    macro_rules! _sqrt {
+        (
+            $a: expr $(,)?
+        ) => ({#[allow(unused_unsafe)]
+            unsafe {
+                core::arch::aarch64::vsqrtq_f32($a)
+            }
+        });
+    }
+    pub use _sqrt as sqrt;
+
+    #[macro_export]
+    macro_rules! _min {
+        (
+            $a: expr,
+            $b: expr $(,)?
+        ) => ({#[allow(unused_unsafe)]
+            unsafe {
+                core::arch::aarch64::vminq_f32($a, $b)
+            }
+        });
+    }
+    pub use _min as min;
+
+    #[macro_export]
+    macro_rules! _lt_mask_32 {
+        (
+            $lhs: expr,
+            $rhs: expr $(,)?
+        ) => ({#[allow(unused_unsafe)]
+            unsafe {
+                core::arch::aarch64::vcltq_s32(
+                    core::arch::aarch64::vreinterpretq_s32_u32($lhs),
+                    core::arch::aarch64::vreinterpretq_s32_u32($rhs),
+                )
+            }
+        });
+    }
+    pub use _lt_mask_32 as lt_mask_32;
+
+    #[macro_export]
+    macro_rules! _eq_mask_u32 {
+        (
+            $lhs: expr,
+            $rhs: expr $(,)?
+        ) => ({#[allow(unused_unsafe)]
+            unsafe {
+                core::arch::aarch64::vceqq_u32($lhs, $rhs)
+            }
+        });
+    }
+    pub use _eq_mask_u32 as eq_mask_u32;
+
+    #[macro_export]
+    macro_rules! _gt_mask_32 {
+        (
+            $lhs: expr,
+            $rhs: expr $(,)?
+        ) => ({#[allow(unused_unsafe)]
+            unsafe {
+                core::arch::aarch64::vcgtq_s32(
+                    core::arch::aarch64::vreinterpretq_s32_u32($lhs),
+                    core::arch::aarch64::vreinterpretq_s32_u32($rhs),
+                )
+            }
+        });
+    }
+    pub use _gt_mask_32 as gt_mask_32;
+
+    /// Returns a vector whose lanes are the lanes of `$v` at the given indices.
+    /// The indices must be literals from 0 to 3.
+    #[macro_export]
+    macro_rules! _shuffle_32 {
+        (
+            $v: expr,
+            $i0: literal,
+            $i1: literal,
+            $i2: literal,
+            $i3: literal $(,)?
+        ) => ({
+            let v = $v;
+            // A table lookup picks bytes, so we pick all four bytes of each
+            // lane.
+            let lanes: [u8; 4] = [$i0, $i1, $i2, $i3];
+            let table: [u8; 16] = core::array::from_fn(|i| lanes[i / 4] * 4 + (i % 4) as u8);
+            #[allow(unused_unsafe)]
+            unsafe {
+                core::arch::aarch64::vreinterpretq_u32_u8(
+                    core::arch::aarch64::vqtbl1q_u8(
+                        core::arch::aarch64::vreinterpretq_u8_u32(v),
+                        core::arch::aarch64::vld1q_u8(table.as_ptr()),
+                    )
+                )
+            }
+        });
+    }
+    pub use _shuffle_32 as shuffle_32;
+}
+
+/// A portable version of the `wide` operations, that works on plain arrays.
+/// This is used when there is no SIMD version for the target, and in tests
+/// to check that the SIMD versions produce the same results.
+#[allow(unused)]
+mod scalar {
+    use super::*;
+    pub const WIDTH: unscaled::Inner = 4;
+
+    const LANES: usize = WIDTH as usize;
+
+    pub type I = [i32; LANES];
+    pub type F = [f32; LANES];
+
+    fn lanes<T, A: Copy>(a: [A; LANES], f: impl Fn(A) -> T) -> [T; LANES] {
+        core::array::from_fn(|i| f(a[i]))
+    }
+
+    fn lanes2<T, A: Copy, B: Copy>(
+        a: [A; LANES],
+        b: [B; LANES],
+        f: impl Fn(A, B) -> T
+    ) -> [T; LANES] {
+        core::array::from_fn(|i| f(a[i], b[i]))
+    }
+
+    fn mask(b: bool) -> i32 {
+        if b { -1 } else { 0 }
+    }
+
+    pub fn pick_via_mask(zero_case: I, one_case: I, mask: I) -> I {
+        core::array::from_fn(|i| (one_case[i] & mask[i]) | (zero_case[i] & !mask[i]))
+    }
+
+    pub fn and(a: I, b: I) -> I {
+        lanes2(a, b, |a, b| a & b)
+    }
+
+    pub fn and_not(just_anded: I, notted: I) -> I {
+        lanes2(just_anded, notted, |a, b| a & !b)
+    }
+
+    pub fn or(a: I, b: I) -> I {
+        lanes2(a, b, |a, b| a | b)
+    }
+
+    pub fn left_shift_32(a: I, imm8: u32) -> I {
+        lanes(a, |a| ((a as u32) << imm8) as i32)
+    }
+
+    pub fn right_shift_32(a: I, imm8: u32) -> I {
+        lanes(a, |a| ((a as u32) >> imm8) as i32)
+    }
+
+    pub fn f32_to_u32(a: F) -> I {
+        // Round to nearest, with ties to even, to match `_mm_cvtps_epi32`.
+        lanes(a, |a| a.round_ties_even() as i32)
+    }
+
+    pub fn u32_to_f32(a: I) -> F {
+        // `_mm_cvtepi32_ps` treats the lanes as signed, so we do too.
+        lanes(a, |a| a as f32)
+    }
+
+    pub fn add_f32(a: F, b: F) -> F {
+        lanes2(a, b, |a, b| a + b)
+    }
+
+    pub fn add_i32(a: I, b: I) -> I {
+        lanes2(a, b, i32::wrapping_add)
+    }
+
+    pub fn sub(a: F, b: F) -> F {
+        lanes2(a, b, |a, b| a - b)
+    }
+
+    pub fn mul(a: F, b: F) -> F {
+        lanes2(a, b, |a, b| a * b)
+    }
+
+    pub fn recip(a: F) -> F {
+        lanes(a, |a| 1. / a)
+    }
+
+    pub fn sqrt(a: F) -> F {
+        lanes(a, f32::sqrt)
+    }
+
+    pub fn min(a: F, b: F) -> F {
+        lanes2(a, b, f32::min)
+    }
+
+    pub fn lt_mask_32(lhs: I, rhs: I) -> I {
+        lanes2(lhs, rhs, |l, r| mask(l < r))
+    }
+
+    pub fn eq_mask_u32(lhs: I, rhs: I) -> I {
+        lanes2(lhs, rhs, |l, r| mask(l == r))
+    }
+
+    pub fn gt_mask_32(lhs: I, rhs: I) -> I {
+        lanes2(lhs, rhs, |l, r| mask(l > r))
+    }
+
+    pub fn shuffle_32(a: I, indices: [usize; LANES]) -> I {
+        core::array::from_fn(|i| a[indices[i]])
+    }
+
+    /// SAFETY: The pointers produced by the code generated by these macros must
+    /// be valid to read 128 bytes from.
+    /// We omit unsafe in the macro's code in order to encourage the user to notice
+    /// the invariants they are expected to uphold.
+    #[macro_export]
+    macro_rules! _scalar_load {
+        (
+            $addr: expr $(,)?
+        ) => ({
+            let addr: *const _ = $addr;
+            core::ptr::read_unaligned(
+                addr as *const $crate::scalar::I
+            )
+        });
+        (
+            $addr: expr,
+            $index: expr $(,)?
+        ) => ({
+            let addr: *const _ = $addr;
+            core::ptr::read_unaligned(
+                addr.add($index) as *const $crate::scalar::I
+            )
+        });
+    }
+    pub use _scalar_load as load;
+
+    /// SAFETY: The pointers produced by the code generated by these macros must
+    /// be valid to write 128 bytes to.
+    /// We omit unsafe in the macro's code in order to encourage the user to notice
+    /// the invariants they are expected to uphold.
+    #[macro_export]
+    macro_rules! _scalar_store {
+        (
+            $vector: expr,
+            $addr: expr $(,)?
+        ) => ({
+            let vector: $crate::scalar::I = $vector;
+            let addr: *mut _ = $addr;
+            core::ptr::write_unaligned(
+                addr as *mut $crate::scalar::I,
+                vector,
+            )
+        });
+        (
+            $vector: expr,
+            $addr: expr,
+            $index: expr $(,)?
+        ) => ({
+            let vector: $crate::scalar::I = $vector;
+
+            let addr: *mut _ = $addr;
+            core::ptr::write_unaligned(
+                addr.add($index) as *mut $crate::scalar::I,
+                vector,
+            )
+        });
+    }
+    pub use _scalar_store as store;
+
+    #[macro_export]
+    macro_rules! _scalar_pick_via_mask {
+        (
+            $zero_case: expr,
+            $one_case: expr,
+            $mask: expr $(,)?
+        ) => ({
+            $crate::scalar::pick_via_mask($zero_case, $one_case, $mask)
+        });
+    }
+    pub use _scalar_pick_via_mask as pick_via_mask;
+
+    #[macro_export]
+    macro_rules! _scalar_i32x4 {
+        (
+            $e0: expr,
+            $e1: expr,
+            $e2: expr,
+            $e3: expr $(,)?
+        ) => ({
+            let array: $crate::scalar::I = [$e0, $e1, $e2, $e3];
+            array
+        });
+    }
+    pub use _scalar_i32x4 as i32x4;
+
+    #[macro_export]
+    macro_rules! _scalar_and {
+        (
+            $a: expr,
+            $b: expr $(,)?
+        ) => ({
+            $crate::scalar::and($a, $b)
+        });
+    }
+    pub use _scalar_and as and;
+
+    #[macro_export]
+    macro_rules! _scalar_and_not {
+        (
+            $just_anded: expr,
+            $notted: expr $(,)?
+        ) => ({
+            $crate::scalar::and_not($just_anded, $notted)
+        });
+    }
+    pub use _scalar_and_not as and_not;
+
+    #[macro_export]
+    macro_rules! _scalar_or {
+        (
+            $a: expr,
+            $b: expr $(,)?
+        ) => ({
+            $crate::scalar::or($a, $b)
+        });
+    }
+    pub use _scalar_or as or;
+
+    #[macro_export]
+    macro_rules! _scalar_left_shift_32 {
+        (
+            $a: expr,
+            $imm8: expr $(,)?
+        ) => ({
+            $crate::scalar::left_shift_32($a, $imm8)
+        });
+    }
+    pub use _scalar_left_shift_32 as left_shift_32;
+
+    #[macro_export]
+    macro_rules! _scalar_right_shift_32 {
+        (
+            $a: expr,
+            $imm8: expr $(,)?
+        ) => ({
+            $crate::scalar::right_shift_32($a, $imm8)
+        });
+    }
+    pub use _scalar_right_shift_32 as right_shift_32;
+
+    #[macro_export]
+    macro_rules! _scalar_f32_to_u32 {
+        (
+            $a: expr $(,)?
+        ) => ({
+            $crate::scalar::f32_to_u32($a)
+        });
+    }
+    pub use _scalar_f32_to_u32 as f32_to_u32;
+
+    #[macro_export]
+    macro_rules! _scalar_u32_to_f32 {
+        (
+            $a: expr $(,)?
+        ) => ({
+            $crate::scalar::u32_to_f32($a)
+        });
+    }
+    pub use _scalar_u32_to_f32 as u32_to_f32;
+
+    #[macro_export]
+    macro_rules! _scalar_i32 {
+        (
+            $a: expr $(,)?
+        ) => ({
+            let a: i32 = $a;
+            let array: $crate::scalar::I = [a; $crate::scalar::WIDTH as usize];
+            array
+        });
+    }
+    pub use _scalar_i32 as i32;
+
+    #[macro_export]
+    macro_rules! _scalar_f32 {
+        (
+            $a: expr $(,)?
+        ) => ({
+            let a: f32 = $a;
+            let array: $crate::scalar::F = [a; $crate::scalar::WIDTH as usize];
+            array
+        });
+    }
+    pub use _scalar_f32 as f32;
+
+    #[macro_export]
+    macro_rules! _scalar_add_f32 {
+        (
+            $a: expr,
+            $b: expr $(,)?
+        ) => ({
+            $crate::scalar::add_f32($a, $b)
+        });
+    }
+    pub use _scalar_add_f32 as add_f32;
+
+    #[macro_export]
+    macro_rules! _scalar_add_i32 {
+        (
+            $a: expr,
+            $b: expr $(,)?
+        ) => ({
+            $crate::scalar::add_i32($a, $b)
+        });
+    }
+    pub use _scalar_add_i32 as add_i32;
+
+    #[macro_export]
+    macro_rules! _scalar_sub {
+        (
+            $a: expr,
+            $b: expr $(,)?
+        ) => ({
+            $crate::scalar::sub($a, $b)
+        });
+    }
+    pub use _scalar_sub as sub;
+
+    #[macro_export]
+    macro_rules! _scalar_mul {
+        (
+            $a: expr,
+            $b: expr $(,)?
+        ) => ({
+            $crate::scalar::mul($a, $b)
+        });
+    }
+    pub use _scalar_mul as mul;
+
+    #[macro_export]
+    macro_rules! _scalar_recip {
+        (
+            $a: expr $(,)?
+        ) => ({
+            $crate::scalar::recip($a)
+        });
+    }
+    pub use _scalar_recip as recip;
+
+    #[macro_export]
+    macro_rules! _scalar_sqrt {
+        (
+            $a: expr $(,)?
+        ) => ({
+            $crate::scalar::sqrt($a)
+        });
+    }
+    pub use _scalar_sqrt as sqrt;
+
+    #[macro_export]
+    macro_rules! _scalar_min {
+        (
+            $a: expr,
+            $b: expr $(,)?
+        ) => ({
+            $crate::scalar::min($a, $b)
+        });
+    }
+    pub use _scalar_min as min;
+
+    #[macro_export]
+    macro_rules! _scalar_lt_mask_32 {
+        (
+            $lhs: expr,
+            $rhs: expr $(,)?
+        ) => ({
+            $crate::scalar::lt_mask_32($lhs, $rhs)
+        });
+    }
+    pub use _scalar_lt_mask_32 as lt_mask_32;
+
+    #[macro_export]
+    macro_rules! _scalar_eq_mask_u32 {
+        (
+            $lhs: expr,
+            $rhs: expr $(,)?
+        ) => ({
+            $crate::scalar::eq_mask_u32($lhs, $rhs)
+        });
+    }
+    pub use _scalar_eq_mask_u32 as eq_mask_u32;
+
+    #[macro_export]
+    macro_rules! _scalar_gt_mask_32 {
+        (
+            $lhs: expr,
+            $rhs: expr $(,)?
+        ) => ({
+            $crate::scalar::gt_mask_32($lhs, $rhs)
+        });
+    }
+    pub use _scalar_gt_mask_32 as gt_mask_32;
+
+    #[macro_export]
+    macro_rules! _scalar_shuffle_32 {
+        (
+            $v: expr,
+            $i0: literal,
+            $i1: literal,
+            $i2: literal,
+            $i3: literal $(,)?
+        ) => ({
+            $crate::scalar::shuffle_32($v, [$i0, $i1, $i2, $i3])
+        });
+    }
+    pub use _scalar_shuffle_32 as shuffle_32;
+}
+
+#[cfg(not(any(
+    all(
+        target_arch = "x86_64",
+        target_feature = "sse",
+        target_feature = "sse2",
+    ),
+    all(
+        target_arch = "wasm32",
+        target_feature = "simd128",
+    ),
+    all(
+        target_arch = "aarch64",
+        target_feature = "neon",
+    ),
+)))]
+use scalar as wide;
+
+#[cfg(test)]
+mod wide_tests {
+    use super::wide::*;
+
+    /// Thses names chosen to be the same length as `0`.
+    const A: i32 = 0xAAAA_AAAAu32 as i32;
+    const B: i32 = 0xBBBB_BBBBu32 as i32;
+    const C: i32 = 0xCCCC_CCCCu32 as i32;
+    const D: i32 = 0xDDDD_DDDDu32 as i32;
+    const F: i32 = 0xFFFF_FFFFu32 as i32;
+
+    macro_rules! to_array {
+        ($v: expr) => ({
+            let mut arr = [0; WIDTH as usize];
+            let v = $v;
+
+            // SAFETY: The pointers produced by the code generated by
+            // these macros are valid to read 128 bytes from.
+            unsafe {
+                store!(v, &mut arr);
+            }
+
+            arr
+        })
+    }
+
+    #[test]
+    fn pick_via_mask_returns_the_expected_result_in_this_or_like_case() {
+        let left = i32x4!(A, B, 0, 0);
+        let right = i32x4!(0, 0, C, D);
+        let mask = i32x4!(0, 0, F, F);
+
+        let actual = pick_via_mask!(
+            left,
+            right,
+            mask,
+        );
+
+        assert_eq!(
+            to_array!(actual),
+            to_array!(i32x4!(A, B, C, D))
+        );
+    }
+
+    #[test]
+    fn shuffle_32_picks_the_lanes_at_the_indices() {
+        let v = i32x4!(A, B, C, D);
+
+        assert_eq!(to_array!(shuffle_32!(v, 0, 0, 1, 1)), [A, A, B, B]);
+        assert_eq!(to_array!(shuffle_32!(v, 2, 3, 3, 3)), [C, D, D, D]);
+        assert_eq!(to_array!(shuffle_32!(v, 3, 2, 1, 0)), [D, C, B, A]);
+    }
+}
+
+#[cfg(test)]
+mod scalar_tests {
+    use super::scalar::*;
+
+    const A: i32 = 0xAAAA_AAAAu32 as i32;
+    const B: i32 = 0xBBBB_BBBBu32 as i32;
+    const C: i32 = 0xCCCC_CCCCu32 as i32;
+    const D: i32 = 0xDDDD_DDDDu32 as i32;
+    const F: i32 = 0xFFFF_FFFFu32 as i32;
+
+    #[test]
+    fn pick_via_mask_returns_the_expected_result_in_this_or_like_case() {
+        let actual = pick_via_mask!(
+            i32x4!(A, B, 0, 0),
+            i32x4!(0, 0, C, D),
+            i32x4!(0, 0, F, F),
+        );
+
+        assert_eq!(actual, [A, B, C, D]);
+    }
+
+    #[test]
+    fn right_shift_32_shifts_in_zeroes() {
+        assert_eq!(right_shift_32!(i32!(F), 24), [0xFF; 4]);
+    }
+
+    #[test]
+    fn f32_to_u32_rounds_ties_to_even() {
+        assert_eq!(
+            f32_to_u32!([0.5, 1.5, 2.5, 254.5]),
+            [0, 2, 2, 254]
+        );
+    }
+}
+
+/// Returns a pointer that is valid to read `N` elements from, which point to the
+/// same values as `slice[index..]`. If there are less than `N` elements left in
+/// `slice`, the available ones are copied into `padding`, followed by zeroes,
+/// and a pointer to that is returned instead.
+fn readable_ptr<const N: usize>(
+    slice: &[ARGB],
+    index: usize,
+    padding: &mut [ARGB; N],
+) -> *const ARGB {
+    match slice.get(index..index + N) {
+        Some(elements) => elements.as_ptr(),
+        None => {
+            let available = slice.get(index..).unwrap_or_default();
+            padding[..available.len()].copy_from_slice(available);
+            padding.as_ptr()
+        }
+    }
+}
+
+/// Returns the points the command draws. Ranges past the end of `points` are
+/// treated as empty.
+fn points_of<'points>(command: &Command, points: &'points [Point]) -> &'points [Point] {
+    points.get(command.points.range()).unwrap_or_default()
+}
+
+/// Blends the points of a points command onto the unscaled buffer, skipping any
+/// that are outside the command's rect or the clip rect. Each point covers a
+/// single pixel, so unlike sprites, there is nothing to gain from going wide.
+fn blend_points(
+    unscaled_buffer: &mut [ARGB; command::LENGTH],
+    points: &[Point],
+    &Command { rect, modulation, blend_mode, .. }: &Command,
+    clip_rects: &[clip::Rect],
+) {
+    // Opaque texels drawn this way come out exactly as they went in, so we can
+    // skip the blending math for them.
+    let is_unchanged_if_opaque = matches!(blend_mode, BlendMode::Over | BlendMode::Replace)
+        && modulation.tint == 0xFFFF_FFFF
+        && modulation.alpha == 0xFF;
+
+    let x_range = clip::X::from(rect.x_min)..=clip::X::from(rect.x_max);
+    let y_range = clip::Y::from(rect.y_min)..=clip::Y::from(rect.y_max);
+
+    for &Point { x, y, colour } in points {
+        let (x, y) = (clip::X::from(x), clip::Y::from(y));
+        if !x_range.contains(&x)
+        || !y_range.contains(&y)
+        || !clip_rects.iter().any(|r| r.x.contains(&x) && r.y.contains(&y)) {
+            continue
+        }
+
+        let i = usize::from(y) * usize::from(command::WIDTH) + usize::from(x);
+
+        let texel = match modulation.replace {
+            Some(replace) if colour >> 24 == 0xFF => replace,
+            _ => colour,
+        };
+
+        unscaled_buffer[i] = if is_unchanged_if_opaque && texel >> 24 == 0xFF {
+            texel
+        } else {
+            reference::blend(texel, unscaled_buffer[i], &modulation, blend_mode)
+        };
+    }
+}
+
+/// Defines a function that blends the given commands onto the unscaled buffer,
+/// using the given `wide` implementation. This is a macro so that we can
+/// test that each implementation produces the same pixels.
+macro_rules! def_blend_commands {
+    ($name: ident, $wide: ident) => {
+        fn $name(
+            unscaled_buffer: &mut [ARGB; command::LENGTH],
+            commands: &[Command],
+            points: &[Point],
+            clip_rects: &[clip::Rect],
+            atlases: &atlas::Atlases,
+        ) {
+            const LANES: usize = $wide::WIDTH as usize;
+
+            let wide_0 = $wide::i32!(0);
+            let wide_1_f32 = $wide::f32!(1.);
+            let wide_255_i32 = $wide::i32!(255);
+            let wide_inv_255_f32 = $wide::f32!(1./255.);
+            let wide_255_f32 = $wide::f32!(255.);
+            let wide_0_to_w = $wide::i32x4!(0, 1, 2, 3);
+
+            for command in commands.iter() {
+                let &Command {
+                    sprite_xy: sprite::XY { x: sprite_x, y: sprite_y },
+                    modulation,
+                    rect,
+                    orientation,
+                    blend_mode,
+                    atlas,
+                    points: command_points,
+                    tile,
+                } = command;
+
+                if !command_points.is_empty() {
+                    blend_points(
+                        unscaled_buffer,
+                        points_of(command, points),
+                        command,
+                        clip_rects,
+                    );
+                    continue
+                }
+
+                let Some(atlas) = atlases.get(atlas) else {
+                    // There is nothing to draw from.
+                    continue
+                };
+
+                let [tint_a, tint_r, tint_g, tint_b] = modulation.tint.to_be_bytes()
+                    .map(|c| reference::to_linear(c.into()));
+                let tint_a = tint_a * reference::to_linear(modulation.alpha.into());
+
+                if tint_a == 0. && blend_mode != BlendMode::Replace {
+                    // Every texel is fully transparent, so nothing would change.
+                    continue
+                }
+
+                let wide_tint_a = $wide::f32!(tint_a);
+                let wide_tint_r = $wide::f32!(tint_r);
+                let wide_tint_g = $wide::f32!(tint_g);
+                let wide_tint_b = $wide::f32!(tint_b);
+
+                let replace_value = $wide::i32!(
+                    modulation.replace.unwrap_or_default() as i32
+                );
+                let not_replace_mask = $wide::i32!(
+                    if modulation.replace.is_none() { -1 } else { 0 }
+                );
+
+                let Rect {
+                    x_min,
+                    y_min,
+                    x_max,
+                    y_max,
+                } = rect;
+
+                // TODO make this wide too?
+                let x_min = clip::X::from(x_min);
+                let y_min = clip::Y::from(y_min);
+                let x_max = clip::W::from(x_max);
+                let y_max = clip::H::from(y_max);
+
+                let sprite_x = usize::from(sprite_x);
+                let sprite_y = usize::from(sprite_y);
+
+                let src_w = atlas.width();
+
+                let rect_w = usize::from(x_max + 1 - x_min);
+                let rect_h = usize::from(y_max + 1 - y_min);
+
+                for clip_rect in clip_rects {
+                    // Only touch the part of the command inside the clip rect.
+                    let x_start = core::cmp::max(x_min, clip_rect.x.start);
+                    let y_start = core::cmp::max(y_min, clip_rect.y.start);
+                    let x_end = core::cmp::min(x_max + 1, clip_rect.x.end);
+                    let y_end = core::cmp::min(y_max + 1, clip_rect.y.end);
+                    if x_start >= x_end || y_start >= y_end {
+                        continue
+                    }
+                    let wide_x_end = $wide::i32!(x_end.into());
+
+                    let mut y_iter_count = usize::from(y_start - y_min);
+                    for y in y_start..y_end {
+                        let mut x_iter_count = usize::from(x_start - x_min);
+                        let mut x = x_start;
+
+                        while x < x_end {
+                            let wide_xs = $wide::add_i32!(
+                                $wide::i32!(x.into()),
+                                wide_0_to_w
+                            );
+
+                            let dest_index = usize::from(y)
+                                * usize::from(command::WIDTH)
+                                + usize::from(x);
+
+                            let mut unders_padding = [0; LANES];
+                            // SAFETY: `readable_ptr` returns a pointer that is valid
+                            // to read `LANES` elements from.
+                            let unders = unsafe {
+                                $wide::load!(
+                                    readable_ptr(
+                                        &unscaled_buffer[..],
+                                        dest_index,
+                                        &mut unders_padding,
+                                    ),
+                                )
+                            };
+
+                            // When tiling, the texels are only contiguous if the
+                            // lanes we need stay within one row of the tile.
+                            let tile_offset = (!tile.is_empty())
+                                .then(|| tile.offset((x_iter_count, y_iter_count)));
+                            let is_contiguous = orientation == Orientation::default()
+                            && tile_offset.is_none_or(|(tile_x, _)| {
+                                let needed = core::cmp::min(
+                                    LANES,
+                                    usize::from(x_end) - usize::from(x),
+                                );
+                                usize::from(tile_x) + needed <= usize::from(tile.w)
+                            });
+
+                            let gfx_colours = if is_contiguous {
+                                let (u, v) = tile_offset.map_or(
+                                    (x_iter_count, y_iter_count),
+                                    |(u, v)| (usize::from(u), usize::from(v)),
+                                );
+                                let base_src_i =
+                                    (sprite_y + v) * src_w
+                                    + (sprite_x + u);
+
+                                // Reading past the end of the atlas gives transparent
+                                // pixels.
+                                let mut gfx_padding = [0; LANES];
+                                // SAFETY: `readable_ptr` returns a pointer that is valid
+                                // to read `LANES` elements from.
+                                unsafe {
+                                    $wide::load!(
+                                        readable_ptr(
+                                            atlas.pixels(),
+                                            base_src_i,
+                                            &mut gfx_padding,
+                                        ),
+                                    )
+                                }
+                            } else {
+                                // The source pixels are not contiguous in general,
+                                // so we gather them one at a time.
+                                let mut texels = [0; LANES];
+                                for (lane, texel) in texels.iter_mut().enumerate() {
+                                    let dx = x_iter_count + lane;
+                                    if dx >= rect_w {
+                                        // This lane won't be written anyway.
+                                        break
+                                    }
+
+                                    let (u, v) = if tile.is_empty() {
+                                        orientation.source_offset(
+                                            (dx as sprite::Inner, y_iter_count as sprite::Inner),
+                                            (rect_w as sprite::Inner, rect_h as sprite::Inner),
+                                        )
+                                    } else {
+                                        orientation.source_offset(
+                                            tile.offset((dx, y_iter_count)),
+                                            (tile.w, tile.h),
+                                        )
+                                    };
+
+                                    *texel = atlas.pixels().get(
+                                        (sprite_y + usize::from(v)) * src_w
+                                        + sprite_x + usize::from(u)
+                                    ).copied().unwrap_or(0) as i32;
+                                }
+
+                                $wide::i32x4!(
+                                    texels[0],
+                                    texels[1],
+                                    texels[2],
+                                    texels[3],
+                                )
+                            };
+
+                            let is_full_alpha_mask = $wide::eq_mask_u32!(
+                                $wide::right_shift_32!(
+                                    gfx_colours,
+                                    24
+                                ),
+                                wide_255_i32
+                            );
+
+                            let do_replace_mask = $wide::and_not!(
+                                is_full_alpha_mask,
+                                not_replace_mask
+                            );
+
+                            let gfx_colours = $wide::pick_via_mask!(
+                                gfx_colours,
+                                replace_value,
+                                do_replace_mask,
+                            );
+
+                            let should_write =
+                                $wide::lt_mask_32!(
+                                    wide_xs,
+                                    wide_x_end
+                                );
+
+                            let rendered = if blend_mode == BlendMode::Replace
+                            && modulation.is_replace_only() {
+                                gfx_colours
+                            } else {
+                                // Don't need to mask the shifted in zeroes.
+                                let gfx_colour_a = $wide::right_shift_32!(
+                                    gfx_colours,
+                                    24
+                                );
+
+                                let gfx_colour_r = $wide::and!(
+                                    $wide::right_shift_32!(
+                                        gfx_colours,
+                                        16
+                                    ),
+                                    wide_255_i32
+                                );
+
+                                let gfx_colour_g = $wide::and!(
+                                    $wide::right_shift_32!(
+                                        gfx_colours,
+                                        8
+                                    ),
+                                    wide_255_i32
+                                );
+
+                                // Don't need to shift since it's already in the right spot
+                                let gfx_colour_b = $wide::and!(
+                                    gfx_colours,
+                                    wide_255_i32
+                                );
+
+                                // Don't need to mask the shifted in zeroes.
+                                let under_a = $wide::right_shift_32!(
+                                    unders,
+                                    24
+                                );
+
+                                let under_r = $wide::and!(
+                                    $wide::right_shift_32!(
+                                        unders,
+                                        16
+                                    ),
+                                    wide_255_i32
+                                );
+
+                                let under_g = $wide::and!(
+                                    $wide::right_shift_32!(
+                                        unders,
+                                        8
+                                    ),
+                                    wide_255_i32
+                                );
+
+                                // Don't need to shift since it's already in the right spot
+                                let under_b = $wide::and!(
+                                    unders,
+                                    wide_255_i32
+                                );
+
+                                // gamma to linear
+                                let mut a_g = $wide::mul!(
+                                    $wide::u32_to_f32!(
+                                        gfx_colour_a
+                                    ),
+                                    wide_inv_255_f32
+                                );
+                                a_g = $wide::mul!(a_g, a_g);
+                                let mut r_g = $wide::mul!(
+                                    $wide::u32_to_f32!(
+                                        gfx_colour_r
+                                    ),
+                                    wide_inv_255_f32
+                                );
+                                r_g = $wide::mul!(r_g, r_g);
+                                let mut g_g = $wide::mul!(
+                                    $wide::u32_to_f32!(
+                                        gfx_colour_g
+                                    ),
+                                    wide_inv_255_f32
+                                );
+                                g_g = $wide::mul!(g_g, g_g);
+                                let mut b_g = $wide::mul!(
+                                    $wide::u32_to_f32!(
+                                        gfx_colour_b
+                                    ),
+                                    wide_inv_255_f32
+                                );
+                                b_g = $wide::mul!(b_g, b_g);
+
+                                a_g = $wide::mul!(a_g, wide_tint_a);
+                                r_g = $wide::mul!(r_g, wide_tint_r);
+                                g_g = $wide::mul!(g_g, wide_tint_g);
+                                b_g = $wide::mul!(b_g, wide_tint_b);
+
+                                let mut a_u = $wide::mul!(
+                                    $wide::u32_to_f32!(
+                                        under_a
+                                    ),
+                                    wide_inv_255_f32
+                                );
+                                a_u = $wide::mul!(a_u, a_u);
+                                let mut r_u = $wide::mul!(
+                                    $wide::u32_to_f32!(
+                                        under_r
+                                    ),
+                                    wide_inv_255_f32
+                                );
+                                r_u = $wide::mul!(r_u, r_u);
+                                let mut g_u = $wide::mul!(
+                                    $wide::u32_to_f32!(
+                                        under_g
+                                    ),
+                                    wide_inv_255_f32
+                                );
+                                g_u = $wide::mul!(g_u, g_u);
+                                let mut b_u = $wide::mul!(
+                                    $wide::u32_to_f32!(
+                                        under_b
+                                    ),
+                                    wide_inv_255_f32
+                                );
+                                b_u = $wide::mul!(b_u, b_u);
+
+                                let (o_a, o_r, o_g, o_b) = if blend_mode == BlendMode::Replace {
+                                    (a_g, r_g, g_g, b_g)
+                                } else {
+                                    if blend_mode == BlendMode::Multiply {
+                                        // Multiplying is the same as regular alpha
+                                        // blending, but with the product as the colour.
+                                        r_g = $wide::mul!(r_g, r_u);
+                                        g_g = $wide::mul!(g_g, g_u);
+                                        b_g = $wide::mul!(b_g, b_u);
+                                    }
+
+                                    // perform alpha blending
+                                    let o_a = $wide::add_f32!(
+                                        a_g,
+                                        $wide::mul!(
+                                            a_u,
+                                            $wide::sub!(wide_1_f32, a_g)
+                                        )
+                                    );
+
+                                    let inv_o_a = $wide::recip!(o_a);
+
+                                    // When adding, all of what is underneath is kept.
+                                    let under_factor = if blend_mode == BlendMode::Add {
+                                        wide_1_f32
+                                    } else {
+                                        $wide::sub!(wide_1_f32, a_g)
+                                    };
+
+                                    let o_r = $wide::min!(
+                                        $wide::mul!(
+                                            $wide::add_f32!(
+                                                $wide::mul!(r_g, a_g),
+                                                $wide::mul!(
+                                                    r_u,
+                                                    under_factor
+                                                )
+                                            ),
+                                            inv_o_a
+                                        ),
+                                        wide_1_f32
+                                    );
+                                    let o_g = $wide::min!(
+                                        $wide::mul!(
+                                            $wide::add_f32!(
+                                                $wide::mul!(g_g, a_g),
+                                                $wide::mul!(
+                                                    g_u,
+                                                    under_factor
+                                                )
+                                            ),
+                                            inv_o_a
+                                        ),
+                                        wide_1_f32
+                                    );
+                                    let o_b = $wide::min!(
+                                        $wide::mul!(
+                                            $wide::add_f32!(
+                                                $wide::mul!(b_g, a_g),
+                                                $wide::mul!(
+                                                    b_u,
+                                                    under_factor
+                                                )
+                                            ),
+                                            inv_o_a
+                                        ),
+                                        wide_1_f32
+                                    );
+
+                                    (o_a, o_r, o_g, o_b)
+                                };
+
+                                // linear to gamma
+                                let rendered_a = $wide::f32_to_u32!(
+                                    $wide::mul!(
+                                        wide_255_f32,
+                                        $wide::sqrt!(o_a)
+                                    )
+                                );
+                                let rendered_r = $wide::f32_to_u32!(
+                                    $wide::mul!(
+                                        wide_255_f32,
+                                        $wide::sqrt!(o_r)
+                                    )
+                                );
+                                let rendered_g = $wide::f32_to_u32!(
+                                    $wide::mul!(
+                                        wide_255_f32,
+                                        $wide::sqrt!(o_g)
+                                    )
+                                );
+                                let rendered_b = $wide::f32_to_u32!(
+                                    $wide::mul!(
+                                        wide_255_f32,
+                                        $wide::sqrt!(o_b)
+                                    )
+                                );
+
+                                let rendered = $wide::or!(
+                                    $wide::or!(
+                                        $wide::left_shift_32!(rendered_a, 24),
+                                        $wide::left_shift_32!(rendered_r, 16),
+                                    ),
+                                    $wide::or!(
+                                        $wide::left_shift_32!(rendered_g, 8),
+                                        rendered_b,
+                                    )
+                                );
+
+                                if blend_mode == BlendMode::Replace {
+                                    rendered
+                                } else {
+                                    // If both alphas are zero, then so is `o_a`, and
+                                    // the other channels end up as NaN, which each
+                                    // backend converts differently. So we pick
+                                    // transparent black.
+                                    let is_transparent_mask = $wide::eq_mask_u32!(
+                                        $wide::or!(gfx_colour_a, under_a),
+                                        wide_0
+                                    );
+
+                                    $wide::pick_via_mask!(
+                                        rendered,
+                                        wide_0,
+                                        is_transparent_mask,
+                                    )
+                                }
+                            };
+
+                            let to_store = $wide::pick_via_mask!(
+                                unders,
+                                rendered,
+                                should_write
+                            );
+
+                            if dest_index + LANES <= unscaled_buffer.len() {
+                                // SAFETY: We just checked that there are `LANES`
+                                // elements to write to, starting at `dest_index`.
+                                unsafe {
+                                    $wide::store!(
+                                        to_store,
+                                        unscaled_buffer.as_mut_ptr(),
+                                        dest_index,
+                                    );
+                                }
+                            } else {
+                                let mut padding = [0; LANES];
+                                // SAFETY: `padding` is `LANES` elements long.
+                                unsafe {
+                                    $wide::store!(
+                                        to_store,
+                                        padding.as_mut_ptr(),
+                                    );
+                                }
+                                let available = &mut unscaled_buffer[dest_index..];
+                                let available_len = available.len();
+                                available.copy_from_slice(&padding[..available_len]);
+                            }
+
+                            x_iter_count += $wide::WIDTH as usize;
+                            x += $wide::WIDTH;
+                        }
+
+                        y_iter_count += 1;
+                    }
+                }
+            }
+        }
+    }
+}
+
+def_blend_commands!(blend_commands, wide);
+
+#[cfg(test)]
+def_blend_commands!(scalar_blend_commands, scalar);
+
+#[cfg(test)]
+mod arb {
+    use super::*;
+    use platform_types::{GFX_HEIGHT, GFX_WIDTH, PALETTE};
+    use xs::Xs;
+
+    fn gen(rng: &mut Xs, range: core::ops::Range<u16>) -> u16 {
+        xs::range(rng, range.start.into()..range.end.into()) as u16
+    }
+
+    /// Prefers values near the edges of the given range, since that is where
+    /// the most interesting cases are.
+    fn edgy(rng: &mut Xs, max: u16) -> u16 {
+        match gen(rng, 0..4) {
+            0 => 0,
+            1 => max.saturating_sub(gen(rng, 0..8)),
+            _ => gen(rng, 0..max + 1),
+        }
+    }
+
+    /// The sizes of the atlases, after the built-in one, that `atlases`
+    /// produces. These are deliberately not multiples of the lane count.
+    pub const ATLAS_SIZES: [(usize, usize); 2] = [(13, 7), (41, 33)];
+
+    /// The size of the texture that `atlases` produces.
+    pub const TEXTURE_SIZE: (usize, usize) = (29, 17);
+
+    fn pixels(rng: &mut Xs, (width, height): (usize, usize)) -> Vec<ARGB> {
+        (0..width * height)
+            .map(|_| match gen(rng, 0..4) {
+                0 => 0,
+                1 => xs::range(rng, 0..u32::MAX),
+                _ => 0xFF00_0000 | xs::range(rng, 0..0x100_0000),
+            })
+            .collect()
+    }
+
+    pub fn atlases(rng: &mut Xs) -> atlas::Atlases {
+        let mut atlases = atlas::Atlases::default();
+
+        for (width, height) in ATLAS_SIZES {
+            atlases.push(
+                atlas::Atlas::from_pixels(width, height, pixels(rng, (width, height)))
+                    .expect("pixel count should match the size")
+            );
+        }
+
+        let mut texture = platform_types::Texture::new(TEXTURE_SIZE.0, TEXTURE_SIZE.1);
+        texture.pixels_mut().copy_from_slice(&pixels(rng, TEXTURE_SIZE));
+        atlases.set_texture(&texture);
+
+        atlases
+    }
+
+    /// How many points `points` produces.
+    pub const POINT_COUNT: u32 = 64;
+
+    pub fn points(rng: &mut Xs) -> Vec<Point> {
+        (0..POINT_COUNT)
+            .map(|_| Point {
+                x: command::X::clipped_inner(edgy(rng, command::WIDTH - 1)),
+                y: command::Y::clipped_inner(edgy(rng, command::HEIGHT - 1)),
+                colour: match gen(rng, 0..4) {
+                    0 => 0,
+                    1 => xs::range(rng, 0..u32::MAX),
+                    _ => 0xFF00_0000 | xs::range(rng, 0..0x100_0000),
+                },
+            })
+            .collect()
+    }
+
+    pub fn command(rng: &mut Xs) -> Command {
+        let x = edgy(rng, command::WIDTH - 1);
+        let y = edgy(rng, command::HEIGHT - 1);
+        let w = match gen(rng, 0..8) {
+            0 => command::WIDTH,
+            _ => gen(rng, 1..64),
+        };
+        let h = match gen(rng, 0..8) {
+            0 => command::HEIGHT,
+            _ => gen(rng, 1..64),
+        };
+
+        // The last id is deliberately one past the atlases `atlases` produces.
+        let atlas = match gen(rng, 0..11) {
+            0 => atlas::Id(1),
+            1 => atlas::Id(2),
+            2 => atlas::Id(ATLAS_SIZES.len() as u8 + 1),
+            3 => atlas::Id::TEXTURE,
+            4 => atlas::Id::SOLID,
+            _ => atlas::Id::BUILT_IN,
+        };
+        let (atlas_width, atlas_height) = if atlas == atlas::Id::TEXTURE {
+            TEXTURE_SIZE
+        } else if atlas == atlas::Id::SOLID {
+            (1, 1)
+        } else {
+            ATLAS_SIZES
+                .get(usize::from(atlas.0).wrapping_sub(1))
+                .copied()
+                .unwrap_or((GFX_WIDTH, GFX_HEIGHT))
+        };
+
+        const SPRITE_SLOP: u16 = 16;
+        let sprite_x = edgy(rng, atlas_width as u16 + SPRITE_SLOP);
+        let sprite_y = edgy(rng, atlas_height as u16 + SPRITE_SLOP);
+
+        let mut modulation = Modulation::IDENTITY;
+        match gen(rng, 0..6) {
+            0 | 1 => {
+                modulation.replace = Some(
+                    PALETTE[usize::from(gen(rng, 0..PALETTE.len() as u16))]
+                );
+            },
+            2 => {
+                modulation.replace = Some(0);
+            },
+            _ => {},
+        }
+        if gen(rng, 0..4) == 0 {
+            modulation.tint = xs::range(rng, 0..u32::MAX);
+        }
+        if gen(rng, 0..4) == 0 {
+            modulation.alpha = gen(rng, 0..256) as u8;
+        }
+
+        let mut orientation = Orientation::default();
+        for flag in [Orientation::FLIP_X, Orientation::FLIP_Y, Orientation::TRANSPOSE] {
+            if gen(rng, 0..4) == 0 {
+                orientation.insert(flag);
+            }
+        }
+
+        let blend_mode = match gen(rng, 0..8) {
+            0 => BlendMode::Add,
+            1 => BlendMode::Multiply,
+            2 => BlendMode::Replace,
+            _ => BlendMode::Over,
+        };
+
+        // The end can be past `POINT_COUNT`, in which case no points are drawn.
+        let points = match gen(rng, 0..8) {
+            0 => {
+                let start = u32::from(gen(rng, 0..POINT_COUNT as u16));
+                command::Points {
+                    start,
+                    end: start + u32::from(gen(rng, 1..POINT_COUNT as u16 + 1)),
+                }
+            },
+            _ => command::Points::default(),
+        };
+
+        // The offsets can be past the size, which should wrap around.
+        let tile = match gen(rng, 0..4) {
+            0 => command::Tile {
+                w: gen(rng, 0..24),
+                h: gen(rng, 0..24),
+                x_offset: gen(rng, 0..32),
+                y_offset: gen(rng, 0..32),
+            },
+            _ => command::Tile::default(),
+        };
+
+        Command {
+            rect: Rect::from_unscaled(unscaled::Rect {
+                x: unscaled::X(x),
+                y: unscaled::Y(y),
+                w: unscaled::W(w),
+                h: unscaled::H(h),
+            }),
+            sprite_xy: sprite::XY {
+                x: sprite::X(sprite_x),
+                y: sprite::Y(sprite_y),
+            },
+            modulation,
+            orientation,
+            blend_mode,
+            atlas,
+            points,
+            tile,
+        }
+    }
+
+    pub fn commands(rng: &mut Xs) -> Vec<Command> {
+        let count = gen(rng, 1..128);
+
+        (0..count).map(|_| command(rng)).collect()
+    }
+}
+
+#[cfg(test)]
+fn assert_same_pixels(expected: &[ARGB], actual: &[ARGB]) {
+    assert_eq!(expected.len(), actual.len());
+    for (i, (e, a)) in expected.iter().zip(actual.iter()).enumerate() {
+        assert_eq!(
+            e,
+            a,
+            "mismatch at ({}, {}): 0x{e:08X} != 0x{a:08X}",
+            i % usize::from(command::WIDTH),
+            i / usize::from(command::WIDTH),
+        );
+    }
+}
+
+#[cfg(test)]
+mod blend_commands_produces_the_same_pixels_as_scalar_blend_commands {
+    use super::*;
+
+    fn assert_same_blended_pixels(
+        commands: &[Command],
+        points: &[Point],
+        atlases: &atlas::Atlases,
+    ) {
+        let mut expected = Box::new([colours::BLACK; command::LENGTH]);
+        let mut actual = Box::new([colours::BLACK; command::LENGTH]);
+
+        scalar_blend_commands(&mut expected, commands, points, core::slice::from_ref(&UNSCALED_RECT), atlases);
+        blend_commands(&mut actual, commands, points, core::slice::from_ref(&UNSCALED_RECT), atlases);
+
+        assert_same_pixels(&expected[..], &actual[..]);
+    }
+
+    #[test]
+    fn on_the_empty_slice() {
+        assert_same_blended_pixels(&[], &[], &atlas::Atlases::default());
+    }
+
+    #[test]
+    fn on_these_random_commands() {
+        let mut rng = xs::from_seed([0xB1; 16]);
+
+        for _ in 0..64 {
+            let atlases = arb::atlases(&mut rng);
+            let points = arb::points(&mut rng);
+            assert_same_blended_pixels(&arb::commands(&mut rng), &points, &atlases);
+        }
+    }
+
+    #[test]
+    fn into_several_rects_at_once_as_into_one_rect_at_a_time() {
+        let mut rng = xs::from_seed([0xB2; 16]);
+
+        let (w, h) = (command::WIDTH, command::HEIGHT);
+        let rects = [
+            clip::Rect { x: 0..w / 3, y: 0..h / 2 },
+            clip::Rect { x: w / 3..w, y: h / 4..h / 2 },
+            clip::Rect { x: w / 2..w, y: h / 2..h },
+        ];
+
+        for _ in 0..16 {
+            let atlases = arb::atlases(&mut rng);
+            let points = arb::points(&mut rng);
+            let commands = arb::commands(&mut rng);
+
+            let mut expected = Box::new([colours::BLACK; command::LENGTH]);
+            for rect in &rects {
+                blend_commands(&mut expected, &commands, &points, core::slice::from_ref(rect), &atlases);
+            }
+
+            let mut actual = Box::new([colours::BLACK; command::LENGTH]);
+            blend_commands(&mut actual, &commands, &points, &rects, &atlases);
+
+            assert_same_pixels(&expected[..], &actual[..]);
+        }
+    }
+}
+
+/// A simple per-pixel version of the blending that `render` does, meant to be
+/// obviously correct rather than fast. Since it does the same floating point
+/// operations in the same order, it should give exactly the same pixels.
+pub mod reference {
+    use super::*;
+
+    pub fn to_linear(channel: ARGB) -> f32 {
+        let c = channel as f32 * (1./255.);
+        c * c
+    }
+
+    fn to_gamma(linear: f32) -> ARGB {
+        (255. * linear.sqrt()).round_ties_even() as i32 as ARGB
+    }
+
+    /// Blends `over`, after applying the tint and alpha from `modulation`, on
+    /// top of `under` using the given blend mode, in a roughly linear colour
+    /// space.
+    pub fn blend(
+        over: ARGB,
+        under: ARGB,
+        modulation: &Modulation,
+        blend_mode: BlendMode,
+    ) -> ARGB {
+        if blend_mode == BlendMode::Replace && modulation.is_replace_only() {
+            return over
+        }
+
+        let [tint_a, tint_r, tint_g, tint_b] = modulation.tint.to_be_bytes()
+            .map(|c| to_linear(c.into()));
+        let tint_a = tint_a * to_linear(modulation.alpha.into());
+
+        let [a_g, r_g, g_g, b_g] = over.to_be_bytes().map(|c| to_linear(c.into()));
+        let (a_g, mut r_g, mut g_g, mut b_g) = (
+            a_g * tint_a,
+            r_g * tint_r,
+            g_g * tint_g,
+            b_g * tint_b,
+        );
+
+        if blend_mode == BlendMode::Replace {
+            return to_gamma(a_g) << 24
+            | to_gamma(r_g) << 16
+            | to_gamma(g_g) << 8
+            | to_gamma(b_g)
+        }
+
+        if tint_a == 0. {
+            return under
+        }
+
+        if over >> 24 == 0 && under >> 24 == 0 {
+            return 0
+        }
+
+        let [a_u, r_u, g_u, b_u] = under.to_be_bytes().map(|c| to_linear(c.into()));
+
+        if blend_mode == BlendMode::Multiply {
+            r_g *= r_u;
+            g_g *= g_u;
+            b_g *= b_u;
+        }
+
+        let o_a = a_g + a_u * (1. - a_g);
+        let inv_o_a = 1. / o_a;
+        let under_factor = if blend_mode == BlendMode::Add {
+            1.
+        } else {
+            1. - a_g
+        };
+
+        let o_r = ((r_g * a_g + r_u * under_factor) * inv_o_a).min(1.);
+        let o_g = ((g_g * a_g + g_u * under_factor) * inv_o_a).min(1.);
+        let o_b = ((b_g * a_g + b_u * under_factor) * inv_o_a).min(1.);
+
+        to_gamma(o_a) << 24
+        | to_gamma(o_r) << 16
+        | to_gamma(o_g) << 8
+        | to_gamma(o_b)
+    }
 
-    #[macro_export]
-    macro_rules! _i32x4 {
-        (
-            $e0: expr,
-            $e1: expr,
-            $e2: expr,
-            $e3: expr $(,)?
-        ) => ({#[allow(unused_unsafe)]
-            unsafe {
-                core::arch::wasm32::i32x4(
-                    $e0,
-                    $e1,
-                    $e2,
-                    $e3,
-                )
-            }
-        });
+    /// Returns the colour of the sprite sheet pixel to draw at the given offset
+    /// into the command's rect, after applying the tile and orientation, and
+    /// the replace colour, if any. Pixels past the end of the atlas are
+    /// transparent.
+    pub fn texel(
+        command: &Command,
+        atlas: &atlas::Atlas,
+        (dx, dy): (usize, usize),
+    ) -> ARGB {
+        let sprite_x = usize::from(command.sprite_xy.x);
+        let sprite_y = usize::from(command.sprite_xy.y);
+
+        let rect = command.rect.unscaled();
+        let tile = command.tile;
+        let (u, v) = if tile.is_empty() {
+            command.orientation.source_offset(
+                (dx as sprite::Inner, dy as sprite::Inner),
+                (rect.w.get(), rect.h.get()),
+            )
+        } else {
+            let tile_x = (usize::from(tile.x_offset) + dx) % usize::from(tile.w);
+            let tile_y = (usize::from(tile.y_offset) + dy) % usize::from(tile.h);
+
+            command.orientation.source_offset(
+                (tile_x as sprite::Inner, tile_y as sprite::Inner),
+                (tile.w, tile.h),
+            )
+        };
+        let (u, v) = (usize::from(u), usize::from(v));
+
+        let texel = atlas.pixels().get((sprite_y + v) * atlas.width() + sprite_x + u)
+            .copied()
+            .unwrap_or(0);
+
+        match command.modulation.replace {
+            Some(colour) if texel >> 24 == 0xFF => colour,
+            _ => texel,
+        }
     }
-    pub use _i32x4 as i32x4;
 
-    #[macro_export]
-    macro_rules! _and {
-        (
-            $a: expr,
-            $b: expr $(,)?
-        ) => ({#[allow(unused_unsafe)]
-            unsafe {
-                core::arch::wasm32::v128_and($a, $b)
+    pub fn blend_commands(
+        unscaled_buffer: &mut [ARGB; command::LENGTH],
+        commands: &[Command],
+        points: &[Point],
+        atlases: &atlas::Atlases,
+    ) {
+        for command in commands {
+            let Rect { x_min, y_min, x_max, y_max } = command.rect;
+            let (x_min, y_min) = (usize::from(x_min), usize::from(y_min));
+            let (x_max, y_max) = (usize::from(x_max), usize::from(y_max));
+
+            if !command.points.is_empty() {
+                for &Point { x, y, colour } in points_of(command, points) {
+                    let (x, y) = (usize::from(x), usize::from(y));
+                    if !(x_min..=x_max).contains(&x) || !(y_min..=y_max).contains(&y) {
+                        continue
+                    }
+
+                    let texel = match command.modulation.replace {
+                        Some(replace) if colour >> 24 == 0xFF => replace,
+                        _ => colour,
+                    };
+
+                    let i = y * usize::from(command::WIDTH) + x;
+
+                    unscaled_buffer[i] = blend(
+                        texel,
+                        unscaled_buffer[i],
+                        &command.modulation,
+                        command.blend_mode,
+                    );
+                }
+                continue
             }
-        });
-    }
-    pub use _and as and;
 
-    #[macro_export]
-    macro_rules! _and_not {
-        (
-            $just_anded: expr,
-            $notted: expr $(,)?
-        ) => ({#[allow(unused_unsafe)]
-            unsafe {
-                core::arch::wasm32::v128_andnot($just_anded, $notted)
+            let Some(atlas) = atlases.get(command.atlas) else { continue };
+
+            for y in y_min..=y_max {
+                for x in x_min..=x_max {
+                    let i = y * usize::from(command::WIDTH) + x;
+
+                    unscaled_buffer[i] = blend(
+                        texel(command, atlas, (x - x_min, y - y_min)),
+                        unscaled_buffer[i],
+                        &command.modulation,
+                        command.blend_mode,
+                    );
+                }
             }
-        });
+        }
     }
-    pub use _and_not as and_not;
+}
 
-    #[macro_export]
-    macro_rules! _or {
-        (
-            $a: expr,
-            $b: expr $(,)?
-        ) => ({#[allow(unused_unsafe)]
-            unsafe {
-                core::arch::wasm32::v128_or($a, $b)
-            }
-        });
+#[cfg(test)]
+mod tiling_works {
+    use super::*;
+
+    const ATLAS_W: usize = 8;
+    const ATLAS_H: usize = 4;
+
+    /// An opaque texel that encodes where it is on the atlas.
+    fn texel_at(u: usize, v: usize) -> ARGB {
+        0xFF00_0000 | (v * ATLAS_W + u) as ARGB
     }
-    pub use _or as or;
 
-    #[macro_export]
-    macro_rules! _left_shift_32 {
-        (
-            $a: expr,
-            $imm8: expr $(,)?
-        ) => ({#[allow(unused_unsafe)]
-            unsafe {
-                core::arch::wasm32::i32x4_shl($a, $imm8)
-            }
-        });
+    /// Draws a 9 by 5 rect from `(1, 1)` on the atlas, with the given tile
+    /// and orientation, with each of the renderers, and returns the pixels in
+    /// the rect, after checking that they all agree.
+    fn tiled(tile: command::Tile, orientation: Orientation) -> Vec<Vec<ARGB>> {
+        let mut atlases = atlas::Atlases::default();
+        let pixels = (0..ATLAS_H)
+            .flat_map(|v| (0..ATLAS_W).map(move |u| texel_at(u, v)))
+            .collect();
+        let id = atlases.push(
+            atlas::Atlas::from_pixels(ATLAS_W, ATLAS_H, pixels).unwrap()
+        ).unwrap();
+
+        let commands = [Command {
+            rect: Rect::from_unscaled(unscaled::Rect {
+                x: unscaled::X(0),
+                y: unscaled::Y(0),
+                w: unscaled::W(9),
+                h: unscaled::H(5),
+            }),
+            sprite_xy: sprite::XY { x: sprite::X(1), y: sprite::Y(1) },
+            orientation,
+            atlas: id,
+            tile,
+            ..<_>::default()
+        }];
+
+        let mut wide = Box::new([colours::BLACK; command::LENGTH]);
+        blend_commands(&mut wide, &commands, &[], core::slice::from_ref(&UNSCALED_RECT), &atlases);
+        let mut scalar = Box::new([colours::BLACK; command::LENGTH]);
+        scalar_blend_commands(&mut scalar, &commands, &[], core::slice::from_ref(&UNSCALED_RECT), &atlases);
+        let mut reference = Box::new([colours::BLACK; command::LENGTH]);
+        reference::blend_commands(&mut reference, &commands, &[], &atlases);
+
+        assert_same_pixels(&reference[..], &wide[..]);
+        assert_same_pixels(&reference[..], &scalar[..]);
+
+        (0..5)
+            .map(|y| wide[y * usize::from(command::WIDTH)..][..9].to_vec())
+            .collect()
     }
-    pub use _left_shift_32 as left_shift_32;
 
-    #[macro_export]
-    macro_rules! _right_shift_32 {
-        (
-            $a: expr,
-            $imm8: expr $(,)?
-        ) => ({#[allow(unused_unsafe)]
-            unsafe {
-                core::arch::wasm32::u32x4_shr($a, $imm8)
-            }
-        });
+    /// Returns the texels a 9 by 5 rect should have, given the offset into
+    /// the atlas for each offset into the rect.
+    fn expected(offset: impl Fn(usize, usize) -> (usize, usize)) -> Vec<Vec<ARGB>> {
+        (0..5)
+            .map(|y| (0..9).map(|x| {
+                let (u, v) = offset(x, y);
+                texel_at(u, v)
+            }).collect())
+            .collect()
     }
-    pub use _right_shift_32 as right_shift_32;
 
-    #[macro_export]
-    macro_rules! _f32_to_u32 {
-        (
-            $a: expr $(,)?
-        ) => ({#[allow(unused_unsafe)]
-            unsafe {
-                core::arch::wasm32::u32x4_trunc_sat_f32x4($a)
-            }
-        });
+    #[test]
+    fn on_a_tile_narrower_than_the_lanes() {
+        let tile = command::Tile { w: 2, h: 3, x_offset: 1, y_offset: 4 };
+
+        assert_eq!(
+            tiled(tile, <_>::default()),
+            expected(|x, y| (1 + (1 + x) % 2, 1 + (4 + y) % 3))
+        );
     }
-    pub use _f32_to_u32 as f32_to_u32;
 
-    #[macro_export]
-    macro_rules! _u32_to_f32 {
-        (
-            $a: expr $(,)?
-        ) => ({#[allow(unused_unsafe)]
-            unsafe {
-                core::arch::wasm32::f32x4_convert_u32x4($a)
-            }
-        });
+    #[test]
+    fn on_a_tile_wider_than_the_lanes() {
+        let tile = command::Tile { w: 6, h: 2, x_offset: 0, y_offset: 0 };
+
+        assert_eq!(
+            tiled(tile, <_>::default()),
+            expected(|x, y| (1 + x % 6, 1 + y % 2))
+        );
     }
-    pub use _u32_to_f32 as u32_to_f32;
 
-    #[macro_export]
-    macro_rules! _i32 {
-        (
-            $a: expr $(,)?
-        ) => ({#[allow(unused_unsafe)]
-            unsafe {
-                core::arch::wasm32::i32x4_splat($a)
-            }
-        });
+    #[test]
+    fn on_a_transposed_tile() {
+        // Drawn 2 wide and 3 tall, so 3 wide and 2 tall on the atlas.
+        let tile = command::Tile { w: 2, h: 3, x_offset: 0, y_offset: 1 };
+
+        assert_eq!(
+            tiled(tile, Orientation::TRANSPOSE),
+            expected(|x, y| (1 + (1 + y) % 3, 1 + x % 2))
+        );
     }
-    pub use _i32 as i32;
 
-    #[macro_export]
-    macro_rules! _f32 {
-        (
-            $a: expr $(,)?
-        ) => ({#[allow(unused_unsafe)]
-            unsafe {
-                core::arch::wasm32::f32x4_splat($a)
-            }
-        });
+    #[test]
+    fn on_an_empty_tile_reading_past_the_region() {
+        let pixels = tiled(<_>::default(), <_>::default());
+
+        assert_eq!(&pixels[0][..3], &[texel_at(1, 1), texel_at(2, 1), texel_at(3, 1)]);
     }
-    pub use _f32 as f32;
+}
 
-    #[macro_export]
-    macro_rules! _add_f32 {
-        (
-            $a: expr,
-            $b: expr $(,)?
-        ) => ({#[allow(unused_unsafe)]
-            unsafe {
-                core::arch::wasm32::f32x4_add($a, $b)
-            }
-        });
+/// These tests feed random commands, biased towards the edges of the screen and
+/// the sprite sheet, to both the reference renderer and the wide ones. Besides
+/// comparing the pixels, this exercises the paths where a naive wide load or
+/// store would go out of bounds.
+#[cfg(test)]
+mod blend_commands_matches_the_reference {
+    use super::*;
+
+    fn reference_pixels(
+        commands: &[Command],
+        points: &[Point],
+        atlases: &atlas::Atlases,
+    ) -> Box<[ARGB; command::LENGTH]> {
+        let mut output = Box::new([colours::BLACK; command::LENGTH]);
+        reference::blend_commands(&mut output, commands, points, atlases);
+        output
     }
-    pub use _add_f32 as add_f32;
 
-    #[macro_export]
-    macro_rules! _add_i32 {
-        (
-            $a: expr,
-            $b: expr $(,)?
-        ) => ({#[allow(unused_unsafe)]
-            unsafe {
-                core::arch::wasm32::i32x4_add($a, $b)
-            }
-        });
+    #[test]
+    fn on_these_random_commands() {
+        let mut rng = xs::from_seed([0x3E; 16]);
+
+        for _ in 0..256 {
+            let atlases = arb::atlases(&mut rng);
+            let points = arb::points(&mut rng);
+            let commands = arb::commands(&mut rng);
+
+            let mut actual = Box::new([colours::BLACK; command::LENGTH]);
+            blend_commands(&mut actual, &commands, &points, core::slice::from_ref(&UNSCALED_RECT), &atlases);
+
+            assert_same_pixels(
+                &reference_pixels(&commands, &points, &atlases)[..],
+                &actual[..],
+            );
+        }
     }
-    pub use _add_i32 as add_i32;
 
-    #[macro_export]
-    macro_rules! _sub {
-        (
-            $a: expr,
-            $b: expr $(,)?
-        ) => ({#[allow(unused_unsafe)]
-            unsafe {
-                core::arch::wasm32::f32x4_sub($a, $b)
-            }
-        });
+    #[test]
+    fn for_scalar_blend_commands_on_these_random_commands() {
+        let mut rng = xs::from_seed([0x5C; 16]);
+
+        for _ in 0..256 {
+            let atlases = arb::atlases(&mut rng);
+            let points = arb::points(&mut rng);
+            let commands = arb::commands(&mut rng);
+
+            let mut actual = Box::new([colours::BLACK; command::LENGTH]);
+            scalar_blend_commands(&mut actual, &commands, &points, core::slice::from_ref(&UNSCALED_RECT), &atlases);
+
+            assert_same_pixels(
+                &reference_pixels(&commands, &points, &atlases)[..],
+                &actual[..],
+            );
+        }
     }
-    pub use _sub as sub;
 
-    #[macro_export]
-    macro_rules! _mul {
-        (
-            $a: expr,
-            $b: expr $(,)?
-        ) => ({#[allow(unused_unsafe)]
-            unsafe {
-                core::arch::wasm32::f32x4_mul($a, $b)
-            }
-        });
+    #[test]
+    fn when_rendered_at_a_multiplier_of_one() {
+        let mut rng = xs::from_seed([0x01; 16]);
+
+        for _ in 0..16 {
+            let commands = arb::commands(&mut rng);
+
+            let frame_buffer = render_headless(
+                &commands,
+                (command::WIDTH, command::HEIGHT),
+            );
+
+            assert_same_pixels(
+                &reference_pixels(&commands, &[], frame_buffer.atlases())[..],
+                &frame_buffer.buffer,
+            );
+        }
     }
-    pub use _mul as mul;
 
-    #[macro_export]
-    macro_rules! _recip {
-        (
-            $a: expr $(,)?
-        ) => ({#[allow(unused_unsafe)]
-            unsafe {
-                // As of this writing, there's no equivalent wasm instrinsic to
-                // `_mm_rcp_ps`, so it seems we need to eat the extra divide
-                // latency. :(
-                core::arch::wasm32::f32x4_div(
-                    core::arch::wasm32::f32x4_splat(1.),
-                    $a
-                )
-            }
-        });
+    #[test]
+    fn on_this_bottom_right_corner_sprite_past_the_end_of_gfx() {
+        let commands = [Command {
+            rect: Rect::from_unscaled(unscaled::Rect {
+                x: unscaled::X(command::WIDTH - 3),
+                y: unscaled::Y(command::HEIGHT - 1),
+                w: unscaled::W(3),
+                h: unscaled::H(1),
+            }),
+            sprite_xy: sprite::XY {
+                x: sprite::X(platform_types::GFX_WIDTH as sprite::Inner - 2),
+                y: sprite::Y(platform_types::GFX_HEIGHT as sprite::Inner - 1),
+            },
+            modulation: <_>::default(),
+            orientation: <_>::default(),
+            blend_mode: <_>::default(),
+            atlas: <_>::default(),
+            points: <_>::default(),
+            tile: <_>::default(),
+        }];
+
+        let atlases = atlas::Atlases::default();
+
+        let mut actual = Box::new([colours::BLACK; command::LENGTH]);
+        blend_commands(&mut actual, &commands, &[], core::slice::from_ref(&UNSCALED_RECT), &atlases);
+
+        assert_same_pixels(&reference_pixels(&commands, &[], &atlases)[..], &actual[..]);
     }
-    pub use _recip as recip;
+}
 
-    #[macro_export]
-    macro_rules! _sqrt {
-        (
-            $a: expr $(,)?
-        ) => ({#[allow(unused_unsafe)]
-            unsafe {
-                core::arch::wasm32::f32x4_sqrt($a)
-            }
-        });
+#[cfg(test)]
+mod blend_modes_work {
+    use super::*;
+
+    /// Draws a single opaque pixel, modulated as given, on top of `under`, and
+    /// checks that each renderer gives the expected result, both for a sprite and
+    /// for a point of the same colour.
+    pub(super) fn assert_modulated_blends_to(
+        expected: ARGB,
+        modulation: Modulation,
+        under: ARGB,
+        blend_mode: BlendMode,
+    ) {
+        let commands = [Command {
+            rect: Rect::from_unscaled(unscaled::Rect {
+                x: unscaled::X(0),
+                y: unscaled::Y(0),
+                w: unscaled::W(1),
+                h: unscaled::H(1),
+            }),
+            modulation,
+            blend_mode,
+            ..<_>::default()
+        }];
+
+        let point_commands = [Command {
+            points: command::Points { start: 0, end: 1 },
+            ..commands[0]
+        }];
+        let points = [Point {
+            x: command::X::clipped_inner(0),
+            y: command::Y::clipped_inner(0),
+            colour: GFX[0],
+        }];
+
+        type BlendFn = fn(&mut [ARGB; command::LENGTH], &[Command], &[Point], &atlas::Atlases);
+
+        let blended_by = |f: BlendFn| {
+            let atlases = atlas::Atlases::default();
+
+            let mut sprite_buffer = Box::new([under; command::LENGTH]);
+            f(&mut sprite_buffer, &commands, &[], &atlases);
+
+            let mut point_buffer = Box::new([under; command::LENGTH]);
+            f(&mut point_buffer, &point_commands, &points, &atlases);
+
+            [sprite_buffer[0], point_buffer[0]]
+        };
+
+        let actual = [
+            blended_by(|b, c, p, a| blend_commands(b, c, p, core::slice::from_ref(&UNSCALED_RECT), a)),
+            blended_by(|b, c, p, a| scalar_blend_commands(b, c, p, core::slice::from_ref(&UNSCALED_RECT), a)),
+            blended_by(reference::blend_commands),
+        ];
+
+        assert_eq!(
+            actual,
+            [[expected; 2]; 3],
+            "0x{expected:08X} != {actual:08X?}",
+        );
     }
-    pub use _sqrt as sqrt;
 
-    #[macro_export]
-    macro_rules! _lt_mask_32 {
-        (
-            $lhs: expr,
-            $rhs: expr $(,)?
-        ) => ({#[allow(unused_unsafe)]
-            unsafe {
-                core::arch::wasm32::i32x4_lt($lhs, $rhs)
-            }
-        });
+    fn assert_blends_to(expected: ARGB, colour: ARGB, under: ARGB, blend_mode: BlendMode) {
+        assert_modulated_blends_to(expected, Modulation::replace(colour), under, blend_mode);
     }
-    pub use _lt_mask_32 as lt_mask_32;
 
-    #[macro_export]
-    macro_rules! _eq_mask_u32 {
-        (
-            $lhs: expr,
-            $rhs: expr $(,)?
-        ) => ({#[allow(unused_unsafe)]
-            unsafe {
-                core::arch::wasm32::i32x4_eq($lhs, $rhs)
-            }
-        });
+    #[test]
+    fn on_over_with_an_opaque_colour() {
+        assert_blends_to(colours::BLUE, colours::BLUE, colours::RED, BlendMode::Over);
     }
-    pub use _eq_mask_u32 as eq_mask_u32;
 
-    #[macro_export]
-    macro_rules! _gt_mask_32 {
-        (
-            $lhs: expr,
-            $rhs: expr $(,)?
-        ) => ({#[allow(unused_unsafe)]
-            unsafe {
-                core::arch::wasm32::i32x4_gt($lhs, $rhs)
-            }
-        });
+    #[test]
+    fn on_add_with_separate_channels() {
+        assert_blends_to(0xFF808000, 0xFF800000, 0xFF008000, BlendMode::Add);
     }
-    pub use _gt_mask_32 as gt_mask_32;
-}
 
-#[cfg(not(any(
-    all(
-        target_arch = "x86_64",
-        target_feature = "sse",
-        target_feature = "sse2",
-    ),
-    all(
-        target_arch = "wasm32",
-        target_feature = "simd128",
-    ),
-)))]
-mod wide {
-    use super::*;
-    pub const WIDTH: unscaled::Inner = 4;
+    #[test]
+    fn on_add_when_it_saturates() {
+        assert_blends_to(0xFFFFFFFF, colours::WHITE, colours::WHITE, BlendMode::Add);
+    }
 
-    compile_error!("wide not implemented for this target");
-}
+    #[test]
+    fn on_multiply_with_white() {
+        assert_blends_to(colours::BLUE, 0xFFFFFFFF, colours::BLUE, BlendMode::Multiply);
+    }
 
-#[cfg(test)]
-mod wide_tests {
-    use super::wide::*;
+    #[test]
+    fn on_multiply_with_black() {
+        assert_blends_to(0xFF000000, 0xFF000000, colours::BLUE, BlendMode::Multiply);
+    }
 
-    /// Thses names chosen to be the same length as `0`.
-    const A: i32 = 0xAAAA_AAAAu32 as i32;
-    const B: i32 = 0xBBBB_BBBBu32 as i32;
-    const C: i32 = 0xCCCC_CCCCu32 as i32;
-    const D: i32 = 0xDDDD_DDDDu32 as i32;
-    const F: i32 = 0xFFFF_FFFFu32 as i32;
+    #[test]
+    fn on_multiply_with_grey() {
+        // (128/255)^4 in linear space is 64.25/255 in gamma space.
+        assert_blends_to(0xFF404040, 0xFF808080, 0xFF808080, BlendMode::Multiply);
+    }
 
-    macro_rules! to_array {
-        ($v: expr) => ({
-            let mut arr = [0; WIDTH as usize];
-            let v = $v;
+    #[test]
+    fn on_replace_with_a_transparent_under() {
+        assert_blends_to(colours::GREEN, colours::GREEN, 0, BlendMode::Replace);
+    }
+}
 
-            // SAFETY: The pointers produced by the code generated by
-            // these macros are valid to read 128 bytes from.
-            unsafe {
-                store!(v, &mut arr);
-            }
+#[cfg(test)]
+mod modulation_works {
+    use super::*;
+    use super::blend_modes_work::assert_modulated_blends_to;
 
-            arr
-        })
+    #[test]
+    fn on_replacing_with_transparent_black() {
+        assert_modulated_blends_to(
+            colours::BLUE,
+            Modulation::replace(0),
+            colours::BLUE,
+            BlendMode::Over,
+        );
+        assert_modulated_blends_to(
+            0,
+            Modulation::replace(0),
+            colours::BLUE,
+            BlendMode::Replace,
+        );
     }
 
     #[test]
-    fn pick_via_mask_returns_the_expected_result_in_this_or_like_case() {
-        let left = i32x4!(A, B, 0, 0);
-        let right = i32x4!(0, 0, C, D);
-        let mask = i32x4!(0, 0, F, F);
+    fn on_a_tint_that_keeps_one_channel() {
+        assert_modulated_blends_to(
+            0xFF00FF00,
+            Modulation {
+                tint: 0xFF00FF00,
+                ..Modulation::replace(0xFFFFFFFF)
+            },
+            colours::BLUE,
+            BlendMode::Over,
+        );
+    }
 
-        let actual = pick_via_mask!(
-            left,
-            right,
-            mask,
+    #[test]
+    fn on_a_grey_tint_with_replace() {
+        // (128/255)^4 in linear space is 64.25/255 in gamma space.
+        assert_modulated_blends_to(
+            0xFF404040,
+            Modulation {
+                tint: 0xFF808080,
+                ..Modulation::replace(0xFF808080)
+            },
+            0,
+            BlendMode::Replace,
         );
+    }
 
-        assert_eq!(
-            to_array!(actual),
-            to_array!(i32x4!(A, B, C, D))
+    #[test]
+    fn on_zero_alpha() {
+        assert_modulated_blends_to(
+            colours::BLUE,
+            Modulation {
+                alpha: 0,
+                ..Modulation::replace(colours::RED)
+            },
+            colours::BLUE,
+            BlendMode::Add,
         );
     }
 }
@@ -999,46 +5222,26 @@ pub fn render(
         frame_buffer.height = command::HEIGHT;
     }
 
-    let width_multiplier = frame_buffer.width / command::WIDTH;
-    let height_multiplier = frame_buffer.height / command::HEIGHT;
-    let multiplier = core::cmp::min(width_multiplier, height_multiplier);
+    let outer_clip_rect = frame_buffer.scale_mode.output_rect(
+        (frame_buffer.width, frame_buffer.height)
+    );
+
+    let multiplier = outer_clip_rect.width() / command::WIDTH;
     if multiplier == 0 {
         debug_assert!(multiplier != 0);
         return NeedsRedraw::No;
     }
 
-    let vertical_bars_width: clip::W = frame_buffer.width - (multiplier * command::WIDTH);
-
-    let left_bar_width: clip::W = (vertical_bars_width + 1) / 2;
-
-    let right_bar_width: clip::W = vertical_bars_width / 2;
-
-    let horizontal_bars_height: clip::H = frame_buffer.height - (multiplier * command::HEIGHT);
-
-    let top_bar_height: clip::H = (horizontal_bars_height + 1) / 2;
-
-    let bottom_bar_height: clip::H = horizontal_bars_height / 2;
-
-    let outer_clip_rect = clip::Rect {
-        x: left_bar_width..(
-            frame_buffer.width - right_bar_width
-        ),
-        y: top_bar_height..(
-            frame_buffer.height - bottom_bar_height
-        ),
-    };
-
-
-    // This started as cached software rendering based on:
+    // This is cached software rendering based on:
     // https://rxi.github.io/cached_software_rendering.html
-    // But, a single cell turned out to be the most performant,
-    // in this particular case, so the code was simplifed with
-    // that in mind. Maybe it's faster to have a single cell, just
-    // because the rendered size is so small. Not sure.
+    // The unscaled screen is split into a grid of cells, and only the cells
+    // whose commands changed since the last frame get redrawn.
 
     frame_buffer.cells.reset_then_hash_commands(
         commands,
+        &frame_buffer.points,
         (frame_buffer.width, frame_buffer.height),
+        frame_buffer.atlases.texture_version(),
     );
 
     let expected_length = usize::from(frame_buffer.width)
@@ -1053,380 +5256,526 @@ pub fn render(
         }
     }
 
-    let (cells, cells_prev) = frame_buffer.cells.current_and_prev();
+    let dirty_rects = frame_buffer.cells.dirty_rects();
 
-    if cells == cells_prev {
+    if dirty_rects.is_empty() {
         frame_buffer.cells.swap();
         return NeedsRedraw::No;
     }
 
-    let wide_0 = wide::i32!(0);
-    let wide_1_f32 = wide::f32!(1.);
-    let wide_255_i32 = wide::i32!(255);
-    let wide_inv_255_f32 = wide::f32!(1./255.);
-    let wide_255_f32 = wide::f32!(255.);
-    let wide_0_to_w = wide::i32x4!(0, 1, 2, 3);
+    // If a filter moves pixels around, we cannot tell which pixels a change
+    // shows up in, so we scale up and filter the whole screen at once.
+    let whole_screen = frame_buffer.filters.iter()
+        .any(|slot| slot.enabled && slot.filter.moves_pixels());
+
+    for rect in &dirty_rects {
+        for y in rect.y.clone() {
+            let row_start = usize::from(y) * usize::from(command::WIDTH);
+            frame_buffer.unscaled_buffer[
+                row_start + usize::from(rect.x.start)..row_start + usize::from(rect.x.end)
+            ].fill(colours::BLACK);
+        }
+    }
+
+    // The dirty rects do not overlap, so going through the commands once, and
+    // drawing each into every rect, gives the same result as drawing all the
+    // commands into one rect after another.
+    blend_commands(
+        &mut frame_buffer.unscaled_buffer,
+        commands,
+        &frame_buffer.points,
+        &dirty_rects,
+        &frame_buffer.atlases,
+    );
 
-    // Hopefully this compiles to something not inefficent
-    for i in 0..frame_buffer.unscaled_buffer.len() {
-        frame_buffer.unscaled_buffer[i] = colours::BLACK;
+    for rect in &dirty_rects {
+        post::run(
+            &mut frame_buffer.filters,
+            post::Stage::Unscaled,
+            &mut frame_buffer.unscaled_buffer[..],
+            command::WIDTH,
+            &UNSCALED_RECT,
+            rect,
+        );
     }
 
-    for &Command {
-        sprite_xy: sprite::XY { x: sprite_x, y: sprite_y },
-        colour_override,
-        rect,
-    } in commands.iter() {
-        let colour_override_value = wide::i32!(colour_override as i32);
+    let rects_to_scale = if whole_screen {
+        vec![UNSCALED_RECT]
+    } else {
+        dirty_rects
+    };
+
+    let output_rects = match frame_buffer.scale_mode {
+        ScaleMode::Integer => upscale(frame_buffer, &rects_to_scale, multiplier, &outer_clip_rect),
+        ScaleMode::Fractional
+        | ScaleMode::Stretch => rects_to_scale.iter()
+            .map(|rect| resample(frame_buffer, rect, &outer_clip_rect, Resampling::Nearest))
+            .collect(),
+        ScaleMode::SharpBilinear => rects_to_scale.iter()
+            .map(|rect| resample(frame_buffer, rect, &outer_clip_rect, Resampling::SharpBilinear))
+            .collect(),
+    };
 
-        let not_colour_override_mask = wide::eq_mask_u32!(
-            colour_override_value,
-            wide_0
+    for output_rect in &output_rects {
+        post::run(
+            &mut frame_buffer.filters,
+            post::Stage::Scaled,
+            &mut frame_buffer.buffer,
+            frame_buffer.width,
+            &outer_clip_rect,
+            output_rect,
         );
+    }
 
-        let Rect {
-            x_min,
-            y_min,
-            x_max,
-            y_max,
-        } = rect;
-
-        // TODO make this wide too?
-        let x_min = clip::X::from(x_min);
-        let y_min = clip::Y::from(y_min);
-        let x_max = clip::W::from(x_max);
-        let y_max = clip::H::from(y_max);
-
-        let x_end = x_max + 1;
-        let y_end = y_max + 1;
-        let wide_x_end = wide::i32!(x_end.into());
-
-        let sprite_x = usize::from(sprite_x);
-        let sprite_y = usize::from(sprite_y);
-
-        let src_w = GFX_WIDTH as usize;
-
-        let mut y_iter_count = 0;
-        for y in y_min..y_end {
-            let mut x_iter_count = 0;
-            let mut x = x_min;
-
-            while x < x_end {
-                let wide_xs = wide::add_i32!(
-                    wide::i32!(x.into()),
-                    wide_0_to_w
-                );
+    frame_buffer.cells.swap();
 
-                let dest_index = usize::from(y)
-                    * usize::from(command::WIDTH)
-                    + usize::from(x);
+    NeedsRedraw::Yes(output_rects)
+}
 
-                let unders = unsafe {
-                    wide::load!(
-                        frame_buffer.unscaled_buffer.as_ptr(),
-                        dest_index,
-                    )
-                };
+/// How the `Integer` scale mode copies the unscaled buffer into the buffer.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Upscaler {
+    /// One pixel at a time, on the current thread.
+    Scalar,
+    /// Several pixels at a time, on the current thread.
+    #[default]
+    Wide,
+    /// Like `Wide`, but large rects are split into bands of rows, each copied
+    /// on its own thread, using up to the given number of threads. On the web,
+    /// where we cannot spawn threads, this is the same as `Wide`.
+    Threaded(core::num::NonZeroUsize),
+}
 
-                let base_src_i =
-                    (sprite_y + y_iter_count) * src_w
-                    + (sprite_x + x_iter_count);
-                debug_assert!(
-                    base_src_i < GFX.len(), 
-                    "({sprite_y} + {y_iter_count}) * {src_w} + ({sprite_x} + {x_iter_count})
-{base_src_i} >= {}
-({x_min} to {x_end}, {y_min} to {y_end})",
-                    GFX.len()
-                );
-                let gfx_colours = unsafe {
-                    wide::load!(
-                        GFX.as_ptr(),
-                        base_src_i
-                    )
-                };
+/// Splitting the work up any finer than this costs more in starting threads
+/// than it saves.
+#[cfg(not(target_arch = "wasm32"))]
+const MIN_PIXELS_PER_THREAD: usize = 1 << 16;
 
-                let is_full_alpha_mask = wide::eq_mask_u32!(
-                    wide::right_shift_32!(
-                        gfx_colours,
-                        24
-                    ),
-                    wide_255_i32
-                );
+/// Copies the given parts of the unscaled buffer into the buffer, `multiplier`
+/// times as large, offset by the start of `outer_clip_rect`. Returns the parts
+/// of the buffer that were written to.
+fn upscale(
+    frame_buffer: &mut FrameBuffer,
+    rects: &[clip::Rect],
+    multiplier: clip::W,
+    outer_clip_rect: &clip::Rect,
+) -> Vec<clip::Rect> {
+    let output_rects: Vec<clip::Rect> = rects.iter()
+        .map(|rect| clip::Rect {
+            x: outer_clip_rect.x.start + rect.x.start * multiplier
+                ..outer_clip_rect.x.start + rect.x.end * multiplier,
+            y: outer_clip_rect.y.start + rect.y.start * multiplier
+                ..outer_clip_rect.y.start + rect.y.end * multiplier,
+        })
+        .collect();
 
-                let do_override_mask = wide::and_not!(
-                    is_full_alpha_mask,
-                    not_colour_override_mask
-                );
+    let (Some(src_y_start), Some(src_y_end)) = (
+        rects.iter().map(|rect| usize::from(rect.y.start)).min(),
+        rects.iter().map(|rect| usize::from(rect.y.end)).max(),
+    ) else {
+        return output_rects
+    };
 
-                let gfx_colours = wide::pick_via_mask!(
-                    gfx_colours,
-                    colour_override_value,
-                    do_override_mask,
-                );
+    let dest_w = usize::from(frame_buffer.width);
+    let multiplier = usize::from(multiplier);
+    let rows: Vec<Rows> = rects.iter().zip(&output_rects)
+        .map(|(rect, output_rect)| Rows {
+            src_x: usize::from(rect.x.start)..usize::from(rect.x.end),
+            src_y: usize::from(rect.y.start)..usize::from(rect.y.end),
+            dest_x: usize::from(output_rect.x.start)..usize::from(output_rect.x.end),
+            dest_w,
+            multiplier,
+        })
+        .collect();
+
+    // All the rects are within these rows of the buffer, so we only need to
+    // pass those along.
+    let src_y = src_y_start..src_y_end;
+    let dest_y_start = usize::from(outer_clip_rect.y.start) + src_y.start * multiplier;
+    let dest = &mut frame_buffer.buffer[
+        dest_y_start * dest_w..(dest_y_start + src_y.len() * multiplier) * dest_w
+    ];
+    let src = &frame_buffer.unscaled_buffer;
+
+    match frame_buffer.upscaler {
+        Upscaler::Scalar => copy_band(dest, src, src_y, &rows, fill_scalar),
+        Upscaler::Wide => copy_band(dest, src, src_y, &rows, fill_wide),
+        #[cfg(target_arch = "wasm32")]
+        Upscaler::Threaded(_) => copy_band(dest, src, src_y, &rows, fill_wide),
+        #[cfg(not(target_arch = "wasm32"))]
+        Upscaler::Threaded(max_threads) => {
+            let pixels: usize = output_rects.iter()
+                .map(|output_rect| output_rect.width() as usize * output_rect.height() as usize)
+                .sum();
+            let threads = core::cmp::min(
+                core::cmp::min(max_threads.get(), pixels / MIN_PIXELS_PER_THREAD),
+                src_y.len(),
+            );
+
+            if threads <= 1 {
+                copy_band(dest, src, src_y, &rows, fill_wide);
+            } else {
+                // We split the rows once for all the rects, so there is only
+                // one set of threads to start each frame.
+                let src_rows_per_thread = src_y.len().div_ceil(threads);
+                let dest_rows_per_src_row = multiplier * dest_w;
+
+                std::thread::scope(|scope| {
+                    for (i, dest_band) in dest.chunks_mut(
+                        src_rows_per_thread * dest_rows_per_src_row
+                    ).enumerate() {
+                        let band_start = src_y.start + i * src_rows_per_thread;
+                        let band_y = band_start..band_start + dest_band.len() / dest_rows_per_src_row;
+                        let rows = &rows;
+
+                        scope.spawn(move || copy_band(dest_band, src, band_y, rows, fill_wide));
+                    }
+                });
+            }
+        }
+    }
 
-                let should_write =
-                    wide::lt_mask_32!(
-                        wide_xs,
-                        wide_x_end
-                    );
+    output_rects
+}
 
-                // Don't need to mask the shifted in zeroes.
-                let gfx_colour_a = wide::right_shift_32!(
-                    gfx_colours,
-                    24
-                );
+/// Copies the parts of each of `rows` that are within the unscaled rows in
+/// `band_y` into `dest`, which starts with the first buffer row that the first
+/// of those is copied to.
+fn copy_band(
+    dest: &mut [ARGB],
+    src: &[ARGB; command::LENGTH],
+    band_y: core::ops::Range<usize>,
+    rows: &[Rows],
+    fill: fn(&[ARGB], &mut [ARGB], usize),
+) {
+    for rect_rows in rows {
+        let src_y = core::cmp::max(rect_rows.src_y.start, band_y.start)
+            ..core::cmp::min(rect_rows.src_y.end, band_y.end);
+        if src_y.is_empty() {
+            continue
+        }
 
-                let gfx_colour_r = wide::and!(
-                    wide::right_shift_32!(
-                        gfx_colours,
-                        16
-                    ),
-                    wide_255_i32
-                );
+        let dest_start = (src_y.start - band_y.start) * rect_rows.multiplier * rect_rows.dest_w;
+        rect_rows.copy(&mut dest[dest_start..], src, src_y, fill);
+    }
+}
 
-                let gfx_colour_g = wide::and!(
-                    wide::right_shift_32!(
-                        gfx_colours,
-                        8
-                    ),
-                    wide_255_i32
-                );
+/// The parts of the rows that `upscale` copies from and to, for one rect.
+struct Rows {
+    src_x: core::ops::Range<usize>,
+    src_y: core::ops::Range<usize>,
+    dest_x: core::ops::Range<usize>,
+    dest_w: usize,
+    multiplier: usize,
+}
 
-                // Don't need to shift since it's already in the right spot
-                let gfx_colour_b = wide::and!(
-                    gfx_colours,
-                    wide_255_i32
-                );
+impl Rows {
+    /// Copies the unscaled rows in `src_y`, which must be within `self.src_y`,
+    /// into `dest`, which starts with the first buffer row that the first of
+    /// them is copied to.
+    fn copy(
+        &self,
+        dest: &mut [ARGB],
+        src: &[ARGB; command::LENGTH],
+        src_y: core::ops::Range<usize>,
+        fill: fn(&[ARGB], &mut [ARGB], usize),
+    ) {
+        let src_w = usize::from(command::WIDTH);
 
-                // Don't need to mask the shifted in zeroes.
-                let under_a = wide::right_shift_32!(
-                    unders,
-                    24
-                );
+        for (row, y) in src_y.enumerate() {
+            let src_row_start = y * src_w;
+            let src_row = &src[
+                src_row_start + self.src_x.start..src_row_start + self.src_x.end
+            ];
 
-                let under_r = wide::and!(
-                    wide::right_shift_32!(
-                        unders,
-                        16
-                    ),
-                    wide_255_i32
-                );
+            let dest_row_start = row * self.multiplier * self.dest_w;
+            let dest_row = dest_row_start + self.dest_x.start..dest_row_start + self.dest_x.end;
 
-                let under_g = wide::and!(
-                    wide::right_shift_32!(
-                        unders,
-                        8
-                    ),
-                    wide_255_i32
-                );
+            fill(src_row, &mut dest[dest_row.clone()], self.multiplier);
 
-                // Don't need to shift since it's already in the right spot
-                let under_b = wide::and!(
-                    unders,
-                    wide_255_i32
+            // The rest of the rows for this source row are the same as the first.
+            for i in 1..self.multiplier {
+                dest.copy_within(
+                    dest_row.clone(),
+                    dest_row.start + i * self.dest_w,
                 );
+            }
+        }
+    }
+}
 
-                // gamma to linear
-                let mut a_g = wide::mul!(
-                    wide::u32_to_f32!(
-                        gfx_colour_a
-                    ),
-                    wide_inv_255_f32
-                );
-                a_g = wide::mul!(a_g, a_g);
-                let mut r_g = wide::mul!(
-                    wide::u32_to_f32!(
-                        gfx_colour_r
-                    ),
-                    wide_inv_255_f32
-                );
-                r_g = wide::mul!(r_g, r_g);
-                let mut g_g = wide::mul!(
-                    wide::u32_to_f32!(
-                        gfx_colour_g
-                    ),
-                    wide_inv_255_f32
-                );
-                g_g = wide::mul!(g_g, g_g);
-                let mut b_g = wide::mul!(
-                    wide::u32_to_f32!(
-                        gfx_colour_b
-                    ),
-                    wide_inv_255_f32
-                );
-                b_g = wide::mul!(b_g, b_g);
+/// Fills `dest` with each pixel of `src` repeated `multiplier` times.
+fn fill_scalar(src: &[ARGB], dest: &mut [ARGB], multiplier: usize) {
+    for (&colour, pixels) in src.iter().zip(dest.chunks_exact_mut(multiplier)) {
+        pixels.fill(colour);
+    }
+}
 
-                let mut a_u = wide::mul!(
-                    wide::u32_to_f32!(
-                        under_a
-                    ),
-                    wide_inv_255_f32
-                );
-                a_u = wide::mul!(a_u, a_u);
-                let mut r_u = wide::mul!(
-                    wide::u32_to_f32!(
-                        under_r
-                    ),
-                    wide_inv_255_f32
-                );
-                r_u = wide::mul!(r_u, r_u);
-                let mut g_u = wide::mul!(
-                    wide::u32_to_f32!(
-                        under_g
-                    ),
-                    wide_inv_255_f32
-                );
-                g_u = wide::mul!(g_u, g_u);
-                let mut b_u = wide::mul!(
-                    wide::u32_to_f32!(
-                        under_b
-                    ),
-                    wide_inv_255_f32
-                );
-                b_u = wide::mul!(b_u, b_u);
-
-                // perform alpha blending
-                let o_a = wide::add_f32!(
-                    a_g,
-                    wide::mul!(
-                        a_u,
-                        wide::sub!(wide_1_f32, a_g)
-                    )
-                );
+/// The same as `fill_scalar`, but writing a whole vector at a time.
+fn fill_wide(src: &[ARGB], dest: &mut [ARGB], multiplier: usize) {
+    const LANES: usize = wide::WIDTH as usize;
 
-                let inv_o_a = wide::recip!(o_a);
+    match multiplier {
+        1 => dest.copy_from_slice(src),
+        2 | 3 => fill_wide_shuffled(src, dest, multiplier),
+        _ => fill_wide_splatted(src, dest, multiplier),
+    }
 
-                let one_minus_a_g = wide::sub!(wide_1_f32, a_g);
+    /// Loads `LANES` pixels at a time, and shuffles copies of them into
+    /// `multiplier` vectors, for when each pixel is repeated fewer times than
+    /// there are lanes.
+    fn fill_wide_shuffled(src: &[ARGB], dest: &mut [ARGB], multiplier: usize) {
+        let whole_src = src.len() - src.len() % LANES;
+        let (src, src_rest) = src.split_at(whole_src);
+        let (dest, dest_rest) = dest.split_at_mut(whole_src * multiplier);
+
+        let src = src.as_ptr();
+        let dest = dest.as_mut_ptr();
+
+        for i in (0..whole_src).step_by(LANES) {
+            let d = i * multiplier;
+            // SAFETY: `i + LANES` is at most `whole_src`, the length of `src`,
+            // and `d + multiplier * LANES` is at most `whole_src * multiplier`,
+            // the length of `dest`.
+            unsafe {
+                let pixels = wide::load!(src, i);
+                if multiplier == 2 {
+                    wide::store!(wide::shuffle_32!(pixels, 0, 0, 1, 1), dest, d);
+                    wide::store!(wide::shuffle_32!(pixels, 2, 2, 3, 3), dest, d + LANES);
+                } else {
+                    wide::store!(wide::shuffle_32!(pixels, 0, 0, 0, 1), dest, d);
+                    wide::store!(wide::shuffle_32!(pixels, 1, 1, 2, 2), dest, d + LANES);
+                    wide::store!(wide::shuffle_32!(pixels, 2, 3, 3, 3), dest, d + 2 * LANES);
+                }
+            }
+        }
 
-                let o_r = wide::mul!(
-                    wide::add_f32!(
-                        wide::mul!(r_g, a_g),
-                        wide::mul!(
-                            r_u,
-                            one_minus_a_g
-                        )
-                    ),
-                    inv_o_a
-                );
-                let o_g = wide::mul!(
-                    wide::add_f32!(
-                        wide::mul!(g_g, a_g),
-                        wide::mul!(
-                            g_u,
-                            one_minus_a_g
-                        )
-                    ),
-                    inv_o_a
-                );
-                let o_b = wide::mul!(
-                    wide::add_f32!(
-                        wide::mul!(b_g, a_g),
-                        wide::mul!(
-                            b_u,
-                            one_minus_a_g
-                        )
-                    ),
-                    inv_o_a
-                );
+        fill_scalar(src_rest, dest_rest, multiplier);
+    }
 
-                // linear to gamma
-                let rendered_a = wide::f32_to_u32!(
-                    wide::mul!(
-                        wide_255_f32,
-                        wide::sqrt!(o_a)
-                    )
-                );
-                let rendered_r = wide::f32_to_u32!(
-                    wide::mul!(
-                        wide_255_f32,
-                        wide::sqrt!(o_r)
-                    )
-                );
-                let rendered_g = wide::f32_to_u32!(
-                    wide::mul!(
-                        wide_255_f32,
-                        wide::sqrt!(o_g)
-                    )
-                );
-                let rendered_b = wide::f32_to_u32!(
-                    wide::mul!(
-                        wide_255_f32,
-                        wide::sqrt!(o_b)
-                    )
-                );
+    /// Writes a vector of copies of each pixel at a time, for when each pixel
+    /// is repeated at least as many times as there are lanes.
+    fn fill_wide_splatted(src: &[ARGB], dest: &mut [ARGB], multiplier: usize) {
+        for (&colour, pixels) in src.iter().zip(dest.chunks_exact_mut(multiplier)) {
+            let vector = wide::i32!(colour as i32);
+            let pixels = pixels.as_mut_ptr();
+
+            let mut i = 0;
+            while i + LANES < multiplier {
+                // SAFETY: `i + LANES` is less than `multiplier`, which is the
+                // length of `pixels`.
+                unsafe { wide::store!(vector, pixels, i); }
+                i += LANES;
+            }
 
-                let rendered = wide::or!(
-                    wide::or!(
-                        wide::left_shift_32!(rendered_a, 24),
-                        wide::left_shift_32!(rendered_r, 16),
-                    ),
-                    wide::or!(
-                        wide::left_shift_32!(rendered_g, 8),
-                        rendered_b,
-                    )
-                );
+            // This may overlap the previous store, which is fine since it writes
+            // the same values.
+            // SAFETY: `multiplier` is the length of `pixels`, and is at least `LANES`.
+            unsafe { wide::store!(vector, pixels, multiplier - LANES); }
+        }
+    }
+}
 
-                let to_store = wide::pick_via_mask!(
-                    unders,
-                    rendered,
-                    should_write
-                );
+/// How `resample` picks the colour of each pixel.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Resampling {
+    Nearest,
+    SharpBilinear,
+}
 
-                // SAFETY: The pointers produced by the code generated by
-                // this macro is valid to write 128 bytes to.
-                unsafe {
-                    wide::store!(
-                        to_store,
-                        frame_buffer.unscaled_buffer.as_mut_ptr(),
-                        dest_index,
-                    );
-                }
+/// Where a pixel's colour comes from, along one axis: the texels at `start`
+/// and `end`, with `end` having a weight of `weight` out of 256.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Sample {
+    start: usize,
+    end: usize,
+    weight: u16,
+}
 
-                x_iter_count += wide::WIDTH as usize;
-                x += wide::WIDTH;
-            }
+/// Maps the pixels of the output rect back to texels, along one axis.
+struct Axis {
+    dest_start: usize,
+    dest_len: usize,
+    src_len: usize,
+}
 
-            y_iter_count += 1;
+impl Axis {
+    fn new(dest: &core::ops::Range<u16>, src_len: u16) -> Self {
+        Self {
+            dest_start: usize::from(dest.start),
+            dest_len: usize::from(dest.end - dest.start),
+            src_len: usize::from(src_len),
         }
     }
 
-    // TODO If we need some more render speed, this could be made wide as well.
-    let mut src_i = 0;
-    let mut src_i_row_start;
-    let mut y_remaining = multiplier;
-    for y in outer_clip_rect.y {
-        let mut x_remaining = multiplier;
+    /// The first pixel whose centre is in or after the given texel, relative
+    /// to the start of the output rect.
+    fn first_pixel_at(&self, texel: usize) -> usize {
+        (2 * texel * self.dest_len)
+            .saturating_sub(self.src_len)
+            .div_ceil(2 * self.src_len)
+    }
+
+    /// The pixels whose colour can depend on the given texels.
+    fn dest_range(
+        &self,
+        src: &core::ops::Range<u16>,
+        resampling: Resampling,
+    ) -> core::ops::Range<usize> {
+        let mut start = self.first_pixel_at(usize::from(src.start));
+        let mut end = self.first_pixel_at(usize::from(src.end));
+
+        if resampling == Resampling::SharpBilinear {
+            // Blending only happens within half a pixel of the edge between
+            // two texels, so at most one more pixel on each side is affected.
+            start = start.saturating_sub(1);
+            end = core::cmp::min(end + 1, self.dest_len);
+        }
+
+        self.dest_start + start..self.dest_start + end
+    }
 
-        src_i_row_start = src_i;
+    fn sample(&self, dest: usize, resampling: Resampling) -> Sample {
+        let dest = dest - self.dest_start;
 
-        for x in outer_clip_rect.x.clone() {
-            let d_i = usize::from(y)
-            * usize::from(frame_buffer.width)
-            + usize::from(x);
+        match resampling {
+            Resampling::Nearest => {
+                let texel = ((2 * dest + 1) * self.src_len) / (2 * self.dest_len);
 
-            if d_i < frame_buffer.buffer.len() {
-                frame_buffer.buffer[d_i] =
-                    frame_buffer.unscaled_buffer[src_i];
+                Sample { start: texel, end: texel, weight: 0 }
             }
-
-            x_remaining -= 1;
-            if x_remaining == 0 {
-                src_i += 1;
-                x_remaining = multiplier;
+            Resampling::SharpBilinear => {
+                // Based on the "sharp-bilinear-simple" shader. Within each
+                // texel, the middle is flat, and only the part within half a
+                // pixel of either edge is interpolated with the neighbour.
+                let scale = self.dest_len as f32 / self.src_len as f32;
+                let texel = (dest as f32 + 0.5) / scale;
+                let floored = texel.floor();
+                let centre_distance = texel - floored - 0.5;
+                let region_range = 0.5 - 0.5 / scale;
+                let fraction = (
+                    centre_distance - centre_distance.clamp(-region_range, region_range)
+                ) * scale + 0.5;
+
+                // Texel centres are at half-integer coordinates.
+                let position = floored + fraction - 0.5;
+                let start = position.floor();
+                let weight = ((position - start) * 256.0).round() as u16;
+
+                let last = (self.src_len - 1) as f32;
+                Sample {
+                    start: start.clamp(0.0, last) as usize,
+                    end: (start + 1.0).clamp(0.0, last) as usize,
+                    weight,
+                }
             }
         }
+    }
+}
+
+fn lerp(a: ARGB, b: ARGB, weight: u16) -> ARGB {
+    let weight = u32::from(weight);
+    let mut output = 0;
+
+    for shift in [0, 8, 16, 24] {
+        let a_channel = (a >> shift) & 0xFF;
+        let b_channel = (b >> shift) & 0xFF;
 
-        src_i = src_i_row_start;
+        output |= ((a_channel * (256 - weight) + b_channel * weight) >> 8) << shift;
+    }
+
+    output
+}
 
-        y_remaining -= 1;
-        if y_remaining == 0 {
-            y_remaining = multiplier;
-            let src_w = usize::from(command::WIDTH);
-            src_i += src_w;
+/// Redraws the part of the buffer showing the given part of the unscaled
+/// buffer, when the unscaled buffer is scaled to fill `outer_clip_rect`.
+/// Returns the part of the buffer that was written to.
+fn resample(
+    frame_buffer: &mut FrameBuffer,
+    rect: &clip::Rect,
+    outer_clip_rect: &clip::Rect,
+    resampling: Resampling,
+) -> clip::Rect {
+    let x_axis = Axis::new(&outer_clip_rect.x, command::WIDTH);
+    let y_axis = Axis::new(&outer_clip_rect.y, command::HEIGHT);
+
+    let dest_x = x_axis.dest_range(&rect.x, resampling);
+    let dest_y = y_axis.dest_range(&rect.y, resampling);
+
+    let x_samples: Vec<Sample> = dest_x.clone()
+        .map(|x| x_axis.sample(x, resampling))
+        .collect();
+
+    let dest_w = usize::from(frame_buffer.width);
+    let src_w = usize::from(command::WIDTH);
+    let src = &frame_buffer.unscaled_buffer;
+
+    for y in dest_y.clone() {
+        let y_sample = y_axis.sample(y, resampling);
+        let start_row = &src[y_sample.start * src_w..(y_sample.start + 1) * src_w];
+        let end_row = &src[y_sample.end * src_w..(y_sample.end + 1) * src_w];
+
+        let dest_row_start = y * dest_w;
+        let dest_row = &mut frame_buffer.buffer[
+            dest_row_start + dest_x.start..dest_row_start + dest_x.end
+        ];
+
+        for (dest, x_sample) in dest_row.iter_mut().zip(x_samples.iter()) {
+            *dest = match resampling {
+                Resampling::Nearest => start_row[x_sample.start],
+                Resampling::SharpBilinear => lerp(
+                    lerp(start_row[x_sample.start], start_row[x_sample.end], x_sample.weight),
+                    lerp(end_row[x_sample.start], end_row[x_sample.end], x_sample.weight),
+                    y_sample.weight,
+                ),
+            };
         }
     }
 
-    frame_buffer.cells.swap();
+    clip::Rect {
+        x: dest_x.start as clip::X..dest_x.end as clip::X,
+        y: dest_y.start as clip::Y..dest_y.end as clip::Y,
+    }
+}
+
+/// Renders the given commands into a fresh `FrameBuffer` of the given size,
+/// without needing a window. Useful for tests and tooling.
+pub fn render_headless(
+    commands: &[Command],
+    size: (clip::W, clip::H),
+) -> FrameBuffer {
+    let mut frame_buffer = FrameBuffer::from_size(size);
+
+    // A fresh `FrameBuffer` always needs a redraw, so we don't need to check.
+    let _ = render(&mut frame_buffer, commands);
+
+    frame_buffer
+}
+
+/// Like `render`, but also sets the points and texture that the commands draw
+/// from. The texture is only looked at if its version changed.
+pub fn render_graphics(
+    frame_buffer: &mut FrameBuffer,
+    Graphics { commands, points, texture, texture_version }: Graphics,
+) -> NeedsRedraw {
+    frame_buffer.set_points(points);
+    if frame_buffer.graphics_texture_version != Some(texture_version) {
+        frame_buffer.set_texture(texture);
+        frame_buffer.graphics_texture_version = Some(texture_version);
+    }
+
+    render(frame_buffer, commands)
+}
+
+/// Like `render_headless`, but also sets the points and texture that the
+/// commands draw from.
+pub fn render_graphics_headless(
+    graphics: Graphics,
+    size: (clip::W, clip::H),
+) -> FrameBuffer {
+    let mut frame_buffer = FrameBuffer::from_size(size);
+
+    let _ = render_graphics(&mut frame_buffer, graphics);
 
-    NeedsRedraw::Yes
+    frame_buffer
 }
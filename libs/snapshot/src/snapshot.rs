@@ -0,0 +1,273 @@
+//! Helpers for writing rendered frames out as PNGs and comparing them against
+//! checked-in golden images.
+
+use platform_types::ARGB;
+use render::FrameBuffer;
+
+use std::path::Path;
+
+/// Set this environment variable to overwrite golden images with the current
+/// output, instead of comparing against them.
+pub const UPDATE_ENV_VAR: &str = "UPDATE_GOLDEN_IMAGES";
+
+#[derive(Debug)]
+pub enum Error {
+    Io(std::io::Error),
+    Decoding(png::DecodingError),
+    Encoding(png::EncodingError),
+    UnsupportedFormat(png::ColorType, png::BitDepth),
+}
+
+impl core::fmt::Display for Error {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        use Error::*;
+        match self {
+            Io(e) => write!(f, "{e}"),
+            Decoding(e) => write!(f, "{e}"),
+            Encoding(e) => write!(f, "{e}"),
+            UnsupportedFormat(colour_type, bit_depth) => write!(
+                f,
+                "Cannot handle {colour_type:?} images with bit depth {bit_depth:?}"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<std::io::Error> for Error {
+    fn from(e: std::io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+impl From<png::DecodingError> for Error {
+    fn from(e: png::DecodingError) -> Self {
+        Error::Decoding(e)
+    }
+}
+
+impl From<render::atlas::Error> for Error {
+    fn from(e: render::atlas::Error) -> Self {
+        use render::atlas::Error::*;
+        match e {
+            Decoding(e) => Error::Decoding(e),
+            UnsupportedFormat(colour_type, bit_depth) => {
+                Error::UnsupportedFormat(colour_type, bit_depth)
+            },
+        }
+    }
+}
+
+impl From<png::EncodingError> for Error {
+    fn from(e: png::EncodingError) -> Self {
+        Error::Encoding(e)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Image {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<ARGB>,
+}
+
+impl Image {
+    pub fn from_frame_buffer(frame_buffer: &FrameBuffer) -> Self {
+        Self {
+            width: frame_buffer.width.into(),
+            height: frame_buffer.height.into(),
+            pixels: frame_buffer.buffer.clone(),
+        }
+    }
+}
+
+pub fn encode_png(image: &Image) -> Result<Vec<u8>, Error> {
+    let mut bytes = Vec::with_capacity(image.pixels.len() * 4);
+    for &argb in &image.pixels {
+        let [a, r, g, b] = argb.to_be_bytes();
+        bytes.extend_from_slice(&[r, g, b, a]);
+    }
+
+    let mut output = Vec::new();
+    {
+        let mut encoder = png::Encoder::new(&mut output, image.width, image.height);
+        encoder.set_color(png::ColorType::Rgba);
+        encoder.set_depth(png::BitDepth::Eight);
+
+        let mut writer = encoder.write_header()?;
+        writer.write_image_data(&bytes)?;
+    }
+
+    Ok(output)
+}
+
+/// Decodes the same way as `render::atlas::Atlas::from_png`, so golden images
+/// and loaded atlases always agree on the pixels a PNG contains.
+pub fn decode_png(bytes: &[u8]) -> Result<Image, Error> {
+    let atlas = render::atlas::Atlas::from_png(bytes)?;
+
+    Ok(Image {
+        // The decoder gave these as `u32`s, so they fit.
+        width: atlas.width() as u32,
+        height: atlas.height() as u32,
+        pixels: atlas.pixels().to_vec(),
+    })
+}
+
+pub fn write_png(path: impl AsRef<Path>, image: &Image) -> Result<(), Error> {
+    std::fs::write(path, encode_png(image)?)?;
+
+    Ok(())
+}
+
+pub fn read_png(path: impl AsRef<Path>) -> Result<Image, Error> {
+    decode_png(&std::fs::read(path)?)
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Mismatch {
+    Size {
+        expected: (u32, u32),
+        actual: (u32, u32),
+    },
+    Pixels {
+        differing_count: usize,
+        /// The coordinates and the expected and actual colours of the first
+        /// differing pixel, in reading order.
+        first: ((u32, u32), ARGB, ARGB),
+    },
+}
+
+impl core::fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Mismatch::Size { expected, actual } => write!(
+                f,
+                "expected a {}x{} image, got a {}x{} image",
+                expected.0, expected.1, actual.0, actual.1,
+            ),
+            Mismatch::Pixels {
+                differing_count,
+                first: ((x, y), expected, actual),
+            } => write!(
+                f,
+                "{differing_count} pixels differ. The first is at ({x}, {y}): expected 0x{expected:08X}, got 0x{actual:08X}",
+            ),
+        }
+    }
+}
+
+pub fn compare(expected: &Image, actual: &Image) -> Result<(), Mismatch> {
+    if (expected.width, expected.height) != (actual.width, actual.height) {
+        return Err(Mismatch::Size {
+            expected: (expected.width, expected.height),
+            actual: (actual.width, actual.height),
+        });
+    }
+
+    let mut differing_count = 0;
+    let mut first = None;
+    for (i, (&e, &a)) in expected.pixels.iter().zip(actual.pixels.iter()).enumerate() {
+        if e != a {
+            differing_count += 1;
+            if first.is_none() {
+                let i = i as u32;
+                first = Some(((i % actual.width, i / actual.width), e, a));
+            }
+        }
+    }
+
+    match first {
+        None => Ok(()),
+        Some(first) => Err(Mismatch::Pixels {
+            differing_count,
+            first,
+        }),
+    }
+}
+
+/// Compares the frame buffer against the PNG at `path`, panicking with a
+/// description of the differences if they do not match. On a mismatch, the
+/// actual output is written next to the golden image, with an `.actual.png`
+/// extension, to make it easy to inspect.
+///
+/// If the environment variable named by `UPDATE_ENV_VAR` is set, the golden
+/// image is (re)written instead.
+#[track_caller]
+pub fn assert_matches_golden(path: impl AsRef<Path>, frame_buffer: &FrameBuffer) {
+    let path = path.as_ref();
+    let actual = Image::from_frame_buffer(frame_buffer);
+
+    if std::env::var_os(UPDATE_ENV_VAR).is_some() {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)
+                .unwrap_or_else(|e| panic!("Could not create {}: {e}", parent.display()));
+        }
+        write_png(path, &actual)
+            .unwrap_or_else(|e| panic!("Could not write {}: {e}", path.display()));
+        return
+    }
+
+    let expected = read_png(path).unwrap_or_else(|e| panic!(
+        "Could not read golden image {}: {e}\nRun with {UPDATE_ENV_VAR}=1 to create it.",
+        path.display()
+    ));
+
+    if let Err(mismatch) = compare(&expected, &actual) {
+        let actual_path = path.with_extension("actual.png");
+        // We're about to panic anyway, so the extra file is just a nicety.
+        let _ = write_png(&actual_path, &actual);
+
+        panic!(
+            "{} does not match: {mismatch}\nSee {} for the actual output.",
+            path.display(),
+            actual_path.display(),
+        );
+    }
+}
+
+#[cfg(test)]
+mod encode_then_decode {
+    use super::*;
+
+    #[test]
+    fn is_identity_on_this_example() {
+        let expected = Image {
+            width: 3,
+            height: 2,
+            pixels: vec![
+                0xFF3352E1, 0xFF30B06E, 0x00000000,
+                0x80DE4949, 0xFFFFB937, 0xFF222222,
+            ],
+        };
+
+        let actual = decode_png(&encode_png(&expected).unwrap()).unwrap();
+
+        assert_eq!(expected, actual);
+    }
+}
+
+#[cfg(test)]
+mod compare_works {
+    use super::*;
+
+    #[test]
+    fn on_this_single_pixel_difference() {
+        let expected = Image {
+            width: 2,
+            height: 2,
+            pixels: vec![0, 0, 0, 0],
+        };
+        let mut actual = expected.clone();
+        actual.pixels[3] = 0xFFFFFFFF;
+
+        assert_eq!(
+            compare(&expected, &actual),
+            Err(Mismatch::Pixels {
+                differing_count: 1,
+                first: ((1, 1), 0, 0xFFFFFFFF),
+            })
+        );
+    }
+}
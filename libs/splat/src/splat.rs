@@ -1,8 +1,15 @@
 use models::{Card, gen_card};
 use gfx::{Commands};
-use platform_types::{command, sprite, unscaled, Button, Input, Speaker, SFX};
+use platform_types::{atlas, command, sprite, unscaled, Button, Input, Speaker, SFX};
 use xs::{Xs, Seed};
 
+/// The atlases this demo draws from, for `State::atlas_sources`.
+pub const ATLAS_SOURCES: &[atlas::Source] = &[
+    atlas::Source::Png(include_bytes!("splat.png")),
+];
+
+const SPLAT_ATLAS: atlas::Id = atlas::Id::from_source_index(0).unwrap();
+
 #[derive(Clone, Default)]
 pub struct Splat {
     pub kind: Card,
@@ -57,10 +64,11 @@ fn render(commands: &mut Commands, state: &State) {
     for &Splat { kind, x, y } in &state.splats {
         commands.draw_card(kind, x, y);
 
+        let previous_atlas = commands.set_atlas(SPLAT_ATLAS);
         commands.sspr(
             sprite::XY {
                 x: sprite::X(0),
-                y: sprite::Y(64),
+                y: sprite::Y(0),
             },
             command::Rect::from_unscaled(unscaled::Rect {
                 x: x.saturating_sub(unscaled::W(16)),
@@ -69,6 +77,7 @@ fn render(commands: &mut Commands, state: &State) {
                 h: unscaled::H(16),
             })
         );
+        commands.set_atlas(previous_atlas);
     }
 }
 
@@ -81,4 +90,50 @@ pub fn update_and_render(
 ) {
     update(state, input, speaker);
     render(commands, state);
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod renders_the_same_as_the_golden_image {
+    use super::*;
+
+    const SEED: Seed = [0x5B; 16];
+
+    fn assert_splats_match(splat_count: usize, name: &str) {
+        let mut state = State::new(SEED);
+        for _ in 1..splat_count {
+            state.add_splat();
+        }
+
+        let mut commands = Commands::default();
+        let mut speaker = Speaker::default();
+        update_and_render(&mut commands, &mut state, Input::default(), &mut speaker);
+
+        let mut frame_buffer = render::FrameBuffer::from_size(
+            (command::WIDTH, command::HEIGHT),
+        );
+        for &source in ATLAS_SOURCES {
+            let atlas::Source::Png(bytes) = source else {
+                panic!("{source:?} is not embedded");
+            };
+            frame_buffer.load_atlas(render::atlas::Atlas::from_png(bytes).unwrap());
+        }
+        let _ = render::render(&mut frame_buffer, commands.slice());
+
+        snapshot::assert_matches_golden(
+            std::path::Path::new(env!("CARGO_MANIFEST_DIR"))
+                .join("golden")
+                .join(name),
+            &frame_buffer,
+        );
+    }
+
+    #[test]
+    fn with_a_single_splat() {
+        assert_splats_match(1, "single_splat.png");
+    }
+
+    #[test]
+    fn with_many_splats() {
+        assert_splats_match(64, "many_splats.png");
+    }
+}